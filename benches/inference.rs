@@ -0,0 +1,53 @@
+//! Compares tagging throughput across batch sizes on the CPU.
+//!
+//! Run with `cargo bench --bench inference`. `TAGGERS` points at a tagger descriptor file like
+//! the server's, defaulting to DeepDanbooru, and `BENCH_IMAGES` sets the number of images
+//! tagged per batch size.
+
+use std::time::Instant;
+
+use image::{DynamicImage, Rgb, RgbImage};
+use mediamon::tagger::{self, ModelDescriptor};
+
+fn main() {
+    let descriptors = match std::env::var("TAGGERS") {
+        Ok(path) => ModelDescriptor::load_all(path).unwrap(),
+        Err(_) => vec![ModelDescriptor::deepdanbooru()],
+    };
+    let total: usize = std::env::var("BENCH_IMAGES")
+        .ok()
+        .and_then(|total| total.parse().ok())
+        .unwrap_or(32);
+    let images: Vec<DynamicImage> = (0..total)
+        .map(|i| {
+            let shade = (i * 37 % 256) as u8;
+            let image = RgbImage::from_fn(768, 512, |x, y| {
+                Rgb([shade, (x % 256) as u8, (y % 256) as u8])
+            });
+            DynamicImage::ImageRgb8(image)
+        })
+        .collect();
+
+    for descriptor in descriptors {
+        let id = descriptor.id();
+        let tagger = tagger::load(descriptor).unwrap();
+        // Warm up so session initialisation does not count against the first batch size.
+        tagger.infer_batch(&images[..1]).unwrap();
+
+        println!("{id}");
+        println!("{:>10} {:>12} {:>12}", "batch", "total ms", "images/s");
+        for batch_size in [1, 2, 4, 8, 16] {
+            let start = Instant::now();
+            for batch in images.chunks(batch_size) {
+                tagger.infer_batch(batch).unwrap();
+            }
+            let elapsed = start.elapsed();
+            println!(
+                "{:>10} {:>12} {:>12.2}",
+                batch_size,
+                elapsed.as_millis(),
+                total as f64 / elapsed.as_secs_f64()
+            );
+        }
+    }
+}
@@ -0,0 +1,22 @@
+-- Add migration script here
+CREATE VIRTUAL TABLE tag_search USING fts5 (
+    name,
+    tokenize = 'trigram'
+);
+
+INSERT INTO tag_search (rowid, name)
+SELECT tag_id, REPLACE(name, '_', ' ') FROM tag;
+
+CREATE TRIGGER tag_search_insert AFTER INSERT ON tag BEGIN
+    INSERT INTO tag_search (rowid, name) VALUES (new.tag_id, REPLACE(new.name, '_', ' '));
+END;
+
+CREATE TRIGGER tag_search_delete AFTER DELETE ON tag BEGIN
+    DELETE FROM tag_search WHERE rowid = old.tag_id;
+END;
+
+CREATE TRIGGER tag_search_update AFTER UPDATE OF name ON tag BEGIN
+    UPDATE tag_search SET name = REPLACE(new.name, '_', ' ') WHERE rowid = new.tag_id;
+END;
+
+CREATE INDEX idx_image_tag_tag ON image_tag (tag_id);
@@ -0,0 +1,21 @@
+-- Add migration script here
+CREATE TABLE tag_alias (
+    alias TEXT PRIMARY KEY,
+    tag_id INTEGER NOT NULL,
+    date_added DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    FOREIGN KEY (tag_id) REFERENCES tag(tag_id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_tag_alias_tag ON tag_alias (tag_id);
+
+CREATE TABLE tag_implication (
+    tag_id INTEGER NOT NULL,
+    implied_tag_id INTEGER NOT NULL,
+    date_added DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    PRIMARY KEY (tag_id, implied_tag_id),
+    CHECK (tag_id != implied_tag_id),
+    FOREIGN KEY (tag_id) REFERENCES tag(tag_id) ON DELETE CASCADE,
+    FOREIGN KEY (implied_tag_id) REFERENCES tag(tag_id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_tag_implication_implied ON tag_implication (implied_tag_id);
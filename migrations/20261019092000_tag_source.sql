@@ -0,0 +1,26 @@
+-- Add migration script here
+ALTER TABLE image_tag ADD COLUMN source TEXT NOT NULL DEFAULT 'model:deepdanbooru';
+
+CREATE TABLE image_tag_removed (
+    image_id INTEGER NOT NULL,
+    tag_id INTEGER NOT NULL,
+    date_removed DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    PRIMARY KEY (image_id, tag_id)
+);
+
+CREATE TABLE video_tag (
+    video_id INTEGER NOT NULL,
+    tag_id INTEGER NOT NULL,
+    score REAL NOT NULL,
+    source TEXT NOT NULL,
+    PRIMARY KEY (video_id, tag_id)
+);
+
+CREATE INDEX idx_video_tag_tag ON video_tag (tag_id);
+
+CREATE TABLE video_tag_removed (
+    video_id INTEGER NOT NULL,
+    tag_id INTEGER NOT NULL,
+    date_removed DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    PRIMARY KEY (video_id, tag_id)
+);
@@ -0,0 +1,9 @@
+-- Add migration script here
+ALTER TABLE tag ADD COLUMN category TEXT NOT NULL DEFAULT 'general'
+    CHECK (category IN ('general', 'character', 'rating', 'artist', 'copyright', 'meta'));
+
+-- DeepDanbooru lays its labels out as general, then character, then rating tags.
+UPDATE tag SET category = 'character' WHERE tag_id BETWEEN 6892 AND 9173;
+UPDATE tag SET category = 'rating' WHERE tag_id BETWEEN 9174 AND 9176;
+
+CREATE INDEX idx_tag_category ON tag (category);
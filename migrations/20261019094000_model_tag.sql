@@ -0,0 +1,10 @@
+-- Add migration script here
+CREATE TABLE image_model_tag (
+    image_id INTEGER NOT NULL,
+    model TEXT NOT NULL,
+    tag_id INTEGER NOT NULL,
+    score REAL NOT NULL,
+    PRIMARY KEY (image_id, model, tag_id)
+);
+
+CREATE INDEX idx_image_model_tag_model ON image_model_tag (model, tag_id);
@@ -0,0 +1,8 @@
+-- Add migration script here
+CREATE TABLE model_label (
+    model TEXT NOT NULL,
+    label_index INTEGER NOT NULL,
+    tag_id INTEGER NOT NULL,
+    PRIMARY KEY (model, label_index),
+    FOREIGN KEY (tag_id) REFERENCES tag(tag_id)
+);
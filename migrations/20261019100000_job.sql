@@ -0,0 +1,21 @@
+-- Add migration script here
+CREATE TABLE job (
+    job_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    kind TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'running'
+        CHECK (status IN ('running', 'completed', 'cancelled', 'failed')),
+    params TEXT NOT NULL DEFAULT '{}',
+    stage TEXT NOT NULL DEFAULT '',
+    cursor INTEGER NOT NULL DEFAULT 0,
+    processed INTEGER NOT NULL DEFAULT 0,
+    failed INTEGER NOT NULL DEFAULT 0,
+    total INTEGER NOT NULL DEFAULT 0,
+    error TEXT,
+    date_added DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    date_updated DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE INDEX idx_job_status ON job (status);
+
+ALTER TABLE image ADD COLUMN tagged_by TEXT;
+ALTER TABLE video ADD COLUMN tagged_by TEXT;
@@ -0,0 +1,10 @@
+-- Add migration script here
+CREATE TABLE inference_cache (
+    content_hash TEXT NOT NULL,
+    model TEXT NOT NULL,
+    scores BLOB NOT NULL,
+    date_added DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    PRIMARY KEY (content_hash, model)
+);
+
+CREATE INDEX idx_inference_cache_model ON inference_cache (model);
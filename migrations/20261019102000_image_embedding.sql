@@ -0,0 +1,10 @@
+-- Add migration script here
+CREATE TABLE image_embedding (
+    image_id INTEGER NOT NULL,
+    model TEXT NOT NULL,
+    embedding BLOB NOT NULL,
+    PRIMARY KEY (image_id, model),
+    FOREIGN KEY (image_id) REFERENCES image (img_id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_image_embedding_model ON image_embedding (model);
@@ -0,0 +1,35 @@
+-- Add migration script here
+CREATE TABLE generation_params (
+    image_id INTEGER PRIMARY KEY,
+    source TEXT NOT NULL,
+    prompt TEXT,
+    negative_prompt TEXT,
+    seed INTEGER,
+    sampler TEXT,
+    steps INTEGER,
+    cfg_scale REAL,
+    model TEXT,
+    model_hash TEXT,
+    loras TEXT NOT NULL DEFAULT '[]',
+    FOREIGN KEY (image_id) REFERENCES image (img_id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_generation_params_model ON generation_params (model COLLATE NOCASE);
+CREATE INDEX idx_generation_params_model_hash ON generation_params (model_hash COLLATE NOCASE);
+
+CREATE VIRTUAL TABLE prompt_search USING fts5 (
+    prompt,
+    tokenize = 'trigram'
+);
+
+CREATE TRIGGER prompt_search_insert AFTER INSERT ON generation_params BEGIN
+    INSERT INTO prompt_search (rowid, prompt) VALUES (new.image_id, COALESCE(new.prompt, ''));
+END;
+
+CREATE TRIGGER prompt_search_delete AFTER DELETE ON generation_params BEGIN
+    DELETE FROM prompt_search WHERE rowid = old.image_id;
+END;
+
+CREATE TRIGGER prompt_search_update AFTER UPDATE OF prompt ON generation_params BEGIN
+    UPDATE prompt_search SET prompt = COALESCE(new.prompt, '') WHERE rowid = new.image_id;
+END;
@@ -0,0 +1,24 @@
+-- Add migration script here
+CREATE TABLE image_metadata (
+    image_id INTEGER PRIMARY KEY,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    format TEXT NOT NULL,
+    color_type TEXT NOT NULL,
+    bit_depth INTEGER NOT NULL,
+    color_space TEXT,
+    has_alpha BOOLEAN NOT NULL,
+    animated BOOLEAN NOT NULL,
+    frame_count INTEGER NOT NULL,
+    camera_make TEXT,
+    camera_model TEXT,
+    lens_model TEXT,
+    date_taken DATETIME,
+    gps_latitude REAL,
+    gps_longitude REAL,
+    exif TEXT NOT NULL DEFAULT '{}',
+    FOREIGN KEY (image_id) REFERENCES image (img_id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_image_metadata_size ON image_metadata (width, height);
+CREATE INDEX idx_image_metadata_date_taken ON image_metadata (date_taken);
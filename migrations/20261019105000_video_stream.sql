@@ -0,0 +1,49 @@
+-- Add migration script here
+ALTER TABLE video ADD COLUMN container TEXT;
+ALTER TABLE video ADD COLUMN duration REAL;
+ALTER TABLE video ADD COLUMN bit_rate INTEGER;
+
+CREATE TABLE video_stream (
+    video_id INTEGER NOT NULL,
+    stream_index INTEGER NOT NULL,
+    codec_type TEXT NOT NULL,
+    codec_name TEXT,
+    profile TEXT,
+    width INTEGER,
+    height INTEGER,
+    frame_rate REAL,
+    bit_rate INTEGER,
+    sample_rate INTEGER,
+    channels INTEGER,
+    channel_layout TEXT,
+    language TEXT,
+    duration REAL,
+    PRIMARY KEY (video_id, stream_index),
+    FOREIGN KEY (video_id) REFERENCES video (video_id) ON DELETE CASCADE
+);
+
+-- A video's container details with its first video and audio stream.
+CREATE VIEW video_summary AS
+SELECT
+    video.video_id,
+    video.container,
+    video.duration,
+    video.bit_rate,
+    video_track.codec_name AS video_codec,
+    video_track.width,
+    video_track.height,
+    video_track.frame_rate,
+    audio_track.codec_name AS audio_codec,
+    audio_track.channels AS audio_channels,
+    audio_track.sample_rate AS audio_sample_rate
+FROM video
+LEFT JOIN video_stream AS video_track ON video_track.video_id = video.video_id
+    AND video_track.stream_index = (
+        SELECT min(stream_index) FROM video_stream
+        WHERE video_id = video.video_id AND codec_type = 'video'
+    )
+LEFT JOIN video_stream AS audio_track ON audio_track.video_id = video.video_id
+    AND audio_track.stream_index = (
+        SELECT min(stream_index) FROM video_stream
+        WHERE video_id = video.video_id AND codec_type = 'audio'
+    );
@@ -0,0 +1,75 @@
+-- Add migration script here
+CREATE TABLE artist (
+    artist_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE COLLATE NOCASE,
+    date_added DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+-- Embedded cover pictures, stored once however many tracks carry them.
+CREATE TABLE cover (
+    cover_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    hash TEXT NOT NULL UNIQUE,
+    mime TEXT NOT NULL,
+    data BLOB NOT NULL
+);
+
+CREATE TABLE album (
+    album_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    title TEXT NOT NULL COLLATE NOCASE,
+    artist_id INTEGER,
+    year INTEGER,
+    genre TEXT,
+    cover_id INTEGER,
+    date_added DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    FOREIGN KEY (artist_id) REFERENCES artist (artist_id) ON DELETE SET NULL,
+    FOREIGN KEY (cover_id) REFERENCES cover (cover_id) ON DELETE SET NULL
+);
+
+CREATE INDEX idx_album_artist ON album (artist_id);
+CREATE INDEX idx_album_title ON album (title);
+
+CREATE TABLE track (
+    track_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    path TEXT NOT NULL UNIQUE,
+    hash TEXT NOT NULL,
+    size INTEGER NOT NULL CHECK (size > 0),
+    title TEXT NOT NULL,
+    artist_id INTEGER,
+    album_id INTEGER,
+    track_number INTEGER,
+    disc_number INTEGER,
+    year INTEGER,
+    genre TEXT,
+    duration REAL,
+    bit_rate INTEGER,
+    codec TEXT,
+    container TEXT,
+    cover_id INTEGER,
+    tagged_by TEXT,
+    date_added DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    date_updated DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    FOREIGN KEY (artist_id) REFERENCES artist (artist_id) ON DELETE SET NULL,
+    FOREIGN KEY (album_id) REFERENCES album (album_id) ON DELETE SET NULL,
+    FOREIGN KEY (cover_id) REFERENCES cover (cover_id) ON DELETE SET NULL
+);
+
+CREATE INDEX idx_track_artist ON track (artist_id);
+CREATE INDEX idx_track_album ON track (album_id, disc_number, track_number);
+CREATE INDEX idx_track_updated ON track (date_updated DESC);
+
+CREATE TABLE track_tag (
+    track_id INTEGER NOT NULL,
+    tag_id INTEGER NOT NULL,
+    score REAL NOT NULL,
+    source TEXT NOT NULL,
+    PRIMARY KEY (track_id, tag_id)
+);
+
+CREATE INDEX idx_track_tag_tag ON track_tag (tag_id);
+
+CREATE TABLE track_tag_removed (
+    track_id INTEGER NOT NULL,
+    tag_id INTEGER NOT NULL,
+    date_removed DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    PRIMARY KEY (track_id, tag_id)
+);
@@ -0,0 +1,35 @@
+-- Add migration script here
+-- Files of a video: the original as ingested and the outputs of transcode profiles.
+CREATE TABLE video_rendition (
+    rendition_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    video_id INTEGER NOT NULL,
+    profile TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending',
+    path TEXT,
+    size INTEGER,
+    container TEXT,
+    video_codec TEXT,
+    audio_codec TEXT,
+    width INTEGER,
+    height INTEGER,
+    bit_rate INTEGER,
+    duration REAL,
+    error TEXT,
+    date_added DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    date_updated DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (video_id, profile),
+    FOREIGN KEY (video_id) REFERENCES video (video_id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_video_rendition_status ON video_rendition (status, rendition_id);
+
+INSERT INTO video_rendition (
+    video_id, profile, status, path, size, container, video_codec, audio_codec, width, height,
+    bit_rate, duration
+)
+SELECT
+    video.video_id, 'original', 'done', video.path, video.size, video_summary.container,
+    video_summary.video_codec, video_summary.audio_codec, video_summary.width,
+    video_summary.height, video_summary.bit_rate, video_summary.duration
+FROM video
+LEFT JOIN video_summary ON video_summary.video_id = video.video_id;
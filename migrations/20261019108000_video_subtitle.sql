@@ -0,0 +1,30 @@
+-- Add migration script here
+-- Subtitles of a video as WebVTT, with their text for searching.
+CREATE TABLE video_subtitle (
+    subtitle_id INTEGER PRIMARY KEY,
+    video_id INTEGER NOT NULL,
+    language TEXT NOT NULL,
+    source TEXT NOT NULL,
+    vtt TEXT NOT NULL,
+    text TEXT NOT NULL,
+    date_added DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (video_id, language),
+    FOREIGN KEY (video_id) REFERENCES video (video_id) ON DELETE CASCADE
+);
+
+CREATE VIRTUAL TABLE subtitle_search USING fts5 (
+    text,
+    tokenize = 'trigram'
+);
+
+CREATE TRIGGER subtitle_search_insert AFTER INSERT ON video_subtitle BEGIN
+    INSERT INTO subtitle_search (rowid, text) VALUES (new.subtitle_id, new.text);
+END;
+
+CREATE TRIGGER subtitle_search_delete AFTER DELETE ON video_subtitle BEGIN
+    DELETE FROM subtitle_search WHERE rowid = old.subtitle_id;
+END;
+
+CREATE TRIGGER subtitle_search_update AFTER UPDATE OF text ON video_subtitle BEGIN
+    UPDATE subtitle_search SET text = new.text WHERE rowid = new.subtitle_id;
+END;
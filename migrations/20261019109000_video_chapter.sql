@@ -0,0 +1,35 @@
+-- Add migration script here
+-- Chapters of a video from yt-dlp or the file itself, numbered from 0 in order.
+CREATE TABLE video_chapter (
+    chapter_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    video_id INTEGER NOT NULL,
+    chapter_index INTEGER NOT NULL,
+    title TEXT,
+    start_time REAL NOT NULL,
+    end_time REAL NOT NULL,
+    thumbnail BLOB,
+    date_added DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (video_id, chapter_index),
+    FOREIGN KEY (video_id) REFERENCES video (video_id) ON DELETE CASCADE
+);
+
+CREATE TABLE video_chapter_tag (
+    chapter_id INTEGER NOT NULL,
+    tag_id INTEGER NOT NULL,
+    score REAL NOT NULL,
+    source TEXT NOT NULL,
+    PRIMARY KEY (chapter_id, tag_id),
+    FOREIGN KEY (chapter_id) REFERENCES video_chapter (chapter_id) ON DELETE CASCADE,
+    FOREIGN KEY (tag_id) REFERENCES tag (tag_id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_video_chapter_tag_tag ON video_chapter_tag (tag_id);
+
+CREATE TABLE video_chapter_tag_removed (
+    chapter_id INTEGER NOT NULL,
+    tag_id INTEGER NOT NULL,
+    date_removed DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    PRIMARY KEY (chapter_id, tag_id),
+    FOREIGN KEY (chapter_id) REFERENCES video_chapter (chapter_id) ON DELETE CASCADE,
+    FOREIGN KEY (tag_id) REFERENCES tag (tag_id) ON DELETE CASCADE
+);
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- Images uploaded before get their fingerprint on their next retag.
+ALTER TABLE image ADD COLUMN fingerprint TEXT;
@@ -0,0 +1,12 @@
+-- Add migration script here
+-- load_tags created DeepDanbooru's labels in output order, so tag n is output n - 1. That is
+-- recorded as the model's label mapping unless it was synced already, after fixing the one
+-- name load_tags spelled differently from tags.txt.
+UPDATE tag SET name = 'don''t_say_"lazy"'
+WHERE name = 'don''t_say_''lazy'''
+AND NOT EXISTS (SELECT 1 FROM tag WHERE name = 'don''t_say_"lazy"');
+
+INSERT INTO model_label (model, label_index, tag_id)
+SELECT 'deepdanbooru@v3', tag_id - 1, tag_id FROM tag
+WHERE tag_id BETWEEN 1 AND 9176
+AND NOT EXISTS (SELECT 1 FROM model_label WHERE model = 'deepdanbooru@v3');
@@ -1,36 +1,83 @@
+use axum::body::Body;
 use axum::extract::DefaultBodyLimit;
 use axum::extract::Path;
+use axum::extract::Query;
 use axum::extract::State;
 use axum::Json;
 use log::debug;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use std::time::UNIX_EPOCH;
 use utoipa::openapi::security::ApiKey;
 use utoipa::openapi::security::ApiKeyValue;
 use utoipa::openapi::security::SecurityScheme;
+use utoipa::IntoParams;
 use utoipa::Modify;
 use utoipa::OpenApi;
 use utoipa::ToSchema;
 
 use axum::{
     extract::{MatchedPath, Multipart},
-    http::{Request, StatusCode},
+    http::{header, HeaderMap, Request, StatusCode},
     response::{IntoResponse, Response},
     routing, Router,
 };
-use futures::TryStreamExt;
-use log::info;
-use tokio_util::io::StreamReader;
+use log::{info, warn};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Semaphore;
+use tokio_util::io::ReaderStream;
+use tower_http::compression::predicate::{DefaultPredicate, NotForContentType, Predicate};
 use tower_http::{compression::CompressionLayer, cors::CorsLayer, trace::TraceLayer};
 use tracing::info_span;
 use utoipa_swagger_ui::SwaggerUi;
 
-use crate::database::Database;
-use crate::deepbooru::Jarvis;
+use crate::chapters;
+use crate::database::{
+    self, AlbumRow, ArtistRow, ChapterRow, Cover, Database, GenerationParamsRow, ImageMetadataRow,
+    ImageRow, MediaTagRow, ModelTagRow, RenditionRow, SubtitleRow, TrackRow, VideoRow,
+    VideoStreamRow, VideoSummaryRow,
+};
+use crate::ffmpeg;
+use crate::fingerprint::{self, Fingerprint, FingerprintIndex};
 use crate::gallerydl;
+use crate::generation::{self, GenerationParams};
+use crate::hls::{self, HlsPackager};
+use crate::inference_cache::{self, InferenceCache};
+use crate::jobs::JobStatus;
+use crate::media::{MediaKind, TagSource};
+use crate::metadata::{self, ImageMetadata};
+use crate::music::AudioTags;
+use crate::preprocess;
+use crate::renditions::{self, RenditionWorker};
+use crate::retag::{self, RetagParams, Retagger};
+use crate::search;
+use crate::similarity::{Embedding, SimilarityIndex};
+use crate::streaming;
+use crate::subsonic;
+use crate::subtitles;
+use crate::tagger::{self, TaggerConfig};
+use crate::tagging::{self, TaggingService};
+use crate::transcode::{self, AudioFormat, Transcode, Transcoder};
+use crate::workers::{self, CpuPool};
+use crate::ytdlp::{self, DownloadKind, DownloadProfile};
 
-pub fn router(jarvis: Jarvis, db: Database) -> Router {
+/// `taggers` must not be empty; the first one is the primary tagger whose tags become the
+/// image's own, the others are only stored for comparison.
+#[allow(clippy::too_many_arguments)]
+pub fn router(
+    taggers: Vec<TaggingService>,
+    cpu: CpuPool,
+    db: Database,
+    retagger: Retagger,
+    inference_cache: InferenceCache,
+    similarity: SimilarityIndex,
+    fingerprints: FingerprintIndex,
+    transcoder: Transcoder,
+    hls: HlsPackager,
+    renditions: RenditionWorker,
+    download_profiles: Vec<DownloadProfile>,
+) -> Router {
     use tracing_subscriber::prelude::*;
     tracing_subscriber::registry()
         .with(tracing_subscriber::fmt::layer().without_time())
@@ -55,7 +102,21 @@ pub fn router(jarvis: Jarvis, db: Database) -> Router {
         .allow_headers(tower_http::cors::Any)
         .allow_methods(tower_http::cors::Any)
         .allow_origin(tower_http::cors::Any);
-    let app_state = AppState { jarvis, db };
+    let app_state = AppState {
+        taggers,
+        cpu,
+        db,
+        retagger,
+        inference_cache,
+        similarity,
+        fingerprints,
+        transcoder,
+        hls,
+        renditions,
+        chapter_thumbnails: Arc::new(Semaphore::new(chapters::THUMBNAIL_JOBS)),
+        download_profiles,
+        subsonic: subsonic::Credentials::from_env(),
+    };
 
     Router::new()
         .route("/", routing::get(root))
@@ -75,16 +136,174 @@ pub fn router(jarvis: Jarvis, db: Database) -> Router {
         )
         .route("/upload/music/url", routing::post(upload_music_url))
         .route("/thumbnail/image/:id", routing::get(image_thumbnail))
+        .route("/tags/autocomplete", routing::get(autocomplete_tags))
+        .route("/search/image", routing::get(search_images))
+        .route("/search/video", routing::get(search_videos))
+        .route("/search/subtitles", routing::get(search_subtitles))
+        .route(
+            "/search/by-image",
+            routing::post(search_by_image).layer(DefaultBodyLimit::disable()),
+        )
+        .route("/admin/taggers", routing::get(list_taggers))
+        .route("/admin/video-profiles", routing::get(list_video_profiles))
+        .route(
+            "/admin/download-profiles",
+            routing::get(list_download_profiles),
+        )
+        .route(
+            "/admin/taggers/:model/config",
+            routing::get(get_tagger_config).put(put_tagger_config),
+        )
+        .route(
+            "/admin/taggers/:model/overrides/:tag",
+            routing::put(put_tagger_override).delete(delete_tagger_override),
+        )
+        .route("/image/:id", routing::get(get_image))
+        .route(
+            "/image/:id/tags",
+            routing::get(list_image_tags).post(add_image_tags),
+        )
+        .route("/image/:id/tags/:tag", routing::delete(remove_image_tag))
+        .route("/image/:id/model-tags", routing::get(list_image_model_tags))
+        .route("/image/:id/related", routing::get(related_images))
+        .route("/video/:id", routing::get(get_video))
+        .route(
+            "/video/:id/tags",
+            routing::get(list_video_tags).post(add_video_tags),
+        )
+        .route("/video/:id/tags/:tag", routing::delete(remove_video_tag))
+        .route(
+            "/video/:id/renditions",
+            routing::get(list_video_renditions).post(queue_video_renditions),
+        )
+        .route(
+            "/video/:id/renditions/:profile/file",
+            routing::get(video_rendition_file),
+        )
+        .route("/video/:id/subtitles", routing::get(list_video_subtitles))
+        .route("/video/:id/chapters", routing::get(list_video_chapters))
+        .route(
+            "/video/:id/chapters/:index/thumbnail",
+            routing::get(chapter_thumbnail),
+        )
+        .route(
+            "/video/:id/chapters/:index/clip",
+            routing::get(chapter_clip),
+        )
+        .route(
+            "/video/:id/chapters/:index/tags",
+            routing::get(list_chapter_tags).post(add_chapter_tags),
+        )
+        .route(
+            "/video/:id/chapters/:index/tags/:tag",
+            routing::delete(remove_chapter_tag),
+        )
+        .route(
+            "/video/:id/subtitles/:file",
+            routing::get(video_subtitle_file),
+        )
+        .route(
+            "/video/:id/hls/master.m3u8",
+            routing::get(hls_master_playlist),
+        )
+        .route("/video/:id/hls/:rendition/:file", routing::get(hls_file))
+        .route("/music/artists", routing::get(list_artists))
+        .route("/music/artists/:id", routing::get(get_artist))
+        .route("/music/albums/:id", routing::get(get_album))
+        .route("/music/covers/:id", routing::get(get_cover))
+        .route("/music/:id", routing::get(get_track))
+        .route("/music/:id/stream", routing::get(stream_track))
+        .route(
+            "/music/:id/tags",
+            routing::get(list_track_tags).post(add_track_tags),
+        )
+        .route("/music/:id/tags/:tag", routing::delete(remove_track_tag))
+        .route(
+            "/admin/tags/aliases",
+            routing::get(list_tag_aliases).post(add_tag_alias),
+        )
+        .route(
+            "/admin/tags/aliases/:alias",
+            routing::delete(remove_tag_alias),
+        )
+        .route(
+            "/admin/tags/implications",
+            routing::get(list_tag_implications).post(add_tag_implication),
+        )
+        .route(
+            "/admin/tags/implications/:tag/:implied",
+            routing::delete(remove_tag_implication),
+        )
+        .route(
+            "/admin/inference-cache",
+            routing::get(inference_cache_report).delete(invalidate_inference_cache),
+        )
+        .route("/jobs", routing::get(list_jobs))
+        .route("/jobs/retag", routing::post(start_retag))
+        .route("/jobs/:id", routing::get(get_job))
+        .route("/jobs/:id/cancel", routing::post(cancel_job))
+        .merge(subsonic::router())
         .merge(SwaggerUi::new("/swagger-ui").url("/api-docs/openapi.json", ApiDoc::openapi()))
         .layer(cors_layer)
         .layer(trace_layer)
-        .layer(CompressionLayer::new().gzip(true).deflate(true))
+        .layer(
+            CompressionLayer::new()
+                .gzip(true)
+                .deflate(true)
+                // Audio and video are compressed already.
+                .compress_when(
+                    DefaultPredicate::new()
+                        .and(NotForContentType::const_new("audio/"))
+                        .and(NotForContentType::const_new("video/")),
+                ),
+        )
         .with_state(Arc::new(app_state))
 }
 
-struct AppState {
-    jarvis: Jarvis,
-    db: Database,
+pub(crate) struct AppState {
+    taggers: Vec<TaggingService>,
+    cpu: CpuPool,
+    pub(crate) db: Database,
+    retagger: Retagger,
+    inference_cache: InferenceCache,
+    similarity: SimilarityIndex,
+    fingerprints: FingerprintIndex,
+    transcoder: Transcoder,
+    hls: HlsPackager,
+    renditions: RenditionWorker,
+    /// Bounds the ffmpeg processes taking chapter thumbnails after uploads.
+    chapter_thumbnails: Arc<Semaphore>,
+    download_profiles: Vec<DownloadProfile>,
+    /// `None` turns the Subsonic API off.
+    pub(crate) subsonic: Option<subsonic::Credentials>,
+}
+
+impl AppState {
+    /// The download profile called `name`, or the first one of `kind`.
+    fn download_profile(
+        &self,
+        kind: DownloadKind,
+        name: Option<&str>,
+    ) -> Result<&DownloadProfile, String> {
+        let profile = self.download_profiles.iter().find(|profile| match name {
+            Some(name) => profile.name == name,
+            None => profile.kind == kind,
+        });
+        match profile {
+            Some(profile) if profile.kind == kind => Ok(profile),
+            Some(profile) => Err(format!("{} is not a {:?} profile", profile.name, kind)),
+            None => Err(format!(
+                "unknown download profile {}",
+                name.unwrap_or_default()
+            )),
+        }
+    }
+
+    fn tagger(&self, name: &str) -> Option<&TaggingService> {
+        self.taggers
+            .iter()
+            .find(|tagging| tagging.tagger().descriptor().name == name)
+    }
 }
 
 #[utoipa::path(
@@ -94,6 +313,7 @@ struct AppState {
     responses(
         (status = 201, description = "Downloaded file successfully", body = String),
         (status = 400, description = "Failed to download file", body = String),
+        (status = 503, description = "Too many images are being processed, retry later", body = String),
     )
 )]
 async fn upload_image_url(
@@ -120,12 +340,11 @@ async fn upload_image_url(
             .and_then(|v| v.to_str())
             .map(|v| v.to_string()),
     };
-    let tag_names = match save_image(file.clone(), &state).await {
-        Ok(tag_names) => tag_names,
-        Err(SaveImageError::UnknownFormat) => todo!(),
-        Err(SaveImageError::Corrupt) => todo!(),
+    let saved = match save_image(file.clone(), &state).await {
+        Ok(saved) => saved,
+        Err(err) => return save_image_error(err),
     };
-    serde_json::to_string_pretty(&tag_names)
+    serde_json::to_string_pretty(&saved.models[0].tags)
         .unwrap()
         .into_response()
 }
@@ -136,11 +355,84 @@ async fn upload_image_url(
     request_body(content = UploadUrlBody),
     responses(
         (status = 201, description = "Downloaded file successfully", body = String),
-        (status = 400, description = "Failed to download file", body = String),
+        (status = 400, description = "Not an http(s) URL, failed to download file, or unknown profile", body = String),
+        (status = 422, description = "Not a video", body = String),
     )
 )]
-async fn upload_video_url(Json(body): Json<UploadUrlBody>) -> Response {
-    body.url.into_response()
+async fn upload_video_url(
+    State(state): State<Arc<AppState>>,
+    Json(body): Json<UploadUrlBody>,
+) -> Response {
+    let profile = match state.download_profile(DownloadKind::Video, body.profile.as_deref()) {
+        Ok(profile) => profile,
+        Err(err) => return (StatusCode::BAD_REQUEST, err).into_response(),
+    };
+    let path = match ytdlp::download(&body.url, profile).await {
+        Ok(path) => path,
+        Err(err) => return (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+    };
+    save_video(&state, &path).await
+}
+
+/// Stores and indexes the subtitles of a video just added, see [`subtitles::collect`].
+async fn store_subtitles(state: &AppState, video_id: i64, path: &str, probe: &ffmpeg::Probe) {
+    for subtitle in subtitles::collect(path, probe).await {
+        let text = subtitles::text(&subtitle.vtt);
+        let saved = state
+            .db
+            .save_video_subtitle(
+                video_id,
+                &subtitle.language,
+                subtitle.source,
+                &subtitle.vtt,
+                &text,
+            )
+            .await;
+        if let Err(err) = saved {
+            warn!(
+                "failed to store {} subtitles of video {}: {}",
+                subtitle.language, video_id, err
+            );
+        }
+    }
+}
+
+/// Stores the chapters of a video just added, see [`chapters::collect`]. Their thumbnails are
+/// taken afterwards in the background, a few videos at a time.
+async fn store_chapters(state: &AppState, video_id: i64, path: &str, probe: &ffmpeg::Probe) {
+    let chapters = chapters::collect(path, probe).await;
+    if chapters.is_empty() {
+        return;
+    }
+    let chapter_ids = match state.db.save_video_chapters(video_id, &chapters).await {
+        Ok(chapter_ids) => chapter_ids,
+        Err(err) => {
+            warn!("failed to store chapters of video {}: {}", video_id, err);
+            return;
+        }
+    };
+    let db = state.db.clone();
+    let permits = state.chapter_thumbnails.clone();
+    let path = path.to_string();
+    tokio::spawn(async move {
+        let Ok(_permit) = permits.acquire_owned().await else {
+            return;
+        };
+        for (chapter_id, chapter) in chapter_ids.into_iter().zip(&chapters) {
+            let Some(chapter_id) = chapter_id else {
+                continue;
+            };
+            let Some(thumbnail) = chapters::thumbnail(&path, chapter).await else {
+                continue;
+            };
+            if let Err(err) = db.set_chapter_thumbnail(chapter_id, &thumbnail).await {
+                warn!(
+                    "failed to store thumbnail of chapter {}: {}",
+                    chapter_id, err
+                );
+            }
+        }
+    });
 }
 
 #[utoipa::path(
@@ -149,16 +441,30 @@ async fn upload_video_url(Json(body): Json<UploadUrlBody>) -> Response {
     request_body(content = UploadUrlBody),
     responses(
         (status = 201, description = "Downloaded file successfully", body = String),
-        (status = 400, description = "Failed to download file", body = String),
+        (status = 400, description = "Not an http(s) URL, failed to download file, or unknown profile", body = String),
+        (status = 422, description = "Not audio", body = String),
     )
 )]
-async fn upload_music_url(Json(body): Json<UploadUrlBody>) -> Response {
-    body.url.into_response()
+async fn upload_music_url(
+    State(state): State<Arc<AppState>>,
+    Json(body): Json<UploadUrlBody>,
+) -> Response {
+    let profile = match state.download_profile(DownloadKind::Music, body.profile.as_deref()) {
+        Ok(profile) => profile,
+        Err(err) => return (StatusCode::BAD_REQUEST, err).into_response(),
+    };
+    let path = match ytdlp::download(&body.url, profile).await {
+        Ok(path) => path,
+        Err(err) => return (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+    };
+    save_music(&state, &path, &path).await
 }
 
 #[derive(ToSchema, Deserialize)]
 struct UploadUrlBody {
     url: String,
+    /// Download profile for videos and music, the first one of the kind when missing
+    profile: Option<String>,
 }
 
 #[utoipa::path(
@@ -168,21 +474,80 @@ struct UploadUrlBody {
     responses(
         (status = 201, description = "Uploaded file successfully", body = String),
         (status = 400, description = "Failed to upload file", body = String),
+        (status = 503, description = "Too many images are being processed, retry later", body = String),
     )
 )]
 async fn upload_image_file(State(state): State<Arc<AppState>>, multipart: Multipart) -> Response {
     info!("Uploading...");
-    let file = extract_file("file", multipart).await.unwrap();
-    let tag_names = match save_image(file.clone(), &state).await {
-        Ok(tag_names) => tag_names,
-        Err(SaveImageError::UnknownFormat) => todo!(),
-        Err(SaveImageError::Corrupt) => todo!(),
+    let file = match extract_file("file", multipart).await {
+        Ok(file) => file,
+        Err(err) => return extract_file_error(err),
+    };
+    let saved = match save_image(file.clone(), &state).await {
+        Ok(saved) => saved,
+        Err(err) => return save_image_error(err),
+    };
+    let img = match state
+        .db
+        .save_image(
+            &file.file_path,
+            &saved.hash,
+            &format!("{:016x}", saved.fingerprint),
+            saved.size,
+        )
+        .await
+    {
+        Ok(img) => img,
+        Err(err) => return database_error(err),
     };
-    let img = state
+    for (i, model) in saved.models.iter().enumerate() {
+        let tags: Vec<(i64, f64)> = model
+            .tags
+            .iter()
+            .map(|(_, (score, tag_id))| (*tag_id, f64::from(*score)))
+            .collect();
+        if i == 0 {
+            if let Err(err) = state
+                .db
+                .replace_model_tags(MediaKind::Image, img.img_id(), &tags, &model.model)
+                .await
+            {
+                return database_error(err);
+            }
+        }
+        if let Err(err) = state
+            .db
+            .save_model_tags(img.img_id(), &model.model, &tags)
+            .await
+        {
+            return database_error(err);
+        }
+        if let Err(err) = state
+            .db
+            .save_embedding(img.img_id(), &model.model, &model.embedding.to_bytes())
+            .await
+        {
+            return database_error(err);
+        }
+        if model.model == state.similarity.model() {
+            state
+                .similarity
+                .insert(img.img_id(), model.embedding.clone());
+        }
+    }
+    state.fingerprints.insert(img.img_id(), saved.fingerprint);
+    if let Err(err) = state
         .db
-        .save_image(&file.file_path, "somerandomhash", 239823)
+        .save_image_metadata(img.img_id(), &saved.metadata)
         .await
-        .unwrap();
+    {
+        return database_error(err);
+    }
+    if let Some(params) = &saved.generation_params {
+        if let Err(err) = state.db.save_generation_params(img.img_id(), params).await {
+            return database_error(err);
+        }
+    }
     (
         StatusCode::CREATED,
         serde_json::to_string_pretty(&img).unwrap(),
@@ -190,11 +555,79 @@ async fn upload_image_file(State(state): State<Arc<AppState>>, multipart: Multip
         .into_response()
 }
 
-async fn save_image(
+/// Decodes, fingerprints and tags an uploaded image. The CPU heavy parts run on the worker
+/// pool and fail with [`SaveImageError::Busy`] instead of queueing without bound.
+async fn save_image(file: MultipartFile, state: &AppState) -> Result<SavedImage, SaveImageError> {
+    let DecodedImage {
+        image: image_data,
+        fingerprint,
+        content_hash,
+        size,
+        generation_params,
+        metadata,
+    } = decode_image(file, state).await?;
+    let predictions = futures::future::join_all(state.taggers.iter().map(|tagging| async {
+        match tagging.cached_scores(&content_hash).await {
+            Some(scores) => Ok(scores),
+            None => {
+                tagging
+                    .try_infer_scores(Some(&content_hash), image_data.clone())
+                    .await
+            }
+        }
+    }))
+    .await;
+    let mut models = Vec::with_capacity(predictions.len());
+    for (tagging, prediction) in state.taggers.iter().zip(predictions) {
+        let scores = match prediction {
+            Ok(scores) => scores,
+            Err(tagging::Error::Worker(workers::Error::Busy)) => return Err(SaveImageError::Busy),
+            Err(err) => return Err(SaveImageError::Tagging(err.to_string())),
+        };
+        let tagger = tagging.tagger();
+        let tags = tagging
+            .select(&scores)
+            .into_iter()
+            .map(|(score, index)| {
+                let name = tagger.labels()[index].name.clone();
+                (name, (score, tagging.tag_id(index)))
+            })
+            .collect();
+        models.push(ModelTags {
+            model: tagger.descriptor().id(),
+            tags,
+            embedding: Embedding::from_scores(&scores),
+        });
+    }
+    Ok(SavedImage {
+        models,
+        hash: content_hash,
+        fingerprint,
+        size,
+        generation_params,
+        metadata,
+    })
+}
+
+struct DecodedImage {
+    image: image::DynamicImage,
+    fingerprint: u64,
+    /// See [`inference_cache::content_hash`].
+    content_hash: String,
+    size: i64,
+    generation_params: Option<GenerationParams>,
+    metadata: ImageMetadata,
+}
+
+/// Reads and decodes an uploaded image on the worker pool.
+async fn decode_image(
     file: MultipartFile,
     state: &AppState,
-) -> Result<Vec<(String, (f32, usize))>, SaveImageError> {
-    let file_data = tokio::fs::read(&file.file_path).await.unwrap();
+) -> Result<DecodedImage, SaveImageError> {
+    let file_data = match tokio::fs::read(&file.file_path).await {
+        Ok(file_data) => file_data,
+        Err(err) => return Err(SaveImageError::Read(err.to_string())),
+    };
     let Some(image_format) = file
         .file_type
         .and_then(image::ImageFormat::from_mime_type)
@@ -207,30 +640,78 @@ async fn save_image(
         tokio::fs::remove_file(file.file_path).await.ok();
         return Err(SaveImageError::UnknownFormat);
     };
-    let Ok(image_data) = image::load_from_memory_with_format(&file_data, image_format) else {
-        return Err(SaveImageError::Corrupt);
-    };
-    let image_tags = state.jarvis.infer_tags(&image_data).unwrap();
-    let tag_names: Vec<(String, (f32, usize))> = state
-        .db
-        .get_tag_names(
-            &image_tags
-                .iter()
-                .map(|(_, tag_id)| *tag_id as i32)
-                .collect::<Vec<i32>>(),
-        )
-        .await
-        .unwrap()
-        .into_iter()
-        .map(|(_, tag_name)| tag_name)
-        .zip(image_tags)
-        .collect();
-    Ok(tag_names)
+    let size = file_data.len() as i64;
+    let decoded = state
+        .cpu
+        .try_run(move || {
+            let image = preprocess::decode(&file_data, Some(image_format)).ok()?;
+            let fingerprint = Fingerprint::new().fingerprint(&image);
+            let metadata = metadata::read(&file_data, image_format, &image);
+            Some(DecodedImage {
+                image,
+                fingerprint,
+                content_hash: inference_cache::content_hash(&file_data),
+                size,
+                generation_params: generation::extract(&file_data),
+                metadata,
+            })
+        })
+        .await;
+    match decoded {
+        Ok(Some(decoded)) => Ok(decoded),
+        Ok(None) => Err(SaveImageError::Corrupt),
+        Err(workers::Error::Busy) => Err(SaveImageError::Busy),
+        Err(err) => Err(SaveImageError::Tagging(err.to_string())),
+    }
+}
+
+struct SavedImage {
+    /// One entry per tagger, the primary tagger first.
+    models: Vec<ModelTags>,
+    /// See [`inference_cache::content_hash`].
+    hash: String,
+    fingerprint: u64,
+    size: i64,
+    generation_params: Option<GenerationParams>,
+    metadata: ImageMetadata,
+}
+
+struct ModelTags {
+    model: String,
+    tags: Vec<(String, (f32, i64))>,
+    embedding: Embedding,
 }
 
 enum SaveImageError {
+    Read(String),
     UnknownFormat,
     Corrupt,
+    Busy,
+    Tagging(String),
+}
+
+fn save_image_error(err: SaveImageError) -> Response {
+    match err {
+        SaveImageError::Read(err) => (StatusCode::INTERNAL_SERVER_ERROR, err).into_response(),
+        SaveImageError::UnknownFormat => {
+            (StatusCode::UNSUPPORTED_MEDIA_TYPE, "unknown image format").into_response()
+        }
+        SaveImageError::Corrupt => {
+            (StatusCode::UNPROCESSABLE_ENTITY, "corrupt image").into_response()
+        }
+        SaveImageError::Busy => busy(),
+        SaveImageError::Tagging(err) => (StatusCode::INTERNAL_SERVER_ERROR, err).into_response(),
+    }
+}
+
+/// Tells clients to back off while the worker pool is saturated.
+fn busy() -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(header::RETRY_AFTER, "5")],
+        "server is busy, try again later",
+    )
+        .into_response()
 }
 
 #[utoipa::path(
@@ -240,12 +721,63 @@ enum SaveImageError {
     responses(
         (status = 201, description = "Uploaded file successfully", body = String),
         (status = 400, description = "Failed to upload file", body = String),
+        (status = 422, description = "Not a video", body = String),
     )
 )]
-async fn upload_video_file(multipart: Multipart) -> Response {
+async fn upload_video_file(State(state): State<Arc<AppState>>, multipart: Multipart) -> Response {
     info!("Uploading...");
-    let file = extract_file("file", multipart).await.unwrap();
-    file.file_path.into_response()
+    let file = match extract_file("file", multipart).await {
+        Ok(file) => file,
+        Err(err) => return extract_file_error(err),
+    };
+    save_video(&state, &file.file_path).await
+}
+
+/// Probes a video file with ffprobe and adds it to the library with its streams.
+async fn save_video(state: &AppState, path: &str) -> Response {
+    let probe = match ffmpeg::probe(path).await {
+        Ok(probe)
+            if probe
+                .streams
+                .iter()
+                .any(|stream| stream.codec_type == "video") =>
+        {
+            probe
+        }
+        Ok(_) => return (StatusCode::UNPROCESSABLE_ENTITY, "no video stream").into_response(),
+        Err(ffmpeg::Error::IO(err)) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+        }
+        Err(err) => return (StatusCode::UNPROCESSABLE_ENTITY, err.to_string()).into_response(),
+    };
+    let hashed = match tokio::fs::metadata(path).await {
+        Ok(metadata) => inference_cache::file_content_hash(path)
+            .await
+            .map(|hash| (hash, metadata.len() as i64)),
+        Err(err) => Err(err),
+    };
+    let (hash, size) = match hashed {
+        Ok(hashed) => hashed,
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    };
+    let video = match state.db.save_video(path, &hash, size, &probe).await {
+        Ok(video) => video,
+        Err(err) => return database_error(err),
+    };
+    if let Err(err) = state.renditions.queue(video.video_id(), None).await {
+        warn!(
+            "failed to queue renditions of video {}: {}",
+            video.video_id(),
+            err
+        );
+    }
+    store_subtitles(state, video.video_id(), path, &probe).await;
+    store_chapters(state, video.video_id(), path, &probe).await;
+    (
+        StatusCode::CREATED,
+        serde_json::to_string_pretty(&video).unwrap(),
+    )
+        .into_response()
 }
 
 #[utoipa::path(
@@ -255,12 +787,85 @@ async fn upload_video_file(multipart: Multipart) -> Response {
     responses(
         (status = 201, description = "Uploaded file successfully", body = String),
         (status = 400, description = "Failed to upload file", body = String),
+        (status = 422, description = "Not audio", body = String),
     )
 )]
-async fn upload_music_file(multipart: Multipart) -> Response {
+async fn upload_music_file(State(state): State<Arc<AppState>>, multipart: Multipart) -> Response {
     info!("Uploading...");
-    let file = extract_file("file", multipart).await.unwrap();
-    file.file_path.into_response()
+    let file = match extract_file("file", multipart).await {
+        Ok(file) => file,
+        Err(err) => return extract_file_error(err),
+    };
+    let name = file.file_name.as_deref().unwrap_or(&file.file_path);
+    save_music(&state, &file.file_path, name).await
+}
+
+/// Reads an audio file's tags and embedded cover with ffprobe and adds it to the library.
+/// Untagged files are titled after `name`, the file name the file came with.
+async fn save_music(state: &AppState, path: &str, name: &str) -> Response {
+    let probe = match ffmpeg::probe(path).await {
+        Ok(probe)
+            if probe
+                .streams
+                .iter()
+                .any(|stream| stream.codec_type == "audio") =>
+        {
+            probe
+        }
+        Ok(_) => return (StatusCode::UNPROCESSABLE_ENTITY, "no audio stream").into_response(),
+        Err(ffmpeg::Error::IO(err)) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+        }
+        Err(err) => return (StatusCode::UNPROCESSABLE_ENTITY, err.to_string()).into_response(),
+    };
+    let tags = AudioTags::from_probe(&probe, std::path::Path::new(name));
+    // A cover that fails to extract or is not a picture is left out rather than failing
+    // the track.
+    let cover = match probe.cover_art {
+        true => match ffmpeg::extract_cover(path).await {
+            Ok(data) => match image::guess_format(&data) {
+                Ok(image::ImageFormat::Png) => Some(("image/png", data)),
+                Ok(image::ImageFormat::Jpeg) => Some(("image/jpeg", data)),
+                Ok(image::ImageFormat::WebP) => Some(("image/webp", data)),
+                Ok(image::ImageFormat::Gif) => Some(("image/gif", data)),
+                _ => None,
+            },
+            Err(err) => {
+                debug!("no cover from {}: {}", path, err);
+                None
+            }
+        },
+        false => None,
+    };
+    let cover_hash = cover
+        .as_ref()
+        .map(|(_, data)| inference_cache::content_hash(data));
+    let hashed = match tokio::fs::metadata(path).await {
+        Ok(metadata) => inference_cache::file_content_hash(path)
+            .await
+            .map(|hash| (hash, metadata.len() as i64)),
+        Err(err) => Err(err),
+    };
+    let (hash, size) = match hashed {
+        Ok(hashed) => hashed,
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    };
+    let cover = cover
+        .as_ref()
+        .zip(cover_hash.as_deref())
+        .map(|((mime, data), hash)| Cover { hash, mime, data });
+    match state
+        .db
+        .save_track(path, &hash, size, &tags, &probe, cover)
+        .await
+    {
+        Ok(track) => (
+            StatusCode::CREATED,
+            serde_json::to_string_pretty(&track).unwrap(),
+        )
+            .into_response(),
+        Err(err) => database_error(err),
+    }
 }
 
 #[allow(dead_code)]
@@ -276,22 +881,45 @@ struct MultipartFile {
     file_name: Option<String>,
     file_type: Option<String>,
 }
-async fn extract_file(field_name: &str, mut multipart: Multipart) -> Result<MultipartFile, ()> {
-    let field = loop {
+
+enum ExtractFileError {
+    /// The request has no such field or is not multipart.
+    Missing,
+    /// The upload broke off.
+    Body(String),
+    /// The upload could not be stored.
+    Store(std::io::Error),
+}
+
+fn extract_file_error(err: ExtractFileError) -> Response {
+    match err {
+        ExtractFileError::Missing => {
+            (StatusCode::BAD_REQUEST, "missing file field").into_response()
+        }
+        ExtractFileError::Body(err) => (StatusCode::BAD_REQUEST, err).into_response(),
+        ExtractFileError::Store(err) => {
+            (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+        }
+    }
+}
+
+/// Stores the field named `field_name` in a new file under `/tmp`.
+async fn extract_file(
+    field_name: &str,
+    mut multipart: Multipart,
+) -> Result<MultipartFile, ExtractFileError> {
+    let mut field = loop {
         match multipart.next_field().await {
             Ok(Some(field)) => match field.name() {
                 Some(name) if name == field_name => break field,
                 _ => continue,
             },
-            _ => return Err(()),
+            _ => return Err(ExtractFileError::Missing),
         }
     };
     let file_name = field.file_name().map(|str| str.to_owned());
     let file_type = field.content_type().map(|str| str.to_owned());
 
-    let body_with_io_error =
-        field.map_err(|err| tokio::io::Error::new(tokio::io::ErrorKind::Other, err));
-    let mut body_reader = StreamReader::new(body_with_io_error);
     let file_path = format!("/tmp/{}", uuid::Uuid::new_v4());
     let mut file_store = tokio::fs::File::options()
         .read(true)
@@ -299,10 +927,22 @@ async fn extract_file(field_name: &str, mut multipart: Multipart) -> Result<Mult
         .create_new(true)
         .open(&file_path)
         .await
-        .unwrap();
-    tokio::io::copy(&mut body_reader, &mut file_store)
-        .await
-        .unwrap();
+        .map_err(ExtractFileError::Store)?;
+    let stored = loop {
+        match field.chunk().await {
+            Ok(Some(chunk)) => {
+                if let Err(err) = file_store.write_all(&chunk).await {
+                    break Err(ExtractFileError::Store(err));
+                }
+            }
+            Ok(None) => break file_store.flush().await.map_err(ExtractFileError::Store),
+            Err(err) => break Err(ExtractFileError::Body(err.to_string())),
+        }
+    };
+    if let Err(err) = stored {
+        tokio::fs::remove_file(&file_path).await.ok();
+        return Err(err);
+    }
     Ok(MultipartFile {
         file_path,
         file_name,
@@ -325,6 +965,1803 @@ async fn image_thumbnail(Path(id): Path<i64>) -> Response {
     id.to_string().into_response()
 }
 
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct AutocompleteQuery {
+    /// Start of the tag name; case, `_` and ` ` are interchangeable
+    prefix: String,
+    /// Maximum number of tags to return, defaults to 10
+    limit: Option<i64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/tags/autocomplete",
+    params(AutocompleteQuery),
+    responses(
+        (status = 200, description = "Matching tags, most used first", body = String),
+        (status = 500, description = "Failed to query tags", body = String),
+    )
+)]
+async fn autocomplete_tags(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<AutocompleteQuery>,
+) -> Response {
+    let limit = query.limit.unwrap_or(10).clamp(1, 100);
+    match state.db.autocomplete_tags(&query.prefix, limit).await {
+        Ok(tags) => Json(tags).into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct SearchQuery {
+    /// Space separated tags, `-tag` to exclude, `"long hair"` for spaces and
+    /// `character:name` to match only tags of that category. `prompt:"red dress"` matches
+    /// generation prompts and `model:name` the checkpoint name or hash. `width`, `height`,
+    /// `megapixels`, `aspect` (`16:9`), `frames` and `bitdepth` compare with `<`, `<=`, `>=`
+    /// or `>`, e.g. `width:>=1920`; `taken:>=2020-05` compares capture dates; `format:png`,
+    /// `camera:canon` and `lens:50mm` match text and `animated`, `alpha` and `gps` take
+    /// `yes` or `no`. Videos also take `duration` (`600` or `10:00`), `fps`, `bitrate` in
+    /// kbps, `channels`, `codec:h264` and `said:"good morning"` for text in their subtitles
+    q: String,
+    /// Maximum number of results, defaults to 50
+    limit: Option<i64>,
+    /// Number of results to skip
+    offset: Option<i64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/search/image",
+    params(SearchQuery),
+    responses(
+        (status = 200, description = "Matching images, newest first", body = String),
+        (status = 500, description = "Failed to search", body = String),
+    )
+)]
+async fn search_images(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<SearchQuery>,
+) -> Response {
+    let filters = search::parse(&query.q);
+    let limit = query.limit.unwrap_or(50).clamp(1, 500);
+    let offset = query.offset.unwrap_or(0).max(0);
+    match state.db.search_images(&filters, limit, offset).await {
+        Ok(images) => Json(images).into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/search/video",
+    params(SearchQuery),
+    responses(
+        (status = 200, description = "Matching videos, newest first", body = String),
+        (status = 500, description = "Failed to search", body = String),
+    )
+)]
+async fn search_videos(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<SearchQuery>,
+) -> Response {
+    let filters = search::parse(&query.q);
+    let limit = query.limit.unwrap_or(50).clamp(1, 500);
+    let offset = query.offset.unwrap_or(0).max(0);
+    match state.db.search_videos(&filters, limit, offset).await {
+        Ok(videos) => Json(videos).into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct SubtitleSearchQuery {
+    /// Text to find, ignoring case
+    q: String,
+    /// Maximum number of subtitles, defaults to 20
+    limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct SubtitleMatch {
+    video_id: i64,
+    language: String,
+    /// Cues containing the text, empty when it runs across cues
+    cues: Vec<subtitles::Cue>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/search/subtitles",
+    params(SubtitleSearchQuery),
+    responses(
+        (status = 200, description = "Subtitles containing the text with the cues saying it, newest videos first", body = String),
+        (status = 400, description = "Empty query", body = String),
+        (status = 500, description = "Failed to search", body = String),
+    )
+)]
+async fn search_subtitles(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<SubtitleSearchQuery>,
+) -> Response {
+    let text = query.q.trim();
+    if text.is_empty() {
+        return (StatusCode::BAD_REQUEST, "empty query").into_response();
+    }
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+    let found = match state.db.search_subtitles(text, limit).await {
+        Ok(found) => found,
+        Err(err) => return database_error(err),
+    };
+    let needle = text.to_lowercase();
+    let matches: Vec<SubtitleMatch> = found
+        .into_iter()
+        .map(|(video_id, language, vtt)| SubtitleMatch {
+            video_id,
+            language,
+            cues: subtitles::cues(&vtt)
+                .into_iter()
+                .filter(|cue| cue.text.replace('\n', " ").to_lowercase().contains(&needle))
+                .collect(),
+        })
+        .collect();
+    Json(matches).into_response()
+}
+
+/// Fingerprints at most this many bits apart are taken to be the same picture.
+const MAX_FINGERPRINT_DISTANCE: u32 = 10;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct SearchByImageQuery {
+    /// Maximum number of matches, defaults to 20
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct SearchByImageResult {
+    /// What the primary tagger sees in the query image
+    tags: Vec<String>,
+    matches: Vec<ImageMatch>,
+}
+
+#[derive(Serialize)]
+struct ImageMatch {
+    #[serde(flatten)]
+    image: ImageRow,
+    /// Differing fingerprint bits out of 64, `0` for the same picture
+    distance: u32,
+    /// Tags in common over tags of either image
+    tag_overlap: f32,
+}
+
+#[utoipa::path(
+    post,
+    path = "/search/by-image",
+    params(SearchByImageQuery),
+    request_body(content = UploadFileBody, content_type="multipart/form-data"),
+    responses(
+        (status = 200, description = "Library images that look alike or share tags, closest fingerprint first", body = String),
+        (status = 400, description = "No file in the request", body = String),
+        (status = 503, description = "Too many images are being processed, retry later", body = String),
+    )
+)]
+async fn search_by_image(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<SearchByImageQuery>,
+    multipart: Multipart,
+) -> Response {
+    let file = match extract_file("file", multipart).await {
+        Ok(file) => file,
+        Err(err) => return extract_file_error(err),
+    };
+    // The query is only looked at, never kept.
+    let path = file.file_path.clone();
+    let decoded = decode_image(file, &state).await;
+    tokio::fs::remove_file(&path).await.ok();
+    let decoded = match decoded {
+        Ok(decoded) => decoded,
+        Err(err) => return save_image_error(err),
+    };
+    let tagging = &state.taggers[0];
+    let scores = match tagging.cached_scores(&decoded.content_hash).await {
+        Some(scores) => scores,
+        None => match tagging.try_infer_scores(None, decoded.image).await {
+            Ok(scores) => scores,
+            Err(tagging::Error::Worker(workers::Error::Busy)) => return busy(),
+            Err(err) => {
+                return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+            }
+        },
+    };
+    let tags = tagging.select(&scores);
+    let query_tags: HashSet<i64> = tags
+        .iter()
+        .map(|(_, index)| tagging.tag_id(*index))
+        .collect();
+    let tag_names = tags
+        .iter()
+        .map(|(_, index)| tagging.tagger().labels()[*index].name.clone())
+        .collect();
+
+    let limit = query.limit.unwrap_or(20).clamp(1, 200);
+    let similarity = state.similarity.clone();
+    let similar = match state
+        .cpu
+        .try_run(move || similarity.similar_to(&Embedding::from_scores(&scores), limit))
+        .await
+    {
+        Ok(similar) => similar,
+        Err(workers::Error::Busy) => return busy(),
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    };
+    let distance = |id: &i64| {
+        state.fingerprints.get(*id).map_or(u32::MAX, |other| {
+            fingerprint::distance(decoded.fingerprint, other)
+        })
+    };
+    let mut candidates: Vec<i64> = state
+        .fingerprints
+        .near(decoded.fingerprint, MAX_FINGERPRINT_DISTANCE)
+        .into_iter()
+        .chain(similar.iter().map(|(id, _)| *id))
+        .collect();
+    candidates.sort_unstable();
+    candidates.dedup();
+
+    let mut candidate_tags: HashMap<i64, HashSet<i64>> = HashMap::new();
+    match state.db.image_tag_ids(&candidates).await {
+        Ok(tags) => {
+            for (image_id, tag_id) in tags {
+                candidate_tags.entry(image_id).or_default().insert(tag_id);
+            }
+        }
+        Err(err) => return database_error(err),
+    }
+    let tag_overlap = |id: &i64| {
+        let tags = candidate_tags.get(id).cloned().unwrap_or_default();
+        let union = tags.union(&query_tags).count();
+        match union {
+            0 => 0.0,
+            union => tags.intersection(&query_tags).count() as f32 / union as f32,
+        }
+    };
+    let mut ranked: Vec<(i64, u32, f32)> = candidates
+        .iter()
+        .map(|id| (*id, distance(id), tag_overlap(id)))
+        .collect();
+    ranked.sort_by(|a, b| a.1.cmp(&b.1).then(b.2.total_cmp(&a.2)));
+    ranked.truncate(limit);
+
+    let ids: Vec<i64> = ranked.iter().map(|(id, _, _)| *id).collect();
+    let mut images: HashMap<i64, ImageRow> = match state.db.images_by_ids(&ids).await {
+        Ok(images) => images
+            .into_iter()
+            .map(|image| (image.img_id(), image))
+            .collect(),
+        Err(err) => return database_error(err),
+    };
+    // Ids the database no longer knows are left out rather than shifting the scores.
+    let matches = ranked
+        .into_iter()
+        .filter_map(|(id, distance, tag_overlap)| {
+            Some(ImageMatch {
+                image: images.remove(&id)?,
+                distance,
+                tag_overlap,
+            })
+        })
+        .collect();
+    Json(SearchByImageResult {
+        tags: tag_names,
+        matches,
+    })
+    .into_response()
+}
+
+#[derive(ToSchema, Deserialize)]
+struct TagAliasBody {
+    alias: String,
+    tag: String,
+}
+
+#[derive(ToSchema, Deserialize)]
+struct TagImplicationBody {
+    tag: String,
+    implies: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/tags/aliases",
+    responses(
+        (status = 200, description = "All tag aliases", body = String),
+    ),
+    security(("api_key" = []))
+)]
+async fn list_tag_aliases(State(state): State<Arc<AppState>>) -> Response {
+    match state.db.list_tag_aliases().await {
+        Ok(aliases) => Json(aliases).into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/tags/aliases",
+    request_body(content = TagAliasBody),
+    responses(
+        (status = 201, description = "Alias created or repointed", body = String),
+        (status = 404, description = "Target tag does not exist", body = String),
+        (status = 409, description = "Alias is already a tag", body = String),
+    ),
+    security(("api_key" = []))
+)]
+async fn add_tag_alias(
+    State(state): State<Arc<AppState>>,
+    Json(body): Json<TagAliasBody>,
+) -> Response {
+    match state.db.add_tag_alias(&body.alias, &body.tag).await {
+        Ok(alias) => (StatusCode::CREATED, Json(alias)).into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/tags/aliases/{alias}",
+    params(
+        ("alias" = String, Path, description = "Alias to remove"),
+    ),
+    responses(
+        (status = 204, description = "Alias removed"),
+        (status = 404, description = "Alias not found"),
+    ),
+    security(("api_key" = []))
+)]
+async fn remove_tag_alias(
+    State(state): State<Arc<AppState>>,
+    Path(alias): Path<String>,
+) -> Response {
+    match state.db.remove_tag_alias(&alias).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => StatusCode::NOT_FOUND.into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/tags/implications",
+    responses(
+        (status = 200, description = "All tag implications", body = String),
+    ),
+    security(("api_key" = []))
+)]
+async fn list_tag_implications(State(state): State<Arc<AppState>>) -> Response {
+    match state.db.list_tag_implications().await {
+        Ok(implications) => Json(implications).into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/tags/implications",
+    request_body(content = TagImplicationBody),
+    responses(
+        (status = 201, description = "Implication created", body = String),
+        (status = 404, description = "Either tag does not exist", body = String),
+        (status = 409, description = "Implication would create a cycle", body = String),
+    ),
+    security(("api_key" = []))
+)]
+async fn add_tag_implication(
+    State(state): State<Arc<AppState>>,
+    Json(body): Json<TagImplicationBody>,
+) -> Response {
+    match state.db.add_tag_implication(&body.tag, &body.implies).await {
+        Ok(implication) => (StatusCode::CREATED, Json(implication)).into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/tags/implications/{tag}/{implied}",
+    params(
+        ("tag" = String, Path, description = "Implying tag"),
+        ("implied" = String, Path, description = "Implied tag"),
+    ),
+    responses(
+        (status = 204, description = "Implication removed"),
+        (status = 404, description = "Implication not found"),
+    ),
+    security(("api_key" = []))
+)]
+async fn remove_tag_implication(
+    State(state): State<Arc<AppState>>,
+    Path((tag, implied)): Path<(String, String)>,
+) -> Response {
+    match state.db.remove_tag_implication(&tag, &implied).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => StatusCode::NOT_FOUND.into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/video-profiles",
+    responses(
+        (status = 200, description = "Transcode profiles every ingested video is made in", body = String),
+    ),
+    security(("api_key" = []))
+)]
+async fn list_video_profiles(State(state): State<Arc<AppState>>) -> Response {
+    Json(state.renditions.profiles()).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/download-profiles",
+    responses(
+        (status = 200, description = "yt-dlp profiles URL uploads can pick, the default of each kind first", body = String),
+    ),
+    security(("api_key" = []))
+)]
+async fn list_download_profiles(State(state): State<Arc<AppState>>) -> Response {
+    Json(&state.download_profiles).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/taggers",
+    responses(
+        (status = 200, description = "Descriptors of the loaded taggers, primary first", body = String),
+    ),
+    security(("api_key" = []))
+)]
+async fn list_taggers(State(state): State<Arc<AppState>>) -> Response {
+    let descriptors: Vec<_> = state
+        .taggers
+        .iter()
+        .map(|tagging| {
+            let mut descriptor = tagging.tagger().descriptor().clone();
+            descriptor.thresholds = tagging.tagger().config();
+            descriptor
+        })
+        .collect();
+    Json(descriptors).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/taggers/{model}/config",
+    params(
+        ("model" = String, Path, description = "Tagger name"),
+    ),
+    responses(
+        (status = 200, description = "Active per-category limits and per-tag overrides", body = String),
+        (status = 404, description = "Tagger not found", body = String),
+    ),
+    security(("api_key" = []))
+)]
+async fn get_tagger_config(
+    State(state): State<Arc<AppState>>,
+    Path(model): Path<String>,
+) -> Response {
+    match state.tagger(&model) {
+        Some(tagging) => Json(tagging.tagger().config()).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/admin/taggers/{model}/config",
+    params(
+        ("model" = String, Path, description = "Tagger name"),
+    ),
+    request_body(content = String, description = "Tagger thresholds, same shape as in the descriptor file", content_type = "application/json"),
+    responses(
+        (status = 200, description = "Config replaced", body = String),
+        (status = 404, description = "Tagger not found or an override names an unknown label", body = String),
+    ),
+    security(("api_key" = []))
+)]
+async fn put_tagger_config(
+    State(state): State<Arc<AppState>>,
+    Path(model): Path<String>,
+    Json(config): Json<TaggerConfig>,
+) -> Response {
+    let Some(tagging) = state.tagger(&model) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    match tagging.tagger().set_config(config) {
+        Ok(()) => Json(tagging.tagger().config()).into_response(),
+        Err(err) => tagger_error(err),
+    }
+}
+
+#[derive(ToSchema, Deserialize)]
+struct TaggerOverrideBody {
+    min_score: f32,
+}
+
+#[utoipa::path(
+    put,
+    path = "/admin/taggers/{model}/overrides/{tag}",
+    params(
+        ("model" = String, Path, description = "Tagger name"),
+        ("tag" = String, Path, description = "Tag name or alias"),
+    ),
+    request_body(content = TaggerOverrideBody),
+    responses(
+        (status = 200, description = "Override set", body = String),
+        (status = 404, description = "Tagger or tag not found", body = String),
+    ),
+    security(("api_key" = []))
+)]
+async fn put_tagger_override(
+    State(state): State<Arc<AppState>>,
+    Path((model, tag)): Path<(String, String)>,
+    Json(body): Json<TaggerOverrideBody>,
+) -> Response {
+    set_tagger_override(&state, &model, &tag, Some(body.min_score)).await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/taggers/{model}/overrides/{tag}",
+    params(
+        ("model" = String, Path, description = "Tagger name"),
+        ("tag" = String, Path, description = "Tag name or alias"),
+    ),
+    responses(
+        (status = 200, description = "Override cleared", body = String),
+        (status = 404, description = "Tagger or tag not found", body = String),
+    ),
+    security(("api_key" = []))
+)]
+async fn delete_tagger_override(
+    State(state): State<Arc<AppState>>,
+    Path((model, tag)): Path<(String, String)>,
+) -> Response {
+    set_tagger_override(&state, &model, &tag, None).await
+}
+
+async fn set_tagger_override(
+    state: &AppState,
+    model: &str,
+    tag: &str,
+    min_score: Option<f32>,
+) -> Response {
+    let Some(tagging) = state.tagger(model) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let tag = match state.db.require_tag(tag).await {
+        Ok(tag) => tag,
+        Err(err) => return database_error(err),
+    };
+    match tagging.tagger().set_override(tag.name(), min_score) {
+        Ok(()) => Json(tagging.tagger().config()).into_response(),
+        Err(err) => tagger_error(err),
+    }
+}
+
+fn tagger_error(err: tagger::Error) -> Response {
+    let status = match err {
+        tagger::Error::UnknownLabel(_) => StatusCode::NOT_FOUND,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, err.to_string()).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/image/{id}/model-tags",
+    params(
+        ("id" = i64, Path, description = "Image id"),
+    ),
+    responses(
+        (status = 200, description = "What each tagger predicted, keyed by model and version", body = String),
+        (status = 404, description = "Image not found", body = String),
+    )
+)]
+async fn list_image_model_tags(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Response {
+    match state.db.media_exists(MediaKind::Image, id).await {
+        Ok(true) => {}
+        Ok(false) => return StatusCode::NOT_FOUND.into_response(),
+        Err(err) => return database_error(err),
+    }
+    match state.db.list_model_tags(id).await {
+        Ok(tags) => Json(ModelTagRow::group_by_model(tags)).into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct RelatedQuery {
+    /// Maximum number of images to return, defaults to 20
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct RelatedImage {
+    #[serde(flatten)]
+    image: ImageRow,
+    /// Cosine similarity of the primary tagger's scores, `1.0` for identical ones
+    similarity: f32,
+}
+
+#[utoipa::path(
+    get,
+    path = "/image/{id}/related",
+    params(
+        ("id" = i64, Path, description = "Image id"),
+        RelatedQuery,
+    ),
+    responses(
+        (status = 200, description = "Images with similar tag scores, most similar first", body = String),
+        (status = 404, description = "Image not found or not tagged since embeddings were added", body = String),
+        (status = 503, description = "Too many images are being processed, retry later", body = String),
+    )
+)]
+async fn related_images(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Query(query): Query<RelatedQuery>,
+) -> Response {
+    let limit = query.limit.unwrap_or(20).clamp(1, 200);
+    let similarity = state.similarity.clone();
+    let related = match state
+        .cpu
+        .try_run(move || similarity.related(id, limit))
+        .await
+    {
+        Ok(Some(related)) => related,
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                "image has no embedding, re-tag it to add one",
+            )
+                .into_response()
+        }
+        Err(workers::Error::Busy) => return busy(),
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    };
+    let ids: Vec<i64> = related.iter().map(|(id, _)| *id).collect();
+    let images = match state.db.images_by_ids(&ids).await {
+        Ok(images) => images,
+        Err(err) => return database_error(err),
+    };
+    let similarities: HashMap<i64, f32> = related.into_iter().collect();
+    let related: Vec<RelatedImage> = images
+        .into_iter()
+        .map(|image| RelatedImage {
+            similarity: similarities[&image.img_id()],
+            image,
+        })
+        .collect();
+    Json(related).into_response()
+}
+
+#[derive(ToSchema, Deserialize)]
+struct AddTagsBody {
+    /// Tag names or aliases; unknown names become new tags, `artist:name` picks the category
+    tags: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ImageDetail {
+    #[serde(flatten)]
+    image: ImageRow,
+    /// Dimensions, format and EXIF, missing for images added before they were recorded
+    metadata: Option<ImageMetadataRow>,
+    /// Stable Diffusion settings found in the file, if any
+    generation_params: Option<GenerationParamsRow>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/image/{id}",
+    params(
+        ("id" = i64, Path, description = "Image id"),
+    ),
+    responses(
+        (status = 200, description = "The image with its metadata and generation parameters", body = String),
+        (status = 404, description = "Image not found", body = String),
+    )
+)]
+async fn get_image(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> Response {
+    let image = match state.db.get_image(id).await {
+        Ok(Some(image)) => image,
+        Ok(None) => return (StatusCode::NOT_FOUND, "image not found").into_response(),
+        Err(err) => return database_error(err),
+    };
+    let metadata = match state.db.get_image_metadata(id).await {
+        Ok(metadata) => metadata,
+        Err(err) => return database_error(err),
+    };
+    match state.db.get_generation_params(id).await {
+        Ok(generation_params) => Json(ImageDetail {
+            image,
+            metadata,
+            generation_params,
+        })
+        .into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/image/{id}/tags",
+    params(
+        ("id" = i64, Path, description = "Image id"),
+    ),
+    responses(
+        (status = 200, description = "Tags with score and source, grouped by category", body = String),
+        (status = 404, description = "Image not found", body = String),
+    )
+)]
+async fn list_image_tags(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> Response {
+    list_tags(&state, MediaKind::Image, id).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/image/{id}/tags",
+    params(
+        ("id" = i64, Path, description = "Image id"),
+    ),
+    request_body(content = AddTagsBody),
+    responses(
+        (status = 200, description = "Tags after the addition", body = String),
+        (status = 404, description = "Image not found", body = String),
+    )
+)]
+async fn add_image_tags(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Json(body): Json<AddTagsBody>,
+) -> Response {
+    add_tags(&state, MediaKind::Image, id, body).await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/image/{id}/tags/{tag}",
+    params(
+        ("id" = i64, Path, description = "Image id"),
+        ("tag" = String, Path, description = "Tag name or alias"),
+    ),
+    responses(
+        (status = 204, description = "Tag removed and blocked from being re-added by models"),
+        (status = 404, description = "Image or tag not found", body = String),
+    )
+)]
+async fn remove_image_tag(
+    State(state): State<Arc<AppState>>,
+    Path((id, tag)): Path<(i64, String)>,
+) -> Response {
+    remove_tag(&state, MediaKind::Image, id, &tag).await
+}
+
+#[derive(Serialize)]
+struct VideoDetail {
+    #[serde(flatten)]
+    video: VideoRow,
+    /// Container details with the first video and audio stream
+    summary: Option<VideoSummaryRow>,
+    streams: Vec<VideoStreamRow>,
+    /// The original file and its transcodes
+    renditions: Vec<RenditionRow>,
+    subtitles: Vec<SubtitleRow>,
+    chapters: Vec<ChapterRow>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/video/{id}",
+    params(
+        ("id" = i64, Path, description = "Video id"),
+    ),
+    responses(
+        (status = 200, description = "The video with a summary, its streams, renditions, subtitles and chapters", body = String),
+        (status = 404, description = "Video not found", body = String),
+    )
+)]
+async fn get_video(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> Response {
+    let video = match state.db.get_video(id).await {
+        Ok(Some(video)) => video,
+        Ok(None) => return (StatusCode::NOT_FOUND, "video not found").into_response(),
+        Err(err) => return database_error(err),
+    };
+    let summary = match state.db.get_video_summary(id).await {
+        Ok(summary) => summary,
+        Err(err) => return database_error(err),
+    };
+    let streams = match state.db.list_video_streams(id).await {
+        Ok(streams) => streams,
+        Err(err) => return database_error(err),
+    };
+    let renditions = match state.db.list_video_renditions(id).await {
+        Ok(renditions) => renditions,
+        Err(err) => return database_error(err),
+    };
+    let subtitles = match state.db.list_video_subtitles(id).await {
+        Ok(subtitles) => subtitles,
+        Err(err) => return database_error(err),
+    };
+    match state.db.list_video_chapters(id).await {
+        Ok(chapters) => Json(VideoDetail {
+            video,
+            summary,
+            streams,
+            renditions,
+            subtitles,
+            chapters,
+        })
+        .into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/video/{id}/renditions",
+    params(
+        ("id" = i64, Path, description = "Video id"),
+    ),
+    responses(
+        (status = 200, description = "The original file and its transcodes with their status", body = String),
+        (status = 404, description = "Video not found", body = String),
+    )
+)]
+async fn list_video_renditions(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Response {
+    match state.db.media_exists(MediaKind::Video, id).await {
+        Ok(true) => {}
+        Ok(false) => return (StatusCode::NOT_FOUND, "video not found").into_response(),
+        Err(err) => return database_error(err),
+    }
+    match state.db.list_video_renditions(id).await {
+        Ok(renditions) => Json(renditions).into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[derive(ToSchema, Deserialize)]
+struct QueueRenditionsBody {
+    /// Profile names, all profiles when missing
+    profiles: Option<Vec<String>>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/video/{id}/renditions",
+    params(
+        ("id" = i64, Path, description = "Video id"),
+    ),
+    request_body(content = QueueRenditionsBody),
+    responses(
+        (status = 202, description = "Renditions queued; made ones are kept and failed ones retried", body = String),
+        (status = 400, description = "Unknown profile", body = String),
+        (status = 404, description = "Video not found", body = String),
+    ),
+    security(("api_key" = []))
+)]
+async fn queue_video_renditions(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Json(body): Json<QueueRenditionsBody>,
+) -> Response {
+    match state.db.media_exists(MediaKind::Video, id).await {
+        Ok(true) => {}
+        Ok(false) => return (StatusCode::NOT_FOUND, "video not found").into_response(),
+        Err(err) => return database_error(err),
+    }
+    match state.renditions.queue(id, body.profiles.as_deref()).await {
+        Ok(()) => {}
+        Err(err @ renditions::Error::UnknownProfile(_)) => {
+            return (StatusCode::BAD_REQUEST, err.to_string()).into_response()
+        }
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+    match state.db.list_video_renditions(id).await {
+        Ok(renditions) => (StatusCode::ACCEPTED, Json(renditions)).into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/video/{id}/renditions/{profile}/file",
+    params(
+        ("id" = i64, Path, description = "Video id"),
+        ("profile" = String, Path, description = "Profile name, or `original`"),
+    ),
+    responses(
+        (status = 200, description = "The rendition's file, answering range requests", content_type = "video/*"),
+        (status = 404, description = "Video or rendition not found, or not made yet", body = String),
+    )
+)]
+async fn video_rendition_file(
+    State(state): State<Arc<AppState>>,
+    Path((id, profile)): Path<(i64, String)>,
+    headers: HeaderMap,
+) -> Response {
+    let rendition = match state.db.get_video_rendition(id, &profile).await {
+        Ok(rendition) => rendition,
+        Err(err) => return database_error(err),
+    };
+    match rendition.as_ref().and_then(|rendition| rendition.path()) {
+        Some(path) => streaming::serve_file(path, streaming::content_type(path), &headers).await,
+        None => (StatusCode::NOT_FOUND, "rendition not found").into_response(),
+    }
+}
+
+/// Content type of subtitles.
+const VTT: &str = "text/vtt; charset=utf-8";
+
+#[utoipa::path(
+    get,
+    path = "/video/{id}/subtitles",
+    params(
+        ("id" = i64, Path, description = "Video id"),
+    ),
+    responses(
+        (status = 200, description = "Languages the video has subtitles in", body = String),
+        (status = 404, description = "Video not found", body = String),
+    )
+)]
+async fn list_video_subtitles(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> Response {
+    match state.db.media_exists(MediaKind::Video, id).await {
+        Ok(true) => {}
+        Ok(false) => return (StatusCode::NOT_FOUND, "video not found").into_response(),
+        Err(err) => return database_error(err),
+    }
+    match state.db.list_video_subtitles(id).await {
+        Ok(subtitles) => Json(subtitles).into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/video/{id}/subtitles/{file}",
+    params(
+        ("id" = i64, Path, description = "Video id"),
+        ("file" = String, Path, description = "Language with a `.vtt` extension, e.g. `en.vtt`"),
+    ),
+    responses(
+        (status = 200, description = "The subtitle as WebVTT", content_type = "text/vtt"),
+        (status = 404, description = "Video or subtitle not found", body = String),
+    )
+)]
+async fn video_subtitle_file(
+    State(state): State<Arc<AppState>>,
+    Path((id, file)): Path<(i64, String)>,
+) -> Response {
+    let Some(language) = file.strip_suffix(".vtt") else {
+        return (StatusCode::NOT_FOUND, "subtitle not found").into_response();
+    };
+    match state.db.get_video_subtitle(id, language).await {
+        Ok(Some(vtt)) => ([(header::CONTENT_TYPE, VTT)], vtt).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "subtitle not found").into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/video/{id}/chapters",
+    params(
+        ("id" = i64, Path, description = "Video id"),
+    ),
+    responses(
+        (status = 200, description = "Chapters in order with their tags", body = String),
+        (status = 404, description = "Video not found", body = String),
+    )
+)]
+async fn list_video_chapters(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> Response {
+    match state.db.media_exists(MediaKind::Video, id).await {
+        Ok(true) => {}
+        Ok(false) => return (StatusCode::NOT_FOUND, "video not found").into_response(),
+        Err(err) => return database_error(err),
+    }
+    match state.db.list_video_chapters(id).await {
+        Ok(chapters) => Json(chapters).into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/video/{id}/chapters/{index}/thumbnail",
+    params(
+        ("id" = i64, Path, description = "Video id"),
+        ("index" = i64, Path, description = "Chapter number, from 0"),
+    ),
+    responses(
+        (status = 200, description = "A frame from early in the chapter", content_type = "image/jpeg"),
+        (status = 404, description = "Chapter not found, or its thumbnail not taken (yet)", body = String),
+    )
+)]
+async fn chapter_thumbnail(
+    State(state): State<Arc<AppState>>,
+    Path((id, index)): Path<(i64, i64)>,
+) -> Response {
+    match state.db.get_chapter_thumbnail(id, index).await {
+        Ok(Some(thumbnail)) => ([(header::CONTENT_TYPE, "image/jpeg")], thumbnail).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "thumbnail not found").into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/video/{id}/chapters/{index}/clip",
+    params(
+        ("id" = i64, Path, description = "Video id"),
+        ("index" = i64, Path, description = "Chapter number, from 0"),
+    ),
+    responses(
+        (status = 200, description = "The chapter cut from the original without re-encoding, starting at the keyframe before it", content_type = "video/x-matroska"),
+        (status = 404, description = "Video or chapter not found", body = String),
+    )
+)]
+async fn chapter_clip(
+    State(state): State<Arc<AppState>>,
+    Path((id, index)): Path<(i64, i64)>,
+) -> Response {
+    let chapter = match state.db.get_video_chapter(id, index).await {
+        Ok(Some(chapter)) => chapter,
+        Ok(None) => return (StatusCode::NOT_FOUND, "chapter not found").into_response(),
+        Err(err) => return database_error(err),
+    };
+    let video = match state.db.get_video(id).await {
+        Ok(Some(video)) => video,
+        Ok(None) => return (StatusCode::NOT_FOUND, "video not found").into_response(),
+        Err(err) => return database_error(err),
+    };
+    let duration = chapter.end_time() - chapter.start_time();
+    let mut child = match ffmpeg::clip(video.path(), chapter.start_time(), duration) {
+        Ok(child) => child,
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    };
+    let Some(stdout) = child.stdout.take() else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    // ffmpeg stops when the client goes away and the pipe closes.
+    tokio::spawn(async move { child.wait().await.ok() });
+    (
+        [
+            (header::CONTENT_TYPE, "video/x-matroska".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!(
+                    "attachment; filename=\"{}-{}.mkv\"",
+                    id,
+                    chapter.chapter_index()
+                ),
+            ),
+        ],
+        Body::from_stream(ReaderStream::new(stdout)),
+    )
+        .into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/video/{id}/chapters/{index}/tags",
+    params(
+        ("id" = i64, Path, description = "Video id"),
+        ("index" = i64, Path, description = "Chapter number, from 0"),
+    ),
+    responses(
+        (status = 200, description = "Tags of the chapter by category", body = String),
+        (status = 404, description = "Chapter not found", body = String),
+    )
+)]
+async fn list_chapter_tags(
+    State(state): State<Arc<AppState>>,
+    Path((id, index)): Path<(i64, i64)>,
+) -> Response {
+    match state.db.get_video_chapter(id, index).await {
+        Ok(Some(chapter)) => list_tags(&state, MediaKind::Chapter, chapter.chapter_id()).await,
+        Ok(None) => (StatusCode::NOT_FOUND, "chapter not found").into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/video/{id}/chapters/{index}/tags",
+    params(
+        ("id" = i64, Path, description = "Video id"),
+        ("index" = i64, Path, description = "Chapter number, from 0"),
+    ),
+    request_body(content = AddTagsBody),
+    responses(
+        (status = 200, description = "Tags after the addition", body = String),
+        (status = 404, description = "Chapter not found", body = String),
+    )
+)]
+async fn add_chapter_tags(
+    State(state): State<Arc<AppState>>,
+    Path((id, index)): Path<(i64, i64)>,
+    Json(body): Json<AddTagsBody>,
+) -> Response {
+    match state.db.get_video_chapter(id, index).await {
+        Ok(Some(chapter)) => add_tags(&state, MediaKind::Chapter, chapter.chapter_id(), body).await,
+        Ok(None) => (StatusCode::NOT_FOUND, "chapter not found").into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/video/{id}/chapters/{index}/tags/{tag}",
+    params(
+        ("id" = i64, Path, description = "Video id"),
+        ("index" = i64, Path, description = "Chapter number, from 0"),
+        ("tag" = String, Path, description = "Tag name or alias"),
+    ),
+    responses(
+        (status = 204, description = "Tag removed and blocked from being re-added by models"),
+        (status = 404, description = "Chapter or tag not found", body = String),
+    )
+)]
+async fn remove_chapter_tag(
+    State(state): State<Arc<AppState>>,
+    Path((id, index, tag)): Path<(i64, i64, String)>,
+) -> Response {
+    match state.db.get_video_chapter(id, index).await {
+        Ok(Some(chapter)) => {
+            remove_tag(&state, MediaKind::Chapter, chapter.chapter_id(), &tag).await
+        }
+        Ok(None) => (StatusCode::NOT_FOUND, "chapter not found").into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+/// Content type of HLS playlists.
+const M3U8: &str = "application/vnd.apple.mpegurl";
+
+#[utoipa::path(
+    get,
+    path = "/video/{id}/hls/master.m3u8",
+    params(
+        ("id" = i64, Path, description = "Video id"),
+    ),
+    responses(
+        (status = 200, description = "HLS master playlist listing the renditions", content_type = "application/vnd.apple.mpegurl"),
+        (status = 404, description = "Video not found", body = String),
+        (status = 422, description = "The video has no picture to package", body = String),
+    )
+)]
+async fn hls_master_playlist(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> Response {
+    match state.db.get_video_summary(id).await {
+        Ok(Some(summary)) => match summary.width().zip(summary.height()) {
+            Some((width, height)) => (
+                [(header::CONTENT_TYPE, M3U8)],
+                hls::master_playlist(width as u32, height as u32),
+            )
+                .into_response(),
+            None => (StatusCode::UNPROCESSABLE_ENTITY, "no video stream").into_response(),
+        },
+        Ok(None) => (StatusCode::NOT_FOUND, "video not found").into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/video/{id}/hls/{rendition}/{file}",
+    params(
+        ("id" = i64, Path, description = "Video id"),
+        ("rendition" = String, Path, description = "Rendition from the master playlist, e.g. `720p`"),
+        ("file" = String, Path, description = "`index.m3u8` or a segment it lists"),
+    ),
+    responses(
+        (status = 200, description = "The rendition's playlist, packaged on first request, or a segment", content_type = "application/vnd.apple.mpegurl"),
+        (status = 404, description = "Video, rendition or segment not found", body = String),
+        (status = 503, description = "Too many videos are being packaged, or the first segment is not ready, retry later", body = String),
+    )
+)]
+async fn hls_file(
+    State(state): State<Arc<AppState>>,
+    Path((id, rendition, file)): Path<(i64, String, String)>,
+    headers: HeaderMap,
+) -> Response {
+    if file != "index.m3u8" {
+        return match state.hls.segment(id, &rendition, &file) {
+            Some(path) => {
+                streaming::serve_file(&path.to_string_lossy(), "video/mp2t", &headers).await
+            }
+            None => (StatusCode::NOT_FOUND, "segment not found").into_response(),
+        };
+    }
+    let video = match state.db.get_video(id).await {
+        Ok(Some(video)) => video,
+        Ok(None) => return (StatusCode::NOT_FOUND, "video not found").into_response(),
+        Err(err) => return database_error(err),
+    };
+    let height = match state.db.get_video_summary(id).await {
+        Ok(summary) => summary.and_then(|summary| summary.height()),
+        Err(err) => return database_error(err),
+    };
+    let Some(height) = height else {
+        return (StatusCode::UNPROCESSABLE_ENTITY, "no video stream").into_response();
+    };
+    let playlist = match state
+        .hls
+        .playlist(id, video.path(), height as u32, &rendition)
+        .await
+    {
+        Ok(playlist) => playlist,
+        Err(hls::Error::UnknownRendition(_)) => {
+            return (StatusCode::NOT_FOUND, "rendition not found").into_response()
+        }
+        Err(hls::Error::Busy) => return busy(),
+        Err(err @ hls::Error::NotReady) => {
+            return (StatusCode::SERVICE_UNAVAILABLE, err.to_string()).into_response()
+        }
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    };
+    match tokio::fs::read(&playlist).await {
+        Ok(playlist) => ([(header::CONTENT_TYPE, M3U8)], playlist).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/video/{id}/tags",
+    params(
+        ("id" = i64, Path, description = "Video id"),
+    ),
+    responses(
+        (status = 200, description = "Tags with score and source, grouped by category", body = String),
+        (status = 404, description = "Video not found", body = String),
+    )
+)]
+async fn list_video_tags(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> Response {
+    list_tags(&state, MediaKind::Video, id).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/video/{id}/tags",
+    params(
+        ("id" = i64, Path, description = "Video id"),
+    ),
+    request_body(content = AddTagsBody),
+    responses(
+        (status = 200, description = "Tags after the addition", body = String),
+        (status = 404, description = "Video not found", body = String),
+    )
+)]
+async fn add_video_tags(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Json(body): Json<AddTagsBody>,
+) -> Response {
+    add_tags(&state, MediaKind::Video, id, body).await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/video/{id}/tags/{tag}",
+    params(
+        ("id" = i64, Path, description = "Video id"),
+        ("tag" = String, Path, description = "Tag name or alias"),
+    ),
+    responses(
+        (status = 204, description = "Tag removed and blocked from being re-added by models"),
+        (status = 404, description = "Video or tag not found", body = String),
+    )
+)]
+async fn remove_video_tag(
+    State(state): State<Arc<AppState>>,
+    Path((id, tag)): Path<(i64, String)>,
+) -> Response {
+    remove_tag(&state, MediaKind::Video, id, &tag).await
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ArtistsQuery {
+    /// Maximum number of artists to return, defaults to 100
+    limit: Option<i64>,
+    /// Artists to skip, for paging
+    offset: Option<i64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/music/artists",
+    params(ArtistsQuery),
+    responses(
+        (status = 200, description = "Artists by name with their album and track counts", body = String),
+    )
+)]
+async fn list_artists(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ArtistsQuery>,
+) -> Response {
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+    let offset = query.offset.unwrap_or(0).max(0);
+    match state.db.list_artists(limit, offset).await {
+        Ok(artists) => Json(artists).into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[derive(Serialize)]
+struct ArtistDetail {
+    #[serde(flatten)]
+    artist: ArtistRow,
+    /// Albums the artist is the album artist of
+    albums: Vec<AlbumRow>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/music/artists/{id}",
+    params(
+        ("id" = i64, Path, description = "Artist id"),
+    ),
+    responses(
+        (status = 200, description = "The artist with their albums", body = String),
+        (status = 404, description = "Artist not found", body = String),
+    )
+)]
+async fn get_artist(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> Response {
+    let artist = match state.db.get_artist(id).await {
+        Ok(Some(artist)) => artist,
+        Ok(None) => return (StatusCode::NOT_FOUND, "artist not found").into_response(),
+        Err(err) => return database_error(err),
+    };
+    match state.db.list_artist_albums(id).await {
+        Ok(albums) => Json(ArtistDetail { artist, albums }).into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[derive(Serialize)]
+struct AlbumDetail {
+    #[serde(flatten)]
+    album: AlbumRow,
+    tracks: Vec<TrackRow>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/music/albums/{id}",
+    params(
+        ("id" = i64, Path, description = "Album id"),
+    ),
+    responses(
+        (status = 200, description = "The album with its tracks in disc and track order", body = String),
+        (status = 404, description = "Album not found", body = String),
+    )
+)]
+async fn get_album(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> Response {
+    let album = match state.db.get_album(id).await {
+        Ok(Some(album)) => album,
+        Ok(None) => return (StatusCode::NOT_FOUND, "album not found").into_response(),
+        Err(err) => return database_error(err),
+    };
+    match state.db.list_album_tracks(id).await {
+        Ok(tracks) => Json(AlbumDetail { album, tracks }).into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/music/covers/{id}",
+    params(
+        ("id" = i64, Path, description = "Cover id, the `cover_id` of an album or track"),
+    ),
+    responses(
+        (status = 200, description = "The cover picture", content_type = "image/*"),
+        (status = 404, description = "Cover not found", body = String),
+    )
+)]
+async fn get_cover(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> Response {
+    match state.db.get_cover(id).await {
+        Ok(Some((mime, data))) => ([(header::CONTENT_TYPE, mime)], data).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "cover not found").into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/music/{id}",
+    params(
+        ("id" = i64, Path, description = "Track id"),
+    ),
+    responses(
+        (status = 200, description = "The track with its artist and album", body = String),
+        (status = 404, description = "Track not found", body = String),
+    )
+)]
+async fn get_track(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> Response {
+    match state.db.get_track(id).await {
+        Ok(Some(track)) => Json(track).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "track not found").into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct StreamQuery {
+    /// `mp3`, `aac` or `opus` to transcode, the stored file when missing
+    #[param(value_type = Option<String>)]
+    format: Option<AudioFormat>,
+    /// Transcode bitrate in kbps, 32 to 320, defaults to 128
+    bitrate: Option<u32>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/music/{id}/stream",
+    params(
+        ("id" = i64, Path, description = "Track id"),
+        StreamQuery,
+    ),
+    responses(
+        (status = 200, description = "The audio; the stored file also answers range requests", content_type = "audio/*"),
+        (status = 400, description = "Bitrate out of range", body = String),
+        (status = 404, description = "Track not found", body = String),
+        (status = 503, description = "Too many transcodes are running, retry later", body = String),
+    )
+)]
+async fn stream_track(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Query(query): Query<StreamQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let track = match state.db.get_track(id).await {
+        Ok(Some(track)) => track,
+        Ok(None) => return (StatusCode::NOT_FOUND, "track not found").into_response(),
+        Err(err) => return database_error(err),
+    };
+    let bit_rate = query.bitrate.unwrap_or(128);
+    if !(32..=320).contains(&bit_rate) {
+        return (StatusCode::BAD_REQUEST, "bitrate must be 32 to 320 kbps").into_response();
+    }
+    match query.format {
+        Some(format) => transcode_track(&state, &track, format, bit_rate, &headers).await,
+        None => {
+            let path = track.path();
+            streaming::serve_file(path, streaming::content_type(path), &headers).await
+        }
+    }
+}
+
+/// Streams a track transcoded on the fly, or a cached transcode with range support.
+pub(crate) async fn transcode_track(
+    state: &AppState,
+    track: &TrackRow,
+    format: AudioFormat,
+    bit_rate: u32,
+    headers: &HeaderMap,
+) -> Response {
+    match state
+        .transcoder
+        .transcode(track.path(), track.hash(), format, bit_rate)
+        .await
+    {
+        Ok(Transcode::Cached(path)) => {
+            streaming::serve_file(&path.to_string_lossy(), format.content_type(), headers).await
+        }
+        Ok(Transcode::Stream(body)) => {
+            ([(header::CONTENT_TYPE, format.content_type())], body).into_response()
+        }
+        Err(transcode::Error::Busy) => busy(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/music/{id}/tags",
+    params(
+        ("id" = i64, Path, description = "Track id"),
+    ),
+    responses(
+        (status = 200, description = "Tags with score and source, grouped by category", body = String),
+        (status = 404, description = "Track not found", body = String),
+    )
+)]
+async fn list_track_tags(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> Response {
+    list_tags(&state, MediaKind::Music, id).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/music/{id}/tags",
+    params(
+        ("id" = i64, Path, description = "Track id"),
+    ),
+    request_body(content = AddTagsBody),
+    responses(
+        (status = 200, description = "Tags after the addition", body = String),
+        (status = 404, description = "Track not found", body = String),
+    )
+)]
+async fn add_track_tags(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Json(body): Json<AddTagsBody>,
+) -> Response {
+    add_tags(&state, MediaKind::Music, id, body).await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/music/{id}/tags/{tag}",
+    params(
+        ("id" = i64, Path, description = "Track id"),
+        ("tag" = String, Path, description = "Tag name or alias"),
+    ),
+    responses(
+        (status = 204, description = "Tag removed and blocked from being re-added by models"),
+        (status = 404, description = "Track or tag not found", body = String),
+    )
+)]
+async fn remove_track_tag(
+    State(state): State<Arc<AppState>>,
+    Path((id, tag)): Path<(i64, String)>,
+) -> Response {
+    remove_tag(&state, MediaKind::Music, id, &tag).await
+}
+
+async fn list_tags(state: &AppState, kind: MediaKind, id: i64) -> Response {
+    match state.db.media_exists(kind, id).await {
+        Ok(true) => {}
+        Ok(false) => return StatusCode::NOT_FOUND.into_response(),
+        Err(err) => return database_error(err),
+    }
+    match state.db.list_tags(kind, id).await {
+        Ok(tags) => Json(MediaTagRow::group_by_category(tags)).into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+async fn add_tags(state: &AppState, kind: MediaKind, id: i64, body: AddTagsBody) -> Response {
+    match state.db.media_exists(kind, id).await {
+        Ok(true) => {}
+        Ok(false) => return StatusCode::NOT_FOUND.into_response(),
+        Err(err) => return database_error(err),
+    }
+    let mut tags = Vec::with_capacity(body.tags.len());
+    for name in body.tags.iter().filter(|name| !name.trim().is_empty()) {
+        match state.db.ensure_tag(name).await {
+            Ok(tag) => tags.push((tag.tag_id(), 1.0)),
+            Err(err) => return database_error(err),
+        }
+    }
+    if let Err(err) = state.db.add_tags(kind, id, &tags, &TagSource::User).await {
+        return database_error(err);
+    }
+    list_tags(state, kind, id).await
+}
+
+async fn remove_tag(state: &AppState, kind: MediaKind, id: i64, name: &str) -> Response {
+    match state.db.media_exists(kind, id).await {
+        Ok(true) => {}
+        Ok(false) => return StatusCode::NOT_FOUND.into_response(),
+        Err(err) => return database_error(err),
+    }
+    let tag = match state.db.resolve_tag(name).await {
+        Ok(Some(tag)) => tag,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(err) => return database_error(err),
+    };
+    match state.db.remove_tag(kind, id, tag.tag_id()).await {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/inference-cache",
+    responses(
+        (status = 200, description = "Cached entries, size and hit rate since startup per model", body = String),
+    ),
+    security(("api_key" = []))
+)]
+async fn inference_cache_report(State(state): State<Arc<AppState>>) -> Response {
+    match state.inference_cache.report().await {
+        Ok(report) => Json(report).into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct InvalidateCacheQuery {
+    /// Model and version like `deepdanbooru@v3`, every model when missing
+    model: Option<String>,
+}
+
+#[derive(Serialize)]
+struct InvalidatedCache {
+    removed: u64,
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/inference-cache",
+    params(InvalidateCacheQuery),
+    responses(
+        (status = 200, description = "Number of cached entries removed", body = String),
+    ),
+    security(("api_key" = []))
+)]
+async fn invalidate_inference_cache(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<InvalidateCacheQuery>,
+) -> Response {
+    match state
+        .inference_cache
+        .invalidate(query.model.as_deref())
+        .await
+    {
+        Ok(removed) => Json(InvalidatedCache { removed }).into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct JobsQuery {
+    /// Maximum number of jobs to return, defaults to 50
+    limit: Option<i64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/jobs",
+    params(JobsQuery),
+    responses(
+        (status = 200, description = "Background jobs with their progress, newest first", body = String),
+    ),
+    security(("api_key" = []))
+)]
+async fn list_jobs(State(state): State<Arc<AppState>>, Query(query): Query<JobsQuery>) -> Response {
+    let limit = query.limit.unwrap_or(50).clamp(1, 500);
+    match state.db.list_jobs(limit).await {
+        Ok(jobs) => Json(jobs).into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/jobs/{id}",
+    params(
+        ("id" = i64, Path, description = "Job id"),
+    ),
+    responses(
+        (status = 200, description = "Job status and progress", body = String),
+        (status = 404, description = "Job not found", body = String),
+    ),
+    security(("api_key" = []))
+)]
+async fn get_job(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> Response {
+    match state.db.get_job(id).await {
+        Ok(Some(job)) => Json(job).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[derive(ToSchema, Deserialize)]
+struct RetagBody {
+    /// Tagger name, defaults to the primary tagger
+    model: Option<String>,
+    /// `image` and/or `video`, defaults to both
+    #[schema(value_type = Option<Vec<String>>)]
+    kinds: Option<Vec<MediaKind>>,
+    /// Skip items already tagged by the tagger's current version, defaults to false
+    only_stale: Option<bool>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/jobs/retag",
+    request_body(content = RetagBody),
+    responses(
+        (status = 202, description = "Re-tagging started", body = String),
+        (status = 400, description = "Only the primary tagger can re-tag videos", body = String),
+        (status = 404, description = "Tagger not found", body = String),
+    ),
+    security(("api_key" = []))
+)]
+async fn start_retag(State(state): State<Arc<AppState>>, Json(body): Json<RetagBody>) -> Response {
+    let mut params = RetagParams {
+        model: body.model,
+        only_stale: body.only_stale.unwrap_or(false),
+        ..RetagParams::default()
+    };
+    if let Some(kinds) = body.kinds {
+        params.kinds = kinds;
+    }
+    match state.retagger.start(params).await {
+        Ok(job) => (StatusCode::ACCEPTED, Json(job)).into_response(),
+        Err(retag::Error::Database(err)) => database_error(err),
+        Err(err @ retag::Error::UnknownModel(_)) => {
+            (StatusCode::NOT_FOUND, err.to_string()).into_response()
+        }
+        Err(
+            err @ (retag::Error::VideoNeedsPrimary
+            | retag::Error::MusicNotTaggable
+            | retag::Error::ChapterNotTaggable),
+        ) => (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/jobs/{id}/cancel",
+    params(
+        ("id" = i64, Path, description = "Job id"),
+    ),
+    responses(
+        (status = 202, description = "Job stops after its current batch", body = String),
+        (status = 404, description = "Job not found", body = String),
+        (status = 409, description = "Job already finished", body = String),
+    ),
+    security(("api_key" = []))
+)]
+async fn cancel_job(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> Response {
+    // A running job missing from this process was orphaned, so it is marked cancelled here.
+    let cancelled = state.retagger.jobs().cancel(id)
+        || match state.db.finish_job(id, JobStatus::Cancelled, None).await {
+            Ok(cancelled) => cancelled,
+            Err(err) => return database_error(err),
+        };
+    match state.db.get_job(id).await {
+        Ok(Some(job)) if cancelled => (StatusCode::ACCEPTED, Json(job)).into_response(),
+        Ok(Some(_)) => (StatusCode::CONFLICT, "job already finished").into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(err) => database_error(err),
+    }
+}
+
+fn database_error(err: database::Error) -> Response {
+    let status = match err {
+        database::Error::UnknownTag(_) => StatusCode::NOT_FOUND,
+        database::Error::AliasIsTag(_) | database::Error::ImplicationCycle(..) => {
+            StatusCode::CONFLICT
+        }
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, err.to_string()).into_response()
+}
+
 #[utoipa::path(
     get,
     path = "/",
@@ -358,8 +2795,73 @@ async fn root() -> Response {
         upload_music_file,
         upload_music_url,
         image_thumbnail,
+        autocomplete_tags,
+        search_images,
+        search_by_image,
+        get_image,
+        search_videos,
+        get_video,
+        list_video_renditions,
+        queue_video_renditions,
+        video_rendition_file,
+        list_video_subtitles,
+        video_subtitle_file,
+        list_video_chapters,
+        chapter_thumbnail,
+        chapter_clip,
+        list_chapter_tags,
+        add_chapter_tags,
+        remove_chapter_tag,
+        search_subtitles,
+        hls_master_playlist,
+        hls_file,
+        list_tag_aliases,
+        add_tag_alias,
+        remove_tag_alias,
+        list_tag_implications,
+        add_tag_implication,
+        remove_tag_implication,
+        list_taggers,
+        list_video_profiles,
+        list_download_profiles,
+        get_tagger_config,
+        put_tagger_config,
+        put_tagger_override,
+        delete_tagger_override,
+        list_image_tags,
+        add_image_tags,
+        remove_image_tag,
+        list_image_model_tags,
+        related_images,
+        list_video_tags,
+        add_video_tags,
+        remove_video_tag,
+        list_artists,
+        get_artist,
+        get_album,
+        get_cover,
+        get_track,
+        stream_track,
+        list_track_tags,
+        add_track_tags,
+        remove_track_tag,
+        inference_cache_report,
+        invalidate_inference_cache,
+        list_jobs,
+        get_job,
+        start_retag,
+        cancel_job,
     ),
-    components(schemas(UploadFileBody, UploadUrlBody)),
+    components(schemas(
+        UploadFileBody,
+        UploadUrlBody,
+        TagAliasBody,
+        TagImplicationBody,
+        TaggerOverrideBody,
+        AddTagsBody,
+        QueueRenditionsBody,
+        RetagBody
+    )),
     modifiers(&SecurityAddon),
 )]
 struct ApiDoc;
@@ -0,0 +1,143 @@
+use std::path::Path;
+
+use log::warn;
+use serde::Deserialize;
+
+use crate::ffmpeg::{self, Probe};
+
+/// Chapter thumbnails are scaled down to this height.
+const THUMBNAIL_HEIGHT: u32 = 360;
+/// How far into a chapter its thumbnail is taken, past title cards and fades.
+const THUMBNAIL_OFFSET: f64 = 5.0;
+/// How many videos have their chapter thumbnails taken at once.
+pub const THUMBNAIL_JOBS: usize = 2;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Chapter {
+    #[serde(default)]
+    pub title: Option<String>,
+    /// Seconds.
+    #[serde(rename = "start_time")]
+    pub start: f64,
+    #[serde(rename = "end_time")]
+    pub end: f64,
+}
+
+impl Chapter {
+    /// When in the video the chapter's thumbnail is taken.
+    pub fn thumbnail_time(&self) -> f64 {
+        self.start + THUMBNAIL_OFFSET.min((self.end - self.start) / 2.0)
+    }
+}
+
+/// The part of yt-dlp's info JSON about chapters.
+#[derive(Deserialize)]
+struct InfoJson {
+    #[serde(default)]
+    chapters: Option<Vec<Chapter>>,
+}
+
+/// The chapters of the video at `path`, from the info JSON yt-dlp writes next to it or else
+/// from the file itself, in order and without empty ones.
+pub async fn collect(path: &str, probe: &Probe) -> Vec<Chapter> {
+    let path = Path::new(path);
+    let info_json = path.with_extension("info.json");
+    let mut chapters = match tokio::fs::read(&info_json).await {
+        Ok(json) => from_info_json(&json).unwrap_or_else(|err| {
+            warn!(
+                "failed to read chapters of {}: {}",
+                info_json.display(),
+                err
+            );
+            Vec::new()
+        }),
+        Err(_) => Vec::new(),
+    };
+    if chapters.is_empty() {
+        chapters = probe
+            .chapters
+            .iter()
+            .map(|chapter| Chapter {
+                title: chapter.title.clone(),
+                start: chapter.start,
+                end: chapter.end,
+            })
+            .collect();
+    }
+    tidy(chapters, probe.duration)
+}
+
+fn from_info_json(json: &[u8]) -> serde_json::Result<Vec<Chapter>> {
+    let info: InfoJson = serde_json::from_slice(json)?;
+    Ok(info.chapters.unwrap_or_default())
+}
+
+/// Sorts chapters, ends them by the end of the video, drops empty ones and blank titles.
+fn tidy(mut chapters: Vec<Chapter>, duration: Option<f64>) -> Vec<Chapter> {
+    for chapter in &mut chapters {
+        if let Some(duration) = duration {
+            chapter.end = chapter.end.min(duration);
+        }
+        chapter.title = chapter
+            .title
+            .take()
+            .map(|title| title.trim().to_string())
+            .filter(|title| !title.is_empty());
+    }
+    chapters.retain(|chapter| chapter.start >= 0.0 && chapter.end > chapter.start);
+    chapters.sort_by(|a, b| a.start.total_cmp(&b.start));
+    chapters
+}
+
+/// A JPEG of the chapter, `None` when there is no picture to take.
+pub async fn thumbnail(path: &str, chapter: &Chapter) -> Option<Vec<u8>> {
+    match ffmpeg::extract_thumbnail(path, chapter.thumbnail_time(), THUMBNAIL_HEIGHT).await {
+        Ok(thumbnail) => Some(thumbnail),
+        Err(err) => {
+            warn!(
+                "failed to take a thumbnail at {:.1}s of {}: {}",
+                chapter.thumbnail_time(),
+                path,
+                err
+            );
+            None
+        }
+    }
+}
+
+#[test]
+fn info_json_chapters() {
+    let json = br#"{"id": "abc", "duration": 300, "chapters": [
+        {"start_time": 120.0, "title": "Part two", "end_time": 320.0},
+        {"start_time": 0.0, "title": " Intro ", "end_time": 2.0},
+        {"start_time": 2.0, "title": "", "end_time": 120.0},
+        {"start_time": 300.0, "title": "After the end", "end_time": 310.0}
+    ]}"#;
+    let chapters = tidy(from_info_json(json).unwrap(), Some(300.0));
+    assert_eq!(
+        chapters,
+        vec![
+            Chapter {
+                title: Some("Intro".to_string()),
+                start: 0.0,
+                end: 2.0
+            },
+            Chapter {
+                title: None,
+                start: 2.0,
+                end: 120.0
+            },
+            Chapter {
+                title: Some("Part two".to_string()),
+                start: 120.0,
+                end: 300.0
+            },
+        ]
+    );
+    assert_eq!(chapters[0].thumbnail_time(), 1.0);
+    assert_eq!(chapters[2].thumbnail_time(), 125.0);
+    assert_eq!(
+        from_info_json(br#"{"id": "abc", "chapters": null}"#).unwrap(),
+        Vec::new()
+    );
+}
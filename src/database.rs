@@ -1,6 +1,25 @@
+use std::collections::BTreeMap;
+
 use chrono::NaiveDateTime;
 use serde::Serialize;
-use sqlx::{migrate, sqlite::SqlitePoolOptions, FromRow, QueryBuilder, Sqlite, SqlitePool};
+use sqlx::types::Json;
+use sqlx::{
+    migrate, sqlite::SqlitePoolOptions, FromRow, QueryBuilder, Sqlite, SqliteConnection, SqlitePool,
+};
+
+use crate::chapters::Chapter;
+use crate::ffmpeg::Probe;
+use crate::generation::{GenerationParams, GenerationSource, Lora};
+use crate::jobs::JobStatus;
+use crate::media::{MediaKind, TagCategory, TagSource};
+use crate::metadata::ImageMetadata;
+use crate::music::AudioTags;
+use crate::renditions::{RenditionStatus, ORIGINAL};
+use crate::search::{
+    canonical_tag, split_namespace, Comparison, Filter, FlagField, NumberField, Term, TextField,
+};
+use crate::subtitles::SubtitleSource;
+use crate::tagger::Label;
 
 #[derive(Clone)]
 pub struct Database {
@@ -11,14 +30,87 @@ pub struct Database {
 pub struct ImageRow {
     date_added: NaiveDateTime,
     date_updated: NaiveDateTime,
+    /// Hex [`Fingerprint`](crate::fingerprint::Fingerprint) of the picture.
+    fingerprint: Option<String>,
     hash: String,
     img_id: i64,
     path: String,
     size: i64,
+    tagged_by: Option<String>,
+}
+
+impl TagRow {
+    pub fn tag_id(&self) -> i64 {
+        self.tag_id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[derive(FromRow, Serialize, Debug, Clone)]
+pub struct ModelTagRow {
+    category: TagCategory,
+    #[serde(skip)]
+    model: String,
+    name: String,
+    score: f64,
+    tag_id: i64,
+}
+
+impl ModelTagRow {
+    pub fn group_by_model(tags: Vec<ModelTagRow>) -> BTreeMap<String, Vec<ModelTagRow>> {
+        let mut groups: BTreeMap<String, Vec<ModelTagRow>> = BTreeMap::new();
+        for tag in tags {
+            groups.entry(tag.model.clone()).or_default().push(tag);
+        }
+        groups
+    }
+}
+
+impl MediaTagRow {
+    pub fn group_by_category(tags: Vec<MediaTagRow>) -> BTreeMap<TagCategory, Vec<MediaTagRow>> {
+        let mut groups: BTreeMap<TagCategory, Vec<MediaTagRow>> = BTreeMap::new();
+        for tag in tags {
+            groups.entry(tag.category).or_default().push(tag);
+        }
+        groups
+    }
+}
+
+impl ImageRow {
+    pub fn img_id(&self) -> i64 {
+        self.img_id
+    }
 }
 
 #[derive(FromRow, Serialize, Debug, Clone)]
 pub struct TagRow {
+    category: TagCategory,
+    name: String,
+    tag_id: i64,
+}
+
+#[derive(FromRow, Serialize, Debug, Clone)]
+pub struct TagUsageRow {
+    category: TagCategory,
+    count: i64,
+    name: String,
+    tag_id: i64,
+}
+
+#[derive(FromRow, Serialize, Debug, Clone)]
+pub struct TagAliasRow {
+    alias: String,
+    name: String,
+    tag_id: i64,
+}
+
+#[derive(FromRow, Serialize, Debug, Clone)]
+pub struct TagImplicationRow {
+    implied_name: String,
+    implied_tag_id: i64,
     name: String,
     tag_id: i64,
 }
@@ -27,19 +119,516 @@ pub struct TagRow {
 pub struct ImageTagRow {
     image_id: i64,
     score: f64,
+    source: String,
+    tag_id: i64,
+}
+
+#[derive(FromRow, Serialize, Debug, Clone)]
+pub struct MediaTagRow {
+    category: TagCategory,
+    name: String,
+    score: f64,
+    source: String,
     tag_id: i64,
 }
 
 #[derive(FromRow, Serialize, Debug, Clone)]
 pub struct VideoRow {
+    bit_rate: Option<i64>,
+    container: Option<String>,
     date_added: NaiveDateTime,
     date_updated: NaiveDateTime,
+    duration: Option<f64>,
     hash: String,
     path: String,
     size: i64,
+    tagged_by: Option<String>,
+    video_id: i64,
+}
+
+impl VideoRow {
+    pub fn video_id(&self) -> i64 {
+        self.video_id
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+/// See [`ProbeStream`](crate::ffmpeg::ProbeStream).
+#[derive(FromRow, Serialize, Debug, Clone)]
+pub struct VideoStreamRow {
+    bit_rate: Option<i64>,
+    channel_layout: Option<String>,
+    channels: Option<i64>,
+    codec_name: Option<String>,
+    codec_type: String,
+    duration: Option<f64>,
+    frame_rate: Option<f64>,
+    height: Option<i64>,
+    language: Option<String>,
+    profile: Option<String>,
+    sample_rate: Option<i64>,
+    stream_index: i64,
+    width: Option<i64>,
+}
+
+/// A video's container details with its first video and audio stream, from the
+/// `video_summary` view.
+#[derive(FromRow, Serialize, Debug, Clone)]
+pub struct VideoSummaryRow {
+    audio_channels: Option<i64>,
+    audio_codec: Option<String>,
+    audio_sample_rate: Option<i64>,
+    bit_rate: Option<i64>,
+    container: Option<String>,
+    duration: Option<f64>,
+    frame_rate: Option<f64>,
+    height: Option<i64>,
+    video_codec: Option<String>,
+    width: Option<i64>,
+}
+
+impl VideoSummaryRow {
+    pub fn video_codec(&self) -> Option<&str> {
+        self.video_codec.as_deref()
+    }
+
+    pub fn audio_codec(&self) -> Option<&str> {
+        self.audio_codec.as_deref()
+    }
+
+    pub fn width(&self) -> Option<i64> {
+        self.width
+    }
+
+    pub fn height(&self) -> Option<i64> {
+        self.height
+    }
+}
+
+/// A file of a video, see [`RenditionWorker`](crate::renditions::RenditionWorker).
+#[derive(FromRow, Serialize, Debug, Clone)]
+pub struct RenditionRow {
+    audio_codec: Option<String>,
+    bit_rate: Option<i64>,
+    container: Option<String>,
+    date_added: NaiveDateTime,
+    date_updated: NaiveDateTime,
+    duration: Option<f64>,
+    error: Option<String>,
+    height: Option<i64>,
+    path: Option<String>,
+    profile: String,
+    rendition_id: i64,
+    size: Option<i64>,
+    status: RenditionStatus,
+    video_codec: Option<String>,
+    video_id: i64,
+    width: Option<i64>,
+}
+
+impl RenditionRow {
+    pub fn rendition_id(&self) -> i64 {
+        self.rendition_id
+    }
+
+    pub fn video_id(&self) -> i64 {
+        self.video_id
+    }
+
+    pub fn profile(&self) -> &str {
+        &self.profile
+    }
+
+    /// Set once the rendition is made.
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+}
+
+/// A chapter of a video with the names of its tags.
+#[derive(FromRow, Serialize, Debug, Clone)]
+pub struct ChapterRow {
+    chapter_id: i64,
+    chapter_index: i64,
+    date_added: NaiveDateTime,
+    end_time: f64,
+    start_time: f64,
+    tags: Json<Vec<String>>,
+    /// Whether there is a thumbnail.
+    thumbnail: bool,
+    title: Option<String>,
+    video_id: i64,
+}
+
+impl ChapterRow {
+    pub fn chapter_id(&self) -> i64 {
+        self.chapter_id
+    }
+
+    pub fn chapter_index(&self) -> i64 {
+        self.chapter_index
+    }
+
+    /// Seconds.
+    pub fn start_time(&self) -> f64 {
+        self.start_time
+    }
+
+    pub fn end_time(&self) -> f64 {
+        self.end_time
+    }
+}
+
+/// A subtitle of a video, without its text.
+#[derive(FromRow, Serialize, Debug, Clone)]
+pub struct SubtitleRow {
+    date_added: NaiveDateTime,
+    language: String,
+    source: SubtitleSource,
+    subtitle_id: i64,
     video_id: i64,
 }
 
+/// Codecs and size of the first video and audio stream.
+struct StreamSummary<'a> {
+    video_codec: Option<&'a str>,
+    audio_codec: Option<&'a str>,
+    width: Option<i64>,
+    height: Option<i64>,
+}
+
+impl<'a> StreamSummary<'a> {
+    fn of(probe: &'a Probe) -> Self {
+        let video = probe
+            .streams
+            .iter()
+            .find(|stream| stream.codec_type == "video");
+        let audio = probe
+            .streams
+            .iter()
+            .find(|stream| stream.codec_type == "audio");
+        Self {
+            video_codec: video.and_then(|stream| stream.codec_name.as_deref()),
+            audio_codec: audio.and_then(|stream| stream.codec_name.as_deref()),
+            width: video.and_then(|stream| stream.width),
+            height: video.and_then(|stream| stream.height),
+        }
+    }
+}
+
+#[derive(FromRow, Serialize, Debug, Clone)]
+pub struct ArtistRow {
+    album_count: i64,
+    artist_id: i64,
+    name: String,
+    track_count: i64,
+}
+
+impl ArtistRow {
+    pub fn artist_id(&self) -> i64 {
+        self.artist_id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn album_count(&self) -> i64 {
+        self.album_count
+    }
+}
+
+#[derive(FromRow, Serialize, Debug, Clone)]
+pub struct AlbumRow {
+    album_id: i64,
+    artist: Option<String>,
+    artist_id: Option<i64>,
+    cover_id: Option<i64>,
+    date_added: NaiveDateTime,
+    /// Seconds over all tracks.
+    duration: Option<f64>,
+    genre: Option<String>,
+    title: String,
+    track_count: i64,
+    year: Option<i64>,
+}
+
+impl AlbumRow {
+    pub fn album_id(&self) -> i64 {
+        self.album_id
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn artist(&self) -> Option<&str> {
+        self.artist.as_deref()
+    }
+
+    pub fn artist_id(&self) -> Option<i64> {
+        self.artist_id
+    }
+
+    pub fn cover_id(&self) -> Option<i64> {
+        self.cover_id
+    }
+
+    pub fn date_added(&self) -> NaiveDateTime {
+        self.date_added
+    }
+
+    pub fn duration(&self) -> Option<f64> {
+        self.duration
+    }
+
+    pub fn genre(&self) -> Option<&str> {
+        self.genre.as_deref()
+    }
+
+    pub fn track_count(&self) -> i64 {
+        self.track_count
+    }
+
+    pub fn year(&self) -> Option<i64> {
+        self.year
+    }
+}
+
+#[derive(FromRow, Serialize, Debug, Clone)]
+pub struct TrackRow {
+    album: Option<String>,
+    album_id: Option<i64>,
+    artist: Option<String>,
+    artist_id: Option<i64>,
+    bit_rate: Option<i64>,
+    codec: Option<String>,
+    container: Option<String>,
+    cover_id: Option<i64>,
+    date_added: NaiveDateTime,
+    date_updated: NaiveDateTime,
+    disc_number: Option<i64>,
+    duration: Option<f64>,
+    genre: Option<String>,
+    hash: String,
+    path: String,
+    size: i64,
+    tagged_by: Option<String>,
+    title: String,
+    track_id: i64,
+    track_number: Option<i64>,
+    year: Option<i64>,
+}
+
+impl TrackRow {
+    pub fn track_id(&self) -> i64 {
+        self.track_id
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn album(&self) -> Option<&str> {
+        self.album.as_deref()
+    }
+
+    pub fn album_id(&self) -> Option<i64> {
+        self.album_id
+    }
+
+    pub fn artist(&self) -> Option<&str> {
+        self.artist.as_deref()
+    }
+
+    pub fn artist_id(&self) -> Option<i64> {
+        self.artist_id
+    }
+
+    pub fn bit_rate(&self) -> Option<i64> {
+        self.bit_rate
+    }
+
+    pub fn cover_id(&self) -> Option<i64> {
+        self.cover_id
+    }
+
+    pub fn date_added(&self) -> NaiveDateTime {
+        self.date_added
+    }
+
+    pub fn disc_number(&self) -> Option<i64> {
+        self.disc_number
+    }
+
+    pub fn duration(&self) -> Option<f64> {
+        self.duration
+    }
+
+    pub fn genre(&self) -> Option<&str> {
+        self.genre.as_deref()
+    }
+
+    pub fn size(&self) -> i64 {
+        self.size
+    }
+
+    pub fn track_number(&self) -> Option<i64> {
+        self.track_number
+    }
+
+    pub fn year(&self) -> Option<i64> {
+        self.year
+    }
+}
+
+/// An embedded cover picture, see [`Database::save_track`].
+pub struct Cover<'a> {
+    /// See [`content_hash`](crate::inference_cache::content_hash).
+    pub hash: &'a str,
+    pub mime: &'a str,
+    pub data: &'a [u8],
+}
+
+/// Selects [`TrackRow`]s, to be followed by a `WHERE` clause.
+const TRACK_SELECT: &str = r#"
+    SELECT track.*, artist.name AS artist, album.title AS album FROM track
+    LEFT JOIN artist ON artist.artist_id = track.artist_id
+    LEFT JOIN album ON album.album_id = track.album_id
+"#;
+
+/// Selects [`ChapterRow`]s, to be followed by a `WHERE` clause.
+const CHAPTER_SELECT: &str = r#"
+    SELECT chapter_id, video_id, chapter_index, title, start_time, end_time,
+        thumbnail IS NOT NULL AS thumbnail, date_added,
+        (
+            SELECT json_group_array(name) FROM (
+                SELECT tag.name FROM video_chapter_tag
+                JOIN tag ON tag.tag_id = video_chapter_tag.tag_id
+                WHERE video_chapter_tag.chapter_id = video_chapter.chapter_id
+                ORDER BY tag.name
+            )
+        ) AS tags
+    FROM video_chapter
+"#;
+
+/// Selects [`AlbumRow`]s, to be followed by a `WHERE` clause and `GROUP BY album.album_id`.
+const ALBUM_SELECT: &str = r#"
+    SELECT album.album_id, album.title, album.artist_id, artist.name AS artist, album.year,
+        album.genre, album.cover_id, album.date_added, COUNT(track.track_id) AS track_count,
+        SUM(track.duration) AS duration
+    FROM album
+    LEFT JOIN artist ON artist.artist_id = album.artist_id
+    LEFT JOIN track ON track.album_id = album.album_id
+"#;
+
+#[derive(FromRow, Serialize, Debug, Clone)]
+pub struct JobRow {
+    cursor: i64,
+    date_added: NaiveDateTime,
+    date_updated: NaiveDateTime,
+    error: Option<String>,
+    failed: i64,
+    job_id: i64,
+    kind: String,
+    params: Json<serde_json::Value>,
+    processed: i64,
+    stage: String,
+    status: JobStatus,
+    total: i64,
+}
+
+impl JobRow {
+    pub fn job_id(&self) -> i64 {
+        self.job_id
+    }
+
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    pub fn params(&self) -> &serde_json::Value {
+        &self.params
+    }
+
+    pub fn status(&self) -> JobStatus {
+        self.status
+    }
+
+    /// Where a resumed job picks up: the stage it was in and the last id it finished.
+    pub fn position(&self) -> (&str, i64) {
+        (&self.stage, self.cursor)
+    }
+}
+
+/// See [`GenerationParams`].
+#[derive(FromRow, Serialize, Debug, Clone)]
+pub struct GenerationParamsRow {
+    cfg_scale: Option<f64>,
+    loras: Json<Vec<Lora>>,
+    model: Option<String>,
+    model_hash: Option<String>,
+    negative_prompt: Option<String>,
+    prompt: Option<String>,
+    sampler: Option<String>,
+    seed: Option<i64>,
+    source: GenerationSource,
+    steps: Option<i64>,
+}
+
+/// See [`ImageMetadata`].
+#[derive(FromRow, Serialize, Debug, Clone)]
+pub struct ImageMetadataRow {
+    animated: bool,
+    bit_depth: i64,
+    camera_make: Option<String>,
+    camera_model: Option<String>,
+    color_space: Option<String>,
+    color_type: String,
+    date_taken: Option<NaiveDateTime>,
+    exif: Json<BTreeMap<String, String>>,
+    format: String,
+    frame_count: i64,
+    gps_latitude: Option<f64>,
+    gps_longitude: Option<f64>,
+    has_alpha: bool,
+    height: i64,
+    lens_model: Option<String>,
+    width: i64,
+}
+
+#[derive(FromRow, Serialize, Debug, Clone)]
+pub struct CachedScoresRow {
+    bytes: i64,
+    entries: i64,
+    model: String,
+}
+
+impl CachedScoresRow {
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    pub fn entries(&self) -> i64 {
+        self.entries
+    }
+
+    pub fn bytes(&self) -> i64 {
+        self.bytes
+    }
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error(transparent)]
@@ -48,6 +637,14 @@ pub enum Error {
     Io(#[from] dotenv::Error),
     #[error(transparent)]
     Migrate(#[from] migrate::MigrateError),
+    #[error("unknown tag {0}")]
+    UnknownTag(String),
+    #[error("{0} is already a tag")]
+    AliasIsTag(String),
+    #[error("{0} already implies {1}")]
+    ImplicationCycle(String, String),
+    #[error("labels of {0} changed since they were synced: {1}")]
+    LabelMismatch(String, String),
 }
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -59,18 +656,25 @@ impl Database {
         Ok(Self { pool })
     }
 
-    pub async fn save_image(&self, path: &str, hash: &str, size: i64) -> Result<ImageRow> {
+    pub async fn save_image(
+        &self,
+        path: &str,
+        hash: &str,
+        fingerprint: &str,
+        size: i64,
+    ) -> Result<ImageRow> {
         let img = sqlx::query_as!(
             ImageRow,
             r#"
                 INSERT INTO image
-                (path, hash, size)
+                (path, hash, fingerprint, size)
                 VALUES
-                (?1, ?2, ?3)
+                (?1, ?2, ?3, ?4)
                 RETURNING *
             "#,
             path,
             hash,
+            fingerprint,
             size
         )
         .fetch_one(&self.pool)
@@ -78,36 +682,2024 @@ impl Database {
         Ok(img)
     }
 
-    pub async fn get_tag_names(&self, tag_ids: &[i32]) -> Result<Vec<(i32, String)>> {
-        let mut query_builder =
-            QueryBuilder::<Sqlite>::new("SELECT tag_id, name FROM tag WHERE tag_id IN (");
-        let mut separated = query_builder.separated(",");
-        for tag_id in tag_ids {
-            separated.push_bind(tag_id);
-        }
-        separated.push_unseparated(")");
-        let query = query_builder
-            .build_query_as::<(i32, String)>()
-            .fetch_all(&self.pool)
+    /// Replaces the content hash of an image, see
+    /// [`content_hash`](crate::inference_cache::content_hash).
+    pub async fn set_image_hash(&self, image_id: i64, hash: &str) -> Result<()> {
+        sqlx::query("UPDATE image SET hash = ?2 WHERE img_id = ?1")
+            .bind(image_id)
+            .bind(hash)
+            .execute(&self.pool)
             .await?;
-        Ok(query)
+        Ok(())
     }
-}
 
-#[test]
-fn where_in() {
-    use sqlx::Execute;
-    let mut query_builder: QueryBuilder<Sqlite> =
-        QueryBuilder::new("SELECT * FROM population WHERE year IN (");
-    let years = vec![2019, 2020, 2021];
-    let mut separated = query_builder.separated(",");
-    for year in years {
-        separated.push_bind(year);
+    pub async fn set_image_fingerprint(&self, image_id: i64, fingerprint: &str) -> Result<()> {
+        sqlx::query("UPDATE image SET fingerprint = ?2 WHERE img_id = ?1")
+            .bind(image_id)
+            .bind(fingerprint)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
     }
-    separated.push_unseparated(")");
-    let mut query = query_builder.build();
-    let arguments = query.take_arguments().unwrap();
-    dbg!(&arguments);
-    let sql = query.sql();
-    assert_eq!(sql, "SELECT * FROM population WHERE year IN (?,?,?)");
+
+    pub async fn get_image(&self, image_id: i64) -> Result<Option<ImageRow>> {
+        let image = sqlx::query_as::<_, ImageRow>("SELECT * FROM image WHERE img_id = ?1")
+            .bind(image_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(image)
+    }
+
+    /// Adds a video with what ffprobe found in it.
+    pub async fn save_video(
+        &self,
+        path: &str,
+        hash: &str,
+        size: i64,
+        probe: &Probe,
+    ) -> Result<VideoRow> {
+        let mut transaction = self.pool.begin().await?;
+        let video = sqlx::query_as::<_, VideoRow>(
+            r#"
+                INSERT INTO video (path, hash, size, container, duration, bit_rate)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                RETURNING *
+            "#,
+        )
+        .bind(path)
+        .bind(hash)
+        .bind(size)
+        .bind(&probe.container)
+        .bind(probe.duration)
+        .bind(probe.bit_rate)
+        .fetch_one(&mut *transaction)
+        .await?;
+        for stream in &probe.streams {
+            sqlx::query(
+                r#"
+                    INSERT INTO video_stream (
+                        video_id, stream_index, codec_type, codec_name, profile, width, height,
+                        frame_rate, bit_rate, sample_rate, channels, channel_layout, language,
+                        duration
+                    ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)
+                "#,
+            )
+            .bind(video.video_id)
+            .bind(stream.index)
+            .bind(&stream.codec_type)
+            .bind(&stream.codec_name)
+            .bind(&stream.profile)
+            .bind(stream.width)
+            .bind(stream.height)
+            .bind(stream.frame_rate)
+            .bind(stream.bit_rate)
+            .bind(stream.sample_rate)
+            .bind(stream.channels)
+            .bind(&stream.channel_layout)
+            .bind(&stream.language)
+            .bind(stream.duration)
+            .execute(&mut *transaction)
+            .await?;
+        }
+        let summary = StreamSummary::of(probe);
+        sqlx::query(
+            r#"
+                INSERT INTO video_rendition (
+                    video_id, profile, status, path, size, container, video_codec, audio_codec,
+                    width, height, bit_rate, duration
+                ) VALUES (?1, ?2, 'done', ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+            "#,
+        )
+        .bind(video.video_id)
+        .bind(ORIGINAL)
+        .bind(path)
+        .bind(size)
+        .bind(&probe.container)
+        .bind(summary.video_codec)
+        .bind(summary.audio_codec)
+        .bind(summary.width)
+        .bind(summary.height)
+        .bind(probe.bit_rate)
+        .bind(probe.duration)
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(video)
+    }
+
+    /// Queues renditions of a video by profile name. Renditions already queued or made are
+    /// kept; failed ones are queued again.
+    pub async fn queue_renditions(&self, video_id: i64, profiles: &[&str]) -> Result<()> {
+        let mut transaction = self.pool.begin().await?;
+        for profile in profiles {
+            sqlx::query(
+                r#"
+                    INSERT INTO video_rendition (video_id, profile) VALUES (?1, ?2)
+                    ON CONFLICT (video_id, profile) DO UPDATE SET
+                        status = 'pending', error = NULL, date_updated = CURRENT_TIMESTAMP
+                    WHERE status = 'failed'
+                "#,
+            )
+            .bind(video_id)
+            .bind(profile)
+            .execute(&mut *transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// Takes the oldest queued rendition and marks it running.
+    pub async fn next_rendition(&self) -> Result<Option<RenditionRow>> {
+        let rendition = sqlx::query_as::<_, RenditionRow>(
+            r#"
+                UPDATE video_rendition SET status = 'running', date_updated = CURRENT_TIMESTAMP
+                WHERE rendition_id = (
+                    SELECT rendition_id FROM video_rendition
+                    WHERE status = 'pending'
+                    ORDER BY rendition_id
+                    LIMIT 1
+                )
+                RETURNING *
+            "#,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(rendition)
+    }
+
+    /// Queues renditions left running by a previous run again, returning how many.
+    pub async fn requeue_running_renditions(&self) -> Result<u64> {
+        let result = sqlx::query(
+            r#"
+                UPDATE video_rendition SET status = 'pending', date_updated = CURRENT_TIMESTAMP
+                WHERE status = 'running'
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Records a rendition that was made.
+    pub async fn complete_rendition(
+        &self,
+        rendition_id: i64,
+        path: &str,
+        size: i64,
+        probe: &Probe,
+    ) -> Result<()> {
+        let summary = StreamSummary::of(probe);
+        sqlx::query(
+            r#"
+                UPDATE video_rendition SET
+                    status = 'done', path = ?2, size = ?3, container = ?4, video_codec = ?5,
+                    audio_codec = ?6, width = ?7, height = ?8, bit_rate = ?9, duration = ?10,
+                    error = NULL, date_updated = CURRENT_TIMESTAMP
+                WHERE rendition_id = ?1
+            "#,
+        )
+        .bind(rendition_id)
+        .bind(path)
+        .bind(size)
+        .bind(&probe.container)
+        .bind(summary.video_codec)
+        .bind(summary.audio_codec)
+        .bind(summary.width)
+        .bind(summary.height)
+        .bind(probe.bit_rate)
+        .bind(probe.duration)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Records a rendition that was not made, with why when it failed.
+    pub async fn finish_rendition(
+        &self,
+        rendition_id: i64,
+        status: RenditionStatus,
+        error: Option<&str>,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+                UPDATE video_rendition SET
+                    status = ?2, error = ?3, date_updated = CURRENT_TIMESTAMP
+                WHERE rendition_id = ?1
+            "#,
+        )
+        .bind(rendition_id)
+        .bind(status)
+        .bind(error)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// A video's renditions, the original first.
+    pub async fn list_video_renditions(&self, video_id: i64) -> Result<Vec<RenditionRow>> {
+        let renditions = sqlx::query_as::<_, RenditionRow>(
+            r#"
+                SELECT * FROM video_rendition WHERE video_id = ?1
+                ORDER BY profile != ?2, rendition_id
+            "#,
+        )
+        .bind(video_id)
+        .bind(ORIGINAL)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(renditions)
+    }
+
+    pub async fn get_video_rendition(
+        &self,
+        video_id: i64,
+        profile: &str,
+    ) -> Result<Option<RenditionRow>> {
+        let rendition = sqlx::query_as::<_, RenditionRow>(
+            "SELECT * FROM video_rendition WHERE video_id = ?1 AND profile = ?2",
+        )
+        .bind(video_id)
+        .bind(profile)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(rendition)
+    }
+
+    pub async fn get_video(&self, video_id: i64) -> Result<Option<VideoRow>> {
+        let video = sqlx::query_as::<_, VideoRow>("SELECT * FROM video WHERE video_id = ?1")
+            .bind(video_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(video)
+    }
+
+    pub async fn get_video_summary(&self, video_id: i64) -> Result<Option<VideoSummaryRow>> {
+        let summary =
+            sqlx::query_as::<_, VideoSummaryRow>("SELECT * FROM video_summary WHERE video_id = ?1")
+                .bind(video_id)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(summary)
+    }
+
+    /// Stores a subtitle of a video unless it has one in that language already.
+    pub async fn save_video_subtitle(
+        &self,
+        video_id: i64,
+        language: &str,
+        source: SubtitleSource,
+        vtt: &str,
+        text: &str,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+                INSERT INTO video_subtitle (video_id, language, source, vtt, text)
+                VALUES (?1, ?2, ?3, ?4, ?5)
+                ON CONFLICT (video_id, language) DO NOTHING
+            "#,
+        )
+        .bind(video_id)
+        .bind(language)
+        .bind(source)
+        .bind(vtt)
+        .bind(text)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn list_video_subtitles(&self, video_id: i64) -> Result<Vec<SubtitleRow>> {
+        let subtitles = sqlx::query_as::<_, SubtitleRow>(
+            r#"
+                SELECT subtitle_id, video_id, language, source, date_added FROM video_subtitle
+                WHERE video_id = ?1 ORDER BY language
+            "#,
+        )
+        .bind(video_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(subtitles)
+    }
+
+    /// The WebVTT of a video's subtitle.
+    pub async fn get_video_subtitle(
+        &self,
+        video_id: i64,
+        language: &str,
+    ) -> Result<Option<String>> {
+        let vtt = sqlx::query_scalar::<_, String>(
+            "SELECT vtt FROM video_subtitle WHERE video_id = ?1 AND language = ?2",
+        )
+        .bind(video_id)
+        .bind(language)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(vtt)
+    }
+
+    /// Video id, language and WebVTT of subtitles whose text contains `text`, ignoring case,
+    /// newest first.
+    pub async fn search_subtitles(
+        &self,
+        text: &str,
+        limit: i64,
+    ) -> Result<Vec<(i64, String, String)>> {
+        let subtitles = match fts_quote(text) {
+            Some(phrase) => {
+                sqlx::query_as::<_, (i64, String, String)>(
+                    r#"
+                        SELECT video_subtitle.video_id, video_subtitle.language, video_subtitle.vtt
+                        FROM subtitle_search
+                        JOIN video_subtitle ON video_subtitle.subtitle_id = subtitle_search.rowid
+                        WHERE subtitle_search MATCH ?1
+                        ORDER BY video_subtitle.video_id DESC LIMIT ?2
+                    "#,
+                )
+                .bind(phrase)
+                .bind(limit)
+                .fetch_all(&self.pool)
+                .await?
+            }
+            // Too short for the trigram index.
+            None => {
+                sqlx::query_as::<_, (i64, String, String)>(
+                    r#"
+                        SELECT video_id, language, vtt FROM video_subtitle
+                        WHERE instr(lower(text), lower(?1)) > 0
+                        ORDER BY video_id DESC LIMIT ?2
+                    "#,
+                )
+                .bind(text)
+                .bind(limit)
+                .fetch_all(&self.pool)
+                .await?
+            }
+        };
+        Ok(subtitles)
+    }
+
+    /// Stores the chapters of a video without thumbnails, numbered in order, unless it has
+    /// chapters already. Returns the id of each chapter stored, `None` for those it had.
+    pub async fn save_video_chapters(
+        &self,
+        video_id: i64,
+        chapters: &[Chapter],
+    ) -> Result<Vec<Option<i64>>> {
+        let mut transaction = self.pool.begin().await?;
+        let mut chapter_ids = Vec::with_capacity(chapters.len());
+        for (index, chapter) in chapters.iter().enumerate() {
+            let chapter_id: Option<i64> = sqlx::query_scalar(
+                r#"
+                    INSERT INTO video_chapter (video_id, chapter_index, title, start_time, end_time)
+                    VALUES (?1, ?2, ?3, ?4, ?5)
+                    ON CONFLICT (video_id, chapter_index) DO NOTHING
+                    RETURNING chapter_id
+                "#,
+            )
+            .bind(video_id)
+            .bind(index as i64)
+            .bind(&chapter.title)
+            .bind(chapter.start)
+            .bind(chapter.end)
+            .fetch_optional(&mut *transaction)
+            .await?;
+            chapter_ids.push(chapter_id);
+        }
+        transaction.commit().await?;
+        Ok(chapter_ids)
+    }
+
+    pub async fn set_chapter_thumbnail(&self, chapter_id: i64, thumbnail: &[u8]) -> Result<()> {
+        sqlx::query("UPDATE video_chapter SET thumbnail = ?2 WHERE chapter_id = ?1")
+            .bind(chapter_id)
+            .bind(thumbnail)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn list_video_chapters(&self, video_id: i64) -> Result<Vec<ChapterRow>> {
+        let chapters = sqlx::query_as::<_, ChapterRow>(&format!(
+            "{CHAPTER_SELECT} WHERE video_id = ?1 ORDER BY chapter_index"
+        ))
+        .bind(video_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(chapters)
+    }
+
+    pub async fn get_video_chapter(&self, video_id: i64, index: i64) -> Result<Option<ChapterRow>> {
+        let chapter = sqlx::query_as::<_, ChapterRow>(&format!(
+            "{CHAPTER_SELECT} WHERE video_id = ?1 AND chapter_index = ?2"
+        ))
+        .bind(video_id)
+        .bind(index)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(chapter)
+    }
+
+    pub async fn get_chapter_thumbnail(
+        &self,
+        video_id: i64,
+        index: i64,
+    ) -> Result<Option<Vec<u8>>> {
+        let thumbnail = sqlx::query_scalar::<_, Option<Vec<u8>>>(
+            "SELECT thumbnail FROM video_chapter WHERE video_id = ?1 AND chapter_index = ?2",
+        )
+        .bind(video_id)
+        .bind(index)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(thumbnail.flatten())
+    }
+
+    pub async fn list_video_streams(&self, video_id: i64) -> Result<Vec<VideoStreamRow>> {
+        let streams = sqlx::query_as::<_, VideoStreamRow>(
+            "SELECT * FROM video_stream WHERE video_id = ?1 ORDER BY stream_index",
+        )
+        .bind(video_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(streams)
+    }
+
+    /// Adds a track, creating its artists and album the first time they are seen. Artists
+    /// match by name ignoring case, albums by title and album artist.
+    pub async fn save_track(
+        &self,
+        path: &str,
+        hash: &str,
+        size: i64,
+        tags: &AudioTags,
+        probe: &Probe,
+        cover: Option<Cover<'_>>,
+    ) -> Result<TrackRow> {
+        let mut transaction = self.pool.begin().await?;
+        let cover_id: Option<i64> = match cover {
+            Some(cover) => Some(
+                sqlx::query_scalar(
+                    r#"
+                        INSERT INTO cover (hash, mime, data) VALUES (?1, ?2, ?3)
+                        ON CONFLICT (hash) DO UPDATE SET hash = excluded.hash
+                        RETURNING cover_id
+                    "#,
+                )
+                .bind(cover.hash)
+                .bind(cover.mime)
+                .bind(cover.data)
+                .fetch_one(&mut *transaction)
+                .await?,
+            ),
+            None => None,
+        };
+        let mut artist_ids = Vec::new();
+        for name in [&tags.artist, &tags.album_artist] {
+            let artist_id: Option<i64> = match name {
+                Some(name) => Some(
+                    sqlx::query_scalar(
+                        r#"
+                            INSERT INTO artist (name) VALUES (?1)
+                            ON CONFLICT (name) DO UPDATE SET name = artist.name
+                            RETURNING artist_id
+                        "#,
+                    )
+                    .bind(name)
+                    .fetch_one(&mut *transaction)
+                    .await?,
+                ),
+                None => None,
+            };
+            artist_ids.push(artist_id);
+        }
+        let (artist_id, album_artist_id) = (artist_ids[0], artist_ids[1]);
+        let album_id: Option<i64> = match &tags.album {
+            Some(title) => {
+                let existing: Option<i64> = sqlx::query_scalar(
+                    "SELECT album_id FROM album WHERE title = ?1 AND artist_id IS ?2",
+                )
+                .bind(title)
+                .bind(album_artist_id)
+                .fetch_optional(&mut *transaction)
+                .await?;
+                match existing {
+                    Some(album_id) => {
+                        // The first track that has them fills in the album's details.
+                        sqlx::query(
+                            r#"
+                                UPDATE album SET year = coalesce(year, ?2),
+                                    genre = coalesce(genre, ?3), cover_id = coalesce(cover_id, ?4)
+                                WHERE album_id = ?1
+                            "#,
+                        )
+                        .bind(album_id)
+                        .bind(tags.year)
+                        .bind(&tags.genre)
+                        .bind(cover_id)
+                        .execute(&mut *transaction)
+                        .await?;
+                        Some(album_id)
+                    }
+                    None => Some(
+                        sqlx::query_scalar(
+                            r#"
+                                INSERT INTO album (title, artist_id, year, genre, cover_id)
+                                VALUES (?1, ?2, ?3, ?4, ?5)
+                                RETURNING album_id
+                            "#,
+                        )
+                        .bind(title)
+                        .bind(album_artist_id)
+                        .bind(tags.year)
+                        .bind(&tags.genre)
+                        .bind(cover_id)
+                        .fetch_one(&mut *transaction)
+                        .await?,
+                    ),
+                }
+            }
+            None => None,
+        };
+        let codec = probe
+            .streams
+            .iter()
+            .find(|stream| stream.codec_type == "audio")
+            .and_then(|stream| stream.codec_name.clone());
+        let track_id: i64 = sqlx::query_scalar(
+            r#"
+                INSERT INTO track (
+                    path, hash, size, title, artist_id, album_id, track_number, disc_number,
+                    year, genre, duration, bit_rate, codec, container, cover_id
+                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)
+                RETURNING track_id
+            "#,
+        )
+        .bind(path)
+        .bind(hash)
+        .bind(size)
+        .bind(&tags.title)
+        .bind(artist_id)
+        .bind(album_id)
+        .bind(tags.track_number)
+        .bind(tags.disc_number)
+        .bind(tags.year)
+        .bind(&tags.genre)
+        .bind(probe.duration)
+        .bind(probe.bit_rate)
+        .bind(codec)
+        .bind(&probe.container)
+        .bind(cover_id)
+        .fetch_one(&mut *transaction)
+        .await?;
+        let track =
+            sqlx::query_as::<_, TrackRow>(&format!("{TRACK_SELECT} WHERE track.track_id = ?1"))
+                .bind(track_id)
+                .fetch_one(&mut *transaction)
+                .await?;
+        transaction.commit().await?;
+        Ok(track)
+    }
+
+    pub async fn get_track(&self, track_id: i64) -> Result<Option<TrackRow>> {
+        let track =
+            sqlx::query_as::<_, TrackRow>(&format!("{TRACK_SELECT} WHERE track.track_id = ?1"))
+                .bind(track_id)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(track)
+    }
+
+    /// Artists of any track or album, by name.
+    pub async fn list_artists(&self, limit: i64, offset: i64) -> Result<Vec<ArtistRow>> {
+        let artists = sqlx::query_as::<_, ArtistRow>(
+            r#"
+                SELECT artist.artist_id, artist.name,
+                    (SELECT COUNT(*) FROM album WHERE album.artist_id = artist.artist_id)
+                        AS album_count,
+                    (SELECT COUNT(*) FROM track WHERE track.artist_id = artist.artist_id)
+                        AS track_count
+                FROM artist
+                ORDER BY artist.name
+                LIMIT ?1 OFFSET ?2
+            "#,
+        )
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(artists)
+    }
+
+    pub async fn get_artist(&self, artist_id: i64) -> Result<Option<ArtistRow>> {
+        let artist = sqlx::query_as::<_, ArtistRow>(
+            r#"
+                SELECT artist.artist_id, artist.name,
+                    (SELECT COUNT(*) FROM album WHERE album.artist_id = artist.artist_id)
+                        AS album_count,
+                    (SELECT COUNT(*) FROM track WHERE track.artist_id = artist.artist_id)
+                        AS track_count
+                FROM artist
+                WHERE artist.artist_id = ?1
+            "#,
+        )
+        .bind(artist_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(artist)
+    }
+
+    /// Albums whose album artist is `artist_id`, oldest first.
+    pub async fn list_artist_albums(&self, artist_id: i64) -> Result<Vec<AlbumRow>> {
+        let albums = sqlx::query_as::<_, AlbumRow>(&format!(
+            r#"
+                {ALBUM_SELECT}
+                WHERE album.artist_id = ?1
+                GROUP BY album.album_id
+                ORDER BY album.year IS NULL, album.year, album.title
+            "#
+        ))
+        .bind(artist_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(albums)
+    }
+
+    pub async fn get_album(&self, album_id: i64) -> Result<Option<AlbumRow>> {
+        let album = sqlx::query_as::<_, AlbumRow>(&format!(
+            "{ALBUM_SELECT} WHERE album.album_id = ?1 GROUP BY album.album_id"
+        ))
+        .bind(album_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(album)
+    }
+
+    /// An album's tracks in disc and track order.
+    pub async fn list_album_tracks(&self, album_id: i64) -> Result<Vec<TrackRow>> {
+        let tracks = sqlx::query_as::<_, TrackRow>(&format!(
+            r#"
+                {TRACK_SELECT}
+                WHERE track.album_id = ?1
+                ORDER BY track.disc_number IS NULL, track.disc_number,
+                    track.track_number IS NULL, track.track_number, track.title
+            "#
+        ))
+        .bind(album_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(tracks)
+    }
+
+    /// Artists whose name contains `query`, ignoring case; an empty query matches all.
+    pub async fn search_artists(
+        &self,
+        query: &str,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<ArtistRow>> {
+        let artists = sqlx::query_as::<_, ArtistRow>(
+            r#"
+                SELECT artist.artist_id, artist.name,
+                    (SELECT COUNT(*) FROM album WHERE album.artist_id = artist.artist_id)
+                        AS album_count,
+                    (SELECT COUNT(*) FROM track WHERE track.artist_id = artist.artist_id)
+                        AS track_count
+                FROM artist
+                WHERE instr(lower(artist.name), lower(?1)) > 0
+                ORDER BY artist.name
+                LIMIT ?2 OFFSET ?3
+            "#,
+        )
+        .bind(query)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(artists)
+    }
+
+    /// Albums whose title contains `query`, ignoring case; an empty query matches all.
+    pub async fn search_albums(
+        &self,
+        query: &str,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<AlbumRow>> {
+        let albums = sqlx::query_as::<_, AlbumRow>(&format!(
+            r#"
+                {ALBUM_SELECT}
+                WHERE instr(lower(album.title), lower(?1)) > 0
+                GROUP BY album.album_id
+                ORDER BY album.title
+                LIMIT ?2 OFFSET ?3
+            "#
+        ))
+        .bind(query)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(albums)
+    }
+
+    /// Tracks whose title contains `query`, ignoring case; an empty query matches all.
+    pub async fn search_tracks(
+        &self,
+        query: &str,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<TrackRow>> {
+        let tracks = sqlx::query_as::<_, TrackRow>(&format!(
+            r#"
+                {TRACK_SELECT}
+                WHERE instr(lower(track.title), lower(?1)) > 0
+                ORDER BY track.title
+                LIMIT ?2 OFFSET ?3
+            "#
+        ))
+        .bind(query)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(tracks)
+    }
+
+    /// Mime type and bytes of a cover picture.
+    pub async fn get_cover(&self, cover_id: i64) -> Result<Option<(String, Vec<u8>)>> {
+        let cover = sqlx::query_as("SELECT mime, data FROM cover WHERE cover_id = ?1")
+            .bind(cover_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(cover)
+    }
+
+    pub async fn get_tag_names(&self, tag_ids: &[i32]) -> Result<Vec<(i32, String)>> {
+        let mut query_builder =
+            QueryBuilder::<Sqlite>::new("SELECT tag_id, name FROM tag WHERE tag_id IN (");
+        let mut separated = query_builder.separated(",");
+        for tag_id in tag_ids {
+            separated.push_bind(tag_id);
+        }
+        separated.push_unseparated(")");
+        let query = query_builder
+            .build_query_as::<(i32, String)>()
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(query)
+    }
+
+    /// Tags whose name starts with `prefix`, ignoring case and `_` vs ` `, most used first.
+    /// Falls back to trigram matching anywhere in the name when the prefix is too rare.
+    pub async fn autocomplete_tags(&self, prefix: &str, limit: i64) -> Result<Vec<TagUsageRow>> {
+        let mut tags = sqlx::query_as::<_, TagUsageRow>(
+            r#"
+                SELECT tag.tag_id, tag.name, tag.category, COUNT(image_tag.image_id) AS count
+                FROM tag
+                LEFT JOIN image_tag ON image_tag.tag_id = tag.tag_id
+                WHERE REPLACE(tag.name, '_', ' ') LIKE ?1 ESCAPE '\'
+                GROUP BY tag.tag_id
+                ORDER BY count DESC, LENGTH(tag.name), tag.name
+                LIMIT ?2
+            "#,
+        )
+        .bind(like_prefix(prefix))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        if tags.len() as i64 >= limit {
+            return Ok(tags);
+        }
+        let Some(phrase) = fts_phrase(prefix) else {
+            return Ok(tags);
+        };
+        let fuzzy = sqlx::query_as::<_, TagUsageRow>(
+            r#"
+                WITH matches AS (
+                    SELECT rowid AS tag_id, rank FROM tag_search
+                    WHERE tag_search MATCH ?1
+                    ORDER BY rank
+                    LIMIT 200
+                )
+                SELECT tag.tag_id, tag.name, tag.category, COUNT(image_tag.image_id) AS count
+                FROM matches
+                JOIN tag ON tag.tag_id = matches.tag_id
+                LEFT JOIN image_tag ON image_tag.tag_id = tag.tag_id
+                GROUP BY tag.tag_id
+                ORDER BY count DESC, MIN(matches.rank), tag.name
+                LIMIT ?2
+            "#,
+        )
+        .bind(phrase)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        for tag in fuzzy {
+            if tags.len() as i64 >= limit {
+                break;
+            }
+            if tags.iter().all(|found| found.tag_id != tag.tag_id) {
+                tags.push(tag);
+            }
+        }
+        Ok(tags)
+    }
+
+    /// Looks `name` up as a tag, then as an alias of one. A `character:` style namespace
+    /// restricts the lookup to that category.
+    pub async fn resolve_tag(&self, name: &str) -> Result<Option<TagRow>> {
+        let name = canonical_tag(name);
+        let (category, bare_name) = split_namespace(&name);
+        let tag = sqlx::query_as::<_, TagRow>(
+            r#"
+                SELECT tag_id, name, category FROM tag
+                WHERE name IN (?1, ?2) AND (?3 IS NULL OR category = ?3)
+                UNION ALL
+                SELECT tag.tag_id, tag.name, tag.category FROM tag_alias
+                JOIN tag ON tag.tag_id = tag_alias.tag_id
+                WHERE tag_alias.alias IN (?1, ?2) AND (?3 IS NULL OR tag.category = ?3)
+                LIMIT 1
+            "#,
+        )
+        .bind(bare_name)
+        .bind(&name)
+        .bind(category)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(tag)
+    }
+
+    pub async fn require_tag(&self, name: &str) -> Result<TagRow> {
+        self.resolve_tag(name)
+            .await?
+            .ok_or_else(|| Error::UnknownTag(name.to_string()))
+    }
+
+    pub async fn list_tag_aliases(&self) -> Result<Vec<TagAliasRow>> {
+        let aliases = sqlx::query_as::<_, TagAliasRow>(
+            r#"
+                SELECT tag_alias.alias, tag.tag_id, tag.name FROM tag_alias
+                JOIN tag ON tag.tag_id = tag_alias.tag_id
+                ORDER BY tag_alias.alias
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(aliases)
+    }
+
+    pub async fn add_tag_alias(&self, alias: &str, tag: &str) -> Result<TagAliasRow> {
+        let alias = canonical_tag(alias);
+        let tag = self.require_tag(tag).await?;
+        let existing: Option<i64> = sqlx::query_scalar("SELECT tag_id FROM tag WHERE name = ?1")
+            .bind(&alias)
+            .fetch_optional(&self.pool)
+            .await?;
+        if existing.is_some() {
+            return Err(Error::AliasIsTag(alias));
+        }
+        sqlx::query(
+            r#"
+                INSERT INTO tag_alias (alias, tag_id) VALUES (?1, ?2)
+                ON CONFLICT (alias) DO UPDATE SET tag_id = excluded.tag_id
+            "#,
+        )
+        .bind(&alias)
+        .bind(tag.tag_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(TagAliasRow {
+            alias,
+            name: tag.name,
+            tag_id: tag.tag_id,
+        })
+    }
+
+    pub async fn remove_tag_alias(&self, alias: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM tag_alias WHERE alias = ?1")
+            .bind(canonical_tag(alias))
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn list_tag_implications(&self) -> Result<Vec<TagImplicationRow>> {
+        let implications = sqlx::query_as::<_, TagImplicationRow>(
+            r#"
+                SELECT
+                    tag.tag_id, tag.name,
+                    implied.tag_id AS implied_tag_id, implied.name AS implied_name
+                FROM tag_implication
+                JOIN tag ON tag.tag_id = tag_implication.tag_id
+                JOIN tag AS implied ON implied.tag_id = tag_implication.implied_tag_id
+                ORDER BY tag.name, implied.name
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(implications)
+    }
+
+    /// Records that `tag` implies `implied`, refusing anything that would close a cycle.
+    pub async fn add_tag_implication(&self, tag: &str, implied: &str) -> Result<TagImplicationRow> {
+        let tag = self.require_tag(tag).await?;
+        let implied = self.require_tag(implied).await?;
+        // Checked and inserted in one transaction, so concurrent additions cannot close a
+        // cycle between them.
+        let mut transaction = self.pool.begin().await?;
+        if tag.tag_id == implied.tag_id
+            || Self::implies(&mut transaction, implied.tag_id, tag.tag_id).await?
+        {
+            return Err(Error::ImplicationCycle(implied.name, tag.name));
+        }
+        sqlx::query(
+            r#"
+                INSERT INTO tag_implication (tag_id, implied_tag_id) VALUES (?1, ?2)
+                ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(tag.tag_id)
+        .bind(implied.tag_id)
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(TagImplicationRow {
+            implied_name: implied.name,
+            implied_tag_id: implied.tag_id,
+            name: tag.name,
+            tag_id: tag.tag_id,
+        })
+    }
+
+    pub async fn remove_tag_implication(&self, tag: &str, implied: &str) -> Result<bool> {
+        let tag = self.require_tag(tag).await?;
+        let implied = self.require_tag(implied).await?;
+        let result =
+            sqlx::query("DELETE FROM tag_implication WHERE tag_id = ?1 AND implied_tag_id = ?2")
+                .bind(tag.tag_id)
+                .bind(implied.tag_id)
+                .execute(&self.pool)
+                .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Whether `implied` is reachable from `tag_id` through any chain of implications.
+    async fn implies(connection: &mut SqliteConnection, tag_id: i64, implied: i64) -> Result<bool> {
+        let found: Option<i64> = sqlx::query_scalar(
+            r#"
+                WITH RECURSIVE reachable(tag_id) AS (
+                    SELECT ?1
+                    UNION
+                    SELECT tag_implication.implied_tag_id FROM tag_implication
+                    JOIN reachable ON tag_implication.tag_id = reachable.tag_id
+                )
+                SELECT tag_id FROM reachable WHERE tag_id = ?2
+            "#,
+        )
+        .bind(tag_id)
+        .bind(implied)
+        .fetch_optional(connection)
+        .await?;
+        Ok(found.is_some())
+    }
+
+    /// Creates `name` as a new tag unless it already resolves to one. `artist:name` style
+    /// namespaces pick the category. Ratings are fixed by the model and cannot be created, and
+    /// a name already taken in another category returns that tag unchanged.
+    pub async fn ensure_tag(&self, name: &str) -> Result<TagRow> {
+        if let Some(tag) = self.resolve_tag(name).await? {
+            return Ok(tag);
+        }
+        let name = canonical_tag(name);
+        let (category, bare_name) = match split_namespace(&name) {
+            (Some(TagCategory::Rating), _) => return Err(Error::UnknownTag(name)),
+            (category, bare_name) => (category.unwrap_or(TagCategory::General), bare_name),
+        };
+        let tag = sqlx::query_as::<_, TagRow>(
+            r#"
+                INSERT INTO tag (name, category) VALUES (?1, ?2)
+                ON CONFLICT (name) DO UPDATE SET name = excluded.name
+                RETURNING tag_id, name, category
+            "#,
+        )
+        .bind(bare_name)
+        .bind(category)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(tag)
+    }
+
+    /// Maps every output of `model` to a tag id, creating tags for labels the tag table does
+    /// not know yet. Existing tags and mappings are never renumbered, so a label file that
+    /// disagrees with an earlier sync of the same model version is an error.
+    pub async fn sync_model_labels(&self, model: &str, labels: &[Label]) -> Result<Vec<i64>> {
+        let labels_json = serde_json::to_string(labels).expect("labels are always serializable");
+        let mut transaction = self.pool.begin().await?;
+        sqlx::query(
+            r#"
+                INSERT INTO tag (name, category)
+                SELECT json_extract(value, '$.name'), json_extract(value, '$.category')
+                FROM json_each(?1) WHERE true
+                ORDER BY key
+                ON CONFLICT (name) DO NOTHING
+            "#,
+        )
+        .bind(&labels_json)
+        .execute(&mut *transaction)
+        .await?;
+        sqlx::query(
+            r#"
+                INSERT INTO model_label (model, label_index, tag_id)
+                SELECT ?2, json_each.key, tag.tag_id
+                FROM json_each(?1)
+                JOIN tag ON tag.name = json_extract(json_each.value, '$.name')
+                WHERE true
+                ON CONFLICT (model, label_index) DO NOTHING
+            "#,
+        )
+        .bind(&labels_json)
+        .bind(model)
+        .execute(&mut *transaction)
+        .await?;
+        let mapped = sqlx::query_as::<_, (i64, i64, String)>(
+            r#"
+                SELECT model_label.label_index, tag.tag_id, tag.name FROM model_label
+                JOIN tag ON tag.tag_id = model_label.tag_id
+                WHERE model_label.model = ?1
+                ORDER BY model_label.label_index
+            "#,
+        )
+        .bind(model)
+        .fetch_all(&mut *transaction)
+        .await?;
+        if mapped.len() != labels.len() {
+            return Err(Error::LabelMismatch(
+                model.to_string(),
+                format!("{} labels, {} mapped", labels.len(), mapped.len()),
+            ));
+        }
+        let mut tag_ids = Vec::with_capacity(labels.len());
+        for (label, (index, tag_id, name)) in labels.iter().zip(mapped) {
+            if label.name != name {
+                return Err(Error::LabelMismatch(
+                    model.to_string(),
+                    format!("output {index} is {name}, label file says {}", label.name),
+                ));
+            }
+            tag_ids.push(tag_id);
+        }
+        transaction.commit().await?;
+        Ok(tag_ids)
+    }
+
+    /// Replaces what `model` predicted for an image, kept apart from the image's effective tags
+    /// so models can be compared.
+    pub async fn save_model_tags(
+        &self,
+        image_id: i64,
+        model: &str,
+        tags: &[(i64, f64)],
+    ) -> Result<()> {
+        let mut transaction = self.pool.begin().await?;
+        sqlx::query("DELETE FROM image_model_tag WHERE image_id = ?1 AND model = ?2")
+            .bind(image_id)
+            .bind(model)
+            .execute(&mut *transaction)
+            .await?;
+        sqlx::query(
+            r#"
+                INSERT INTO image_model_tag (image_id, model, tag_id, score)
+                SELECT ?1, ?2, json_extract(value, '$[0]'), json_extract(value, '$[1]')
+                FROM json_each(?3)
+            "#,
+        )
+        .bind(image_id)
+        .bind(model)
+        .bind(serde_json::to_string(tags).expect("tags are always serializable"))
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    pub async fn list_model_tags(&self, image_id: i64) -> Result<Vec<ModelTagRow>> {
+        let tags = sqlx::query_as::<_, ModelTagRow>(
+            r#"
+                SELECT image_model_tag.model, tag.tag_id, tag.name, tag.category,
+                    image_model_tag.score
+                FROM image_model_tag
+                JOIN tag ON tag.tag_id = image_model_tag.tag_id
+                WHERE image_model_tag.image_id = ?1
+                ORDER BY image_model_tag.model, image_model_tag.score DESC
+            "#,
+        )
+        .bind(image_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(tags)
+    }
+
+    pub async fn media_exists(&self, kind: MediaKind, media_id: i64) -> Result<bool> {
+        let found: Option<i64> = sqlx::query_scalar(&format!(
+            "SELECT 1 FROM {} WHERE {} = ?1",
+            kind.table(),
+            kind.primary_key()
+        ))
+        .bind(media_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(found.is_some())
+    }
+
+    pub async fn list_tags(&self, kind: MediaKind, media_id: i64) -> Result<Vec<MediaTagRow>> {
+        let tags = sqlx::query_as::<_, MediaTagRow>(&format!(
+            r#"
+                SELECT tag.tag_id, tag.name, tag.category, media_tag.score, media_tag.source
+                FROM {tag_table} AS media_tag
+                JOIN tag ON tag.tag_id = media_tag.tag_id
+                WHERE media_tag.{foreign_key} = ?1
+                ORDER BY media_tag.score DESC, tag.name
+            "#,
+            tag_table = kind.tag_table(),
+            foreign_key = kind.tag_foreign_key(),
+        ))
+        .bind(media_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(tags)
+    }
+
+    /// Every tag in `tags` plus everything they imply. Implied tags inherit the highest score
+    /// of the tags implying them.
+    async fn expand_implications(&self, tags: &[(i64, f64)]) -> Result<Vec<(i64, f64)>> {
+        let seed = serde_json::to_string(tags).expect("tags are always serializable");
+        let expanded = sqlx::query_as::<_, (i64, f64)>(
+            r#"
+                WITH RECURSIVE implied(tag_id, score) AS (
+                    SELECT json_extract(value, '$[0]'), json_extract(value, '$[1]')
+                    FROM json_each(?1)
+                    UNION
+                    SELECT tag_implication.implied_tag_id, implied.score FROM tag_implication
+                    JOIN implied ON tag_implication.tag_id = implied.tag_id
+                )
+                SELECT tag_id, MAX(score) FROM implied GROUP BY tag_id
+            "#,
+        )
+        .bind(seed)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(expanded)
+    }
+
+    /// Attaches `tags` and everything they imply to a media item.
+    ///
+    /// Models never attach a tag a person removed and never overwrite a tag a person added.
+    /// Anything else clears the removal and takes ownership of existing rows.
+    pub async fn add_tags(
+        &self,
+        kind: MediaKind,
+        media_id: i64,
+        tags: &[(i64, f64)],
+        source: &TagSource,
+    ) -> Result<u64> {
+        let expanded = self.expand_implications(tags).await?;
+        let expanded = serde_json::to_string(&expanded).expect("tags are always serializable");
+        let tag_table = kind.tag_table();
+        let removed_table = kind.removed_tag_table();
+        let foreign_key = kind.tag_foreign_key();
+        let mut transaction = self.pool.begin().await?;
+        let insert = if source.is_model() {
+            model_tag_upsert(kind)
+        } else {
+            sqlx::query(&format!(
+                r#"
+                    DELETE FROM {removed_table} WHERE {foreign_key} = ?1
+                    AND tag_id IN (SELECT json_extract(value, '$[0]') FROM json_each(?2))
+                "#
+            ))
+            .bind(media_id)
+            .bind(&expanded)
+            .execute(&mut *transaction)
+            .await?;
+            format!(
+                r#"
+                    INSERT INTO {tag_table} ({foreign_key}, tag_id, score, source)
+                    SELECT ?1, json_extract(value, '$[0]'), json_extract(value, '$[1]'), ?3
+                    FROM json_each(?2) WHERE true
+                    ON CONFLICT ({foreign_key}, tag_id) DO UPDATE
+                    SET score = excluded.score, source = excluded.source
+                "#
+            )
+        };
+        let result = sqlx::query(&insert)
+            .bind(media_id)
+            .bind(&expanded)
+            .bind(source.to_string())
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Swaps every model made tag of an item for `tags`, as a re-tag with a newer model does.
+    /// Tags added by people and tags people removed stay as they are.
+    pub async fn replace_model_tags(
+        &self,
+        kind: MediaKind,
+        media_id: i64,
+        tags: &[(i64, f64)],
+        model: &str,
+    ) -> Result<()> {
+        let expanded = self.expand_implications(tags).await?;
+        let expanded = serde_json::to_string(&expanded).expect("tags are always serializable");
+        let tag_table = kind.tag_table();
+        let foreign_key = kind.tag_foreign_key();
+        let mut transaction = self.pool.begin().await?;
+        sqlx::query(&format!(
+            "DELETE FROM {tag_table} WHERE {foreign_key} = ?1 AND source LIKE 'model:%'"
+        ))
+        .bind(media_id)
+        .execute(&mut *transaction)
+        .await?;
+        sqlx::query(&model_tag_upsert(kind))
+            .bind(media_id)
+            .bind(&expanded)
+            .bind(TagSource::Model(model.to_string()).to_string())
+            .execute(&mut *transaction)
+            .await?;
+        sqlx::query(&format!(
+            r#"
+                UPDATE {} SET tagged_by = ?2, date_updated = CURRENT_TIMESTAMP
+                WHERE {} = ?1
+            "#,
+            kind.table(),
+            kind.primary_key()
+        ))
+        .bind(media_id)
+        .bind(model)
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// The next `limit` items after `cursor` in id order with their paths, skipping those
+    /// already tagged by `skip_model`.
+    pub async fn media_after(
+        &self,
+        kind: MediaKind,
+        cursor: i64,
+        limit: i64,
+        skip_model: Option<&str>,
+    ) -> Result<Vec<(i64, String)>> {
+        let media = sqlx::query_as::<_, (i64, String)>(&format!(
+            r#"
+                SELECT {id}, path FROM {table}
+                WHERE {id} > ?1 AND (?3 IS NULL OR tagged_by IS NOT ?3)
+                ORDER BY {id} LIMIT ?2
+            "#,
+            id = kind.primary_key(),
+            table = kind.table(),
+        ))
+        .bind(cursor)
+        .bind(limit)
+        .bind(skip_model)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(media)
+    }
+
+    /// How many items [`Database::media_after`] walks through from the start.
+    pub async fn count_media(&self, kind: MediaKind, skip_model: Option<&str>) -> Result<i64> {
+        let count = sqlx::query_scalar(&format!(
+            "SELECT COUNT(*) FROM {} WHERE ?1 IS NULL OR tagged_by IS NOT ?1",
+            kind.table()
+        ))
+        .bind(skip_model)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(count)
+    }
+
+    /// Detaches a tag and remembers that it was removed so models do not attach it again.
+    pub async fn remove_tag(&self, kind: MediaKind, media_id: i64, tag_id: i64) -> Result<bool> {
+        let tag_table = kind.tag_table();
+        let removed_table = kind.removed_tag_table();
+        let foreign_key = kind.tag_foreign_key();
+        let mut transaction = self.pool.begin().await?;
+        let result = sqlx::query(&format!(
+            "DELETE FROM {tag_table} WHERE {foreign_key} = ?1 AND tag_id = ?2"
+        ))
+        .bind(media_id)
+        .bind(tag_id)
+        .execute(&mut *transaction)
+        .await?;
+        sqlx::query(&format!(
+            r#"
+                INSERT INTO {removed_table} ({foreign_key}, tag_id) VALUES (?1, ?2)
+                ON CONFLICT DO NOTHING
+            "#
+        ))
+        .bind(media_id)
+        .bind(tag_id)
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Stores a quantized score vector, see [`Embedding`](crate::similarity::Embedding).
+    pub async fn save_embedding(&self, image_id: i64, model: &str, embedding: &[u8]) -> Result<()> {
+        sqlx::query(
+            r#"
+                INSERT INTO image_embedding (image_id, model, embedding) VALUES (?1, ?2, ?3)
+                ON CONFLICT (image_id, model) DO UPDATE SET embedding = excluded.embedding
+            "#,
+        )
+        .bind(image_id)
+        .bind(model)
+        .bind(embedding)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn list_embeddings(&self, model: &str) -> Result<Vec<(i64, Vec<u8>)>> {
+        let embeddings = sqlx::query_as(
+            "SELECT image_id, embedding FROM image_embedding WHERE model = ?1 ORDER BY image_id",
+        )
+        .bind(model)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(embeddings)
+    }
+
+    /// Every fingerprinted image's id and fingerprint.
+    pub async fn image_fingerprints(&self) -> Result<Vec<(i64, String)>> {
+        let fingerprints =
+            sqlx::query_as("SELECT img_id, fingerprint FROM image WHERE fingerprint IS NOT NULL")
+                .fetch_all(&self.pool)
+                .await?;
+        Ok(fingerprints)
+    }
+
+    /// `(image_id, tag_id)` pairs of the given images.
+    pub async fn image_tag_ids(&self, image_ids: &[i64]) -> Result<Vec<(i64, i64)>> {
+        let tags = sqlx::query_as(
+            r#"
+                SELECT image_id, tag_id FROM image_tag
+                WHERE image_id IN (SELECT value FROM json_each(?1))
+            "#,
+        )
+        .bind(serde_json::to_string(image_ids).expect("ids are always serializable"))
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(tags)
+    }
+
+    /// Images with the given ids, in the same order. Missing ids are left out.
+    pub async fn images_by_ids(&self, image_ids: &[i64]) -> Result<Vec<ImageRow>> {
+        let images = sqlx::query_as::<_, ImageRow>(
+            r#"
+                SELECT image.* FROM json_each(?1) AS ids
+                JOIN image ON image.img_id = ids.value
+                ORDER BY ids.key
+            "#,
+        )
+        .bind(serde_json::to_string(image_ids).expect("ids are always serializable"))
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(images)
+    }
+
+    pub async fn save_generation_params(
+        &self,
+        image_id: i64,
+        params: &GenerationParams,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+                INSERT INTO generation_params (
+                    image_id, source, prompt, negative_prompt, seed, sampler, steps, cfg_scale,
+                    model, model_hash, loras
+                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+                ON CONFLICT (image_id) DO UPDATE SET
+                    source = excluded.source,
+                    prompt = excluded.prompt,
+                    negative_prompt = excluded.negative_prompt,
+                    seed = excluded.seed,
+                    sampler = excluded.sampler,
+                    steps = excluded.steps,
+                    cfg_scale = excluded.cfg_scale,
+                    model = excluded.model,
+                    model_hash = excluded.model_hash,
+                    loras = excluded.loras
+            "#,
+        )
+        .bind(image_id)
+        .bind(params.source)
+        .bind(&params.prompt)
+        .bind(&params.negative_prompt)
+        .bind(params.seed)
+        .bind(&params.sampler)
+        .bind(params.steps)
+        .bind(params.cfg_scale)
+        .bind(&params.model)
+        .bind(&params.model_hash)
+        .bind(Json(&params.loras))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_generation_params(
+        &self,
+        image_id: i64,
+    ) -> Result<Option<GenerationParamsRow>> {
+        let params = sqlx::query_as::<_, GenerationParamsRow>(
+            "SELECT * FROM generation_params WHERE image_id = ?1",
+        )
+        .bind(image_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(params)
+    }
+
+    pub async fn save_image_metadata(&self, image_id: i64, metadata: &ImageMetadata) -> Result<()> {
+        let exif = &metadata.exif;
+        sqlx::query(
+            r#"
+                INSERT INTO image_metadata (
+                    image_id, width, height, format, color_type, bit_depth, color_space,
+                    has_alpha, animated, frame_count, camera_make, camera_model, lens_model,
+                    date_taken, gps_latitude, gps_longitude, exif
+                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17)
+                ON CONFLICT (image_id) DO UPDATE SET
+                    width = excluded.width,
+                    height = excluded.height,
+                    format = excluded.format,
+                    color_type = excluded.color_type,
+                    bit_depth = excluded.bit_depth,
+                    color_space = excluded.color_space,
+                    has_alpha = excluded.has_alpha,
+                    animated = excluded.animated,
+                    frame_count = excluded.frame_count,
+                    camera_make = excluded.camera_make,
+                    camera_model = excluded.camera_model,
+                    lens_model = excluded.lens_model,
+                    date_taken = excluded.date_taken,
+                    gps_latitude = excluded.gps_latitude,
+                    gps_longitude = excluded.gps_longitude,
+                    exif = excluded.exif
+            "#,
+        )
+        .bind(image_id)
+        .bind(metadata.width)
+        .bind(metadata.height)
+        .bind(&metadata.format)
+        .bind(&metadata.color_type)
+        .bind(metadata.bit_depth)
+        .bind(&metadata.color_space)
+        .bind(metadata.has_alpha)
+        .bind(metadata.animated)
+        .bind(metadata.frame_count)
+        .bind(&exif.camera_make)
+        .bind(&exif.camera_model)
+        .bind(&exif.lens_model)
+        .bind(exif.date_taken)
+        .bind(exif.gps_latitude)
+        .bind(exif.gps_longitude)
+        .bind(Json(&exif.fields))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_image_metadata(&self, image_id: i64) -> Result<Option<ImageMetadataRow>> {
+        let metadata = sqlx::query_as::<_, ImageMetadataRow>(
+            "SELECT * FROM image_metadata WHERE image_id = ?1",
+        )
+        .bind(image_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(metadata)
+    }
+
+    /// Raw scores `model` gave the content hashing to `content_hash`, see
+    /// [`InferenceCache`](crate::inference_cache::InferenceCache).
+    pub async fn cached_scores(&self, content_hash: &str, model: &str) -> Result<Option<Vec<u8>>> {
+        let scores = sqlx::query_scalar(
+            "SELECT scores FROM inference_cache WHERE content_hash = ?1 AND model = ?2",
+        )
+        .bind(content_hash)
+        .bind(model)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(scores)
+    }
+
+    pub async fn cache_scores(&self, content_hash: &str, model: &str, scores: &[u8]) -> Result<()> {
+        sqlx::query(
+            r#"
+                INSERT INTO inference_cache (content_hash, model, scores) VALUES (?1, ?2, ?3)
+                ON CONFLICT (content_hash, model) DO UPDATE
+                SET scores = excluded.scores, date_added = CURRENT_TIMESTAMP
+            "#,
+        )
+        .bind(content_hash)
+        .bind(model)
+        .bind(scores)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Drops cached scores of `model`, or of every model. Returns how many were dropped.
+    pub async fn invalidate_cached_scores(&self, model: Option<&str>) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM inference_cache WHERE ?1 IS NULL OR model = ?1")
+            .bind(model)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Number and total size of cached scores per model.
+    pub async fn cached_scores_usage(&self) -> Result<Vec<CachedScoresRow>> {
+        let usage = sqlx::query_as::<_, CachedScoresRow>(
+            r#"
+                SELECT model, COUNT(*) AS entries, SUM(length(scores)) AS bytes
+                FROM inference_cache GROUP BY model ORDER BY model
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(usage)
+    }
+
+    pub async fn create_job(
+        &self,
+        kind: &str,
+        params: &serde_json::Value,
+        stage: &str,
+        total: i64,
+    ) -> Result<JobRow> {
+        let job = sqlx::query_as::<_, JobRow>(
+            r#"
+                INSERT INTO job (kind, params, stage, total) VALUES (?1, ?2, ?3, ?4)
+                RETURNING *
+            "#,
+        )
+        .bind(kind)
+        .bind(Json(params))
+        .bind(stage)
+        .bind(total)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(job)
+    }
+
+    pub async fn get_job(&self, job_id: i64) -> Result<Option<JobRow>> {
+        let job = sqlx::query_as::<_, JobRow>("SELECT * FROM job WHERE job_id = ?1")
+            .bind(job_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(job)
+    }
+
+    /// Newest jobs first.
+    pub async fn list_jobs(&self, limit: i64) -> Result<Vec<JobRow>> {
+        let jobs = sqlx::query_as::<_, JobRow>("SELECT * FROM job ORDER BY job_id DESC LIMIT ?1")
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(jobs)
+    }
+
+    /// Jobs left running by a previous process, oldest first.
+    pub async fn running_jobs(&self, kind: &str) -> Result<Vec<JobRow>> {
+        let jobs = sqlx::query_as::<_, JobRow>(
+            "SELECT * FROM job WHERE kind = ?1 AND status = 'running' ORDER BY job_id",
+        )
+        .bind(kind)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(jobs)
+    }
+
+    /// Records a finished batch: the job's new position and how many items it handled.
+    pub async fn advance_job(
+        &self,
+        job_id: i64,
+        stage: &str,
+        cursor: i64,
+        processed: i64,
+        failed: i64,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+                UPDATE job SET stage = ?2, cursor = ?3, processed = processed + ?4,
+                    failed = failed + ?5, date_updated = CURRENT_TIMESTAMP
+                WHERE job_id = ?1
+            "#,
+        )
+        .bind(job_id)
+        .bind(stage)
+        .bind(cursor)
+        .bind(processed)
+        .bind(failed)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Moves a running job to `status`. Returns false when the job was not running.
+    pub async fn finish_job(
+        &self,
+        job_id: i64,
+        status: JobStatus,
+        error: Option<&str>,
+    ) -> Result<bool> {
+        let result = sqlx::query(
+            r#"
+                UPDATE job SET status = ?2, error = ?3, date_updated = CURRENT_TIMESTAMP
+                WHERE job_id = ?1 AND status = 'running'
+            "#,
+        )
+        .bind(job_id)
+        .bind(status)
+        .bind(error)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Images matching every filter, newest first. Tag names go through aliases; an unknown
+    /// required tag matches nothing while an unknown excluded tag is ignored.
+    pub async fn search_images(
+        &self,
+        filters: &[Filter],
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<ImageRow>> {
+        let mut query_builder = QueryBuilder::<Sqlite>::new(
+            r#"
+                SELECT image.* FROM image
+                LEFT JOIN image_metadata ON image_metadata.image_id = image.img_id
+                WHERE true
+            "#,
+        );
+        if !self
+            .push_search_filters(&mut query_builder, MediaKind::Image, filters)
+            .await?
+        {
+            return Ok(Vec::new());
+        }
+        query_builder.push(" ORDER BY image.date_updated DESC LIMIT ");
+        query_builder.push_bind(limit);
+        query_builder.push(" OFFSET ");
+        query_builder.push_bind(offset);
+        let images = query_builder
+            .build_query_as::<ImageRow>()
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(images)
+    }
+
+    /// Videos matching every filter, newest first, see [`Database::search_images`].
+    pub async fn search_videos(
+        &self,
+        filters: &[Filter],
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<VideoRow>> {
+        let mut query_builder = QueryBuilder::<Sqlite>::new(
+            r#"
+                SELECT video.* FROM video
+                JOIN video_summary ON video_summary.video_id = video.video_id
+                WHERE true
+            "#,
+        );
+        if !self
+            .push_search_filters(&mut query_builder, MediaKind::Video, filters)
+            .await?
+        {
+            return Ok(Vec::new());
+        }
+        query_builder.push(" ORDER BY video.date_updated DESC LIMIT ");
+        query_builder.push_bind(limit);
+        query_builder.push(" OFFSET ");
+        query_builder.push_bind(offset);
+        let videos = query_builder
+            .build_query_as::<VideoRow>()
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(videos)
+    }
+
+    /// Pushes ` AND ..` conditions for every filter. Returns false when a required tag is
+    /// unknown, so nothing can match.
+    async fn push_search_filters(
+        &self,
+        query_builder: &mut QueryBuilder<'_, Sqlite>,
+        kind: MediaKind,
+        filters: &[Filter],
+    ) -> Result<bool> {
+        let (table, primary_key) = (kind.table(), kind.primary_key());
+        let (tag_table, foreign_key) = (kind.tag_table(), kind.tag_foreign_key());
+        for filter in filters {
+            match &filter.term {
+                Term::Tag(name) => {
+                    let tag = self.resolve_tag(name).await?;
+                    let tag_id = match (tag, filter.negated) {
+                        (Some(tag), _) => tag.tag_id,
+                        (None, true) => continue,
+                        (None, false) => return Ok(false),
+                    };
+                    query_builder.push(if filter.negated {
+                        " AND NOT EXISTS"
+                    } else {
+                        " AND EXISTS"
+                    });
+                    query_builder.push(format!(
+                        " (SELECT 1 FROM {tag_table} WHERE {tag_table}.{foreign_key} = {table}.{primary_key} AND {tag_table}.tag_id = "
+                    ));
+                    query_builder.push_bind(tag_id);
+                    query_builder.push(")");
+                }
+                Term::Prompt(text) if kind == MediaKind::Image => {
+                    query_builder.push(if filter.negated {
+                        " AND NOT EXISTS"
+                    } else {
+                        " AND EXISTS"
+                    });
+                    match fts_quote(text) {
+                        Some(phrase) => {
+                            query_builder.push(
+                                " (SELECT 1 FROM prompt_search WHERE prompt_search.rowid = image.img_id AND prompt_search MATCH ",
+                            );
+                            query_builder.push_bind(phrase);
+                        }
+                        // Too short for the trigram index.
+                        None => {
+                            query_builder.push(
+                                " (SELECT 1 FROM generation_params WHERE generation_params.image_id = image.img_id AND instr(lower(prompt), lower(",
+                            );
+                            query_builder.push_bind(text.clone());
+                            query_builder.push(")) > 0");
+                        }
+                    }
+                    query_builder.push(")");
+                }
+                Term::Said(text) if kind == MediaKind::Video => {
+                    query_builder.push(if filter.negated {
+                        " AND NOT EXISTS"
+                    } else {
+                        " AND EXISTS"
+                    });
+                    match fts_quote(text) {
+                        Some(phrase) => {
+                            query_builder.push(
+                                " (SELECT 1 FROM video_subtitle JOIN subtitle_search ON subtitle_search.rowid = video_subtitle.subtitle_id WHERE video_subtitle.video_id = video.video_id AND subtitle_search MATCH ",
+                            );
+                            query_builder.push_bind(phrase);
+                        }
+                        // Too short for the trigram index.
+                        None => {
+                            query_builder.push(
+                                " (SELECT 1 FROM video_subtitle WHERE video_subtitle.video_id = video.video_id AND instr(lower(text), lower(",
+                            );
+                            query_builder.push_bind(text.clone());
+                            query_builder.push(")) > 0");
+                        }
+                    }
+                    query_builder.push(")");
+                }
+                Term::Model(name) if kind == MediaKind::Image => {
+                    query_builder.push(if filter.negated {
+                        " AND NOT EXISTS"
+                    } else {
+                        " AND EXISTS"
+                    });
+                    query_builder.push(
+                        " (SELECT 1 FROM generation_params WHERE generation_params.image_id = image.img_id AND (model = ",
+                    );
+                    query_builder.push_bind(name.clone());
+                    query_builder.push(" COLLATE NOCASE OR model_hash = ");
+                    query_builder.push_bind(name.clone());
+                    query_builder.push(" COLLATE NOCASE))");
+                }
+                term => {
+                    // Media without metadata only match negated terms.
+                    query_builder.push(if filter.negated {
+                        " AND NOT coalesce(("
+                    } else {
+                        " AND coalesce(("
+                    });
+                    match kind {
+                        MediaKind::Image => push_image_metadata_condition(query_builder, term),
+                        MediaKind::Video => push_video_metadata_condition(query_builder, term),
+                        MediaKind::Music | MediaKind::Chapter => {
+                            query_builder.push("NULL");
+                        }
+                    }
+                    query_builder.push("), false)");
+                }
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Inserts model tags from a `[[tag_id, score], ..]` json array, leaving alone tags people
+/// added or removed. Binds the media id, the array and the source.
+fn model_tag_upsert(kind: MediaKind) -> String {
+    let tag_table = kind.tag_table();
+    let removed_table = kind.removed_tag_table();
+    let foreign_key = kind.tag_foreign_key();
+    format!(
+        r#"
+            INSERT INTO {tag_table} ({foreign_key}, tag_id, score, source)
+            SELECT ?1, json_extract(value, '$[0]'), json_extract(value, '$[1]'), ?3
+            FROM json_each(?2)
+            WHERE json_extract(value, '$[0]') NOT IN (
+                SELECT tag_id FROM {removed_table} WHERE {foreign_key} = ?1
+            )
+            ON CONFLICT ({foreign_key}, tag_id) DO UPDATE
+            SET score = excluded.score, source = excluded.source
+            WHERE {tag_table}.source LIKE 'model:%'
+        "#
+    )
+}
+
+/// Pushes the condition a metadata term puts on the `image_metadata` row.
+fn push_image_metadata_condition(query_builder: &mut QueryBuilder<Sqlite>, term: &Term) {
+    match term {
+        Term::Number(field, comparison, value) => {
+            let column = match field {
+                NumberField::Width => "image_metadata.width",
+                NumberField::Height => "image_metadata.height",
+                NumberField::Aspect => "CAST(image_metadata.width AS REAL) / image_metadata.height",
+                NumberField::Megapixels => "image_metadata.width * image_metadata.height / 1e6",
+                NumberField::Frames => "image_metadata.frame_count",
+                NumberField::BitDepth => "image_metadata.bit_depth",
+                NumberField::Duration
+                | NumberField::FrameRate
+                | NumberField::Bitrate
+                | NumberField::Channels => "NULL",
+            };
+            // Ratios are rounded when written down, 16:9 is 1.777.. but 1366x768 is 1.778..
+            if *field == NumberField::Aspect && *comparison == Comparison::Equal {
+                query_builder.push(format!("abs({column} - "));
+                query_builder.push_bind(*value);
+                query_builder.push(") < 0.01");
+            } else {
+                query_builder.push(format!("{column} {} ", comparison.operator()));
+                query_builder.push_bind(*value);
+            }
+        }
+        Term::Text(field, text) => {
+            let column = match field {
+                TextField::Format => {
+                    query_builder.push("image_metadata.format = ");
+                    query_builder.push_bind(text.clone());
+                    return;
+                }
+                TextField::Camera => {
+                    "coalesce(image_metadata.camera_make, '') || ' ' || coalesce(image_metadata.camera_model, '')"
+                }
+                TextField::Lens => "image_metadata.lens_model",
+                TextField::Codec => {
+                    query_builder.push("NULL");
+                    return;
+                }
+            };
+            query_builder.push(format!("instr(lower({column}), lower("));
+            query_builder.push_bind(text.clone());
+            query_builder.push(")) > 0");
+        }
+        Term::Flag(field, value) => {
+            query_builder.push(match field {
+                FlagField::Animated => "image_metadata.animated = ",
+                FlagField::Alpha => "image_metadata.has_alpha = ",
+                FlagField::Gps => "(image_metadata.gps_latitude IS NOT NULL) = ",
+            });
+            query_builder.push_bind(*value);
+        }
+        Term::Taken(comparison, date) => {
+            query_builder.push("substr(image_metadata.date_taken, 1, ");
+            query_builder.push_bind(date.len() as i64);
+            query_builder.push(format!(") {} ", comparison.operator()));
+            query_builder.push_bind(date.clone());
+        }
+        Term::Tag(_) | Term::Prompt(_) | Term::Model(_) | Term::Said(_) => {
+            query_builder.push("NULL");
+        }
+    }
+}
+
+/// Pushes the condition a metadata term puts on the `video_summary` row.
+fn push_video_metadata_condition(query_builder: &mut QueryBuilder<Sqlite>, term: &Term) {
+    match term {
+        Term::Number(field, comparison, value) => {
+            let column = match field {
+                NumberField::Width => "video_summary.width",
+                NumberField::Height => "video_summary.height",
+                NumberField::Aspect => "CAST(video_summary.width AS REAL) / video_summary.height",
+                NumberField::Megapixels => "video_summary.width * video_summary.height / 1e6",
+                NumberField::Duration => "video_summary.duration",
+                NumberField::FrameRate => "video_summary.frame_rate",
+                NumberField::Bitrate => "video_summary.bit_rate / 1000.0",
+                NumberField::Channels => "video_summary.audio_channels",
+                NumberField::Frames | NumberField::BitDepth => "NULL",
+            };
+            // Frame rates are rounded too, 29.97 is 30000/1001.
+            if matches!(field, NumberField::Aspect | NumberField::FrameRate)
+                && *comparison == Comparison::Equal
+            {
+                query_builder.push(format!("abs({column} - "));
+                query_builder.push_bind(*value);
+                query_builder.push(") < 0.01");
+            } else {
+                query_builder.push(format!("{column} {} ", comparison.operator()));
+                query_builder.push_bind(*value);
+            }
+        }
+        Term::Text(TextField::Format, format) => {
+            // ffprobe lists every name of the container, e.g. `matroska,webm`.
+            query_builder.push("instr(',' || video_summary.container || ',', ',' || ");
+            query_builder.push_bind(format.clone());
+            query_builder.push(" || ',') > 0");
+        }
+        Term::Text(TextField::Codec, codec) => {
+            query_builder.push("(video_summary.video_codec = ");
+            query_builder.push_bind(codec.clone());
+            query_builder.push(" OR video_summary.audio_codec = ");
+            query_builder.push_bind(codec.clone());
+            query_builder.push(")");
+        }
+        _ => {
+            query_builder.push("NULL");
+        }
+    }
+}
+
+/// Lowercases and folds `_` to ` `, the form `tag_search` indexes names in.
+fn normalize_tag(name: &str) -> String {
+    name.trim().to_lowercase().replace('_', " ")
+}
+
+fn like_prefix(prefix: &str) -> String {
+    let mut pattern = String::new();
+    for c in normalize_tag(prefix).chars() {
+        if matches!(c, '%' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+fn fts_phrase(query: &str) -> Option<String> {
+    fts_quote(&normalize_tag(query))
+}
+
+/// Quotes `text` as an FTS5 phrase. Trigram queries need at least three characters to match
+/// anything.
+fn fts_quote(text: &str) -> Option<String> {
+    if text.chars().count() < 3 {
+        return None;
+    }
+    Some(format!("\"{}\"", text.replace('"', "\"\"")))
+}
+
+#[test]
+fn where_in() {
+    use sqlx::Execute;
+    let mut query_builder: QueryBuilder<Sqlite> =
+        QueryBuilder::new("SELECT * FROM population WHERE year IN (");
+    let years = vec![2019, 2020, 2021];
+    let mut separated = query_builder.separated(",");
+    for year in years {
+        separated.push_bind(year);
+    }
+    separated.push_unseparated(")");
+    let mut query = query_builder.build();
+    let arguments = query.take_arguments().unwrap();
+    dbg!(&arguments);
+    let sql = query.sql();
+    assert_eq!(sql, "SELECT * FROM population WHERE year IN (?,?,?)");
+}
+
+#[test]
+fn tag_patterns() {
+    assert_eq!(like_prefix("Cat_Ears"), "cat ears%");
+    assert_eq!(like_prefix("100%"), "100\\%%");
+    assert_eq!(fts_phrase("ca"), None);
+    assert_eq!(
+        fts_phrase("say \"hi\"").as_deref(),
+        Some("\"say \"\"hi\"\"\"")
+    );
 }
@@ -1,104 +1,82 @@
 use std::path::Path;
+use std::sync::RwLock;
 
-use image::{imageops::FilterType, DynamicImage, ImageBuffer, Rgb};
-use ndarray::{Array4, CowArray};
-use ort::{
-    tensor::OrtOwnedTensor, Environment, ExecutionProvider, GraphOptimizationLevel, Session,
-    SessionBuilder, Value,
-};
+use image::DynamicImage;
+use ort::Session;
 
+use crate::media::TagCategory;
+use crate::tagger::{self, Label, ModelDescriptor, Result, Tagger, Thresholds};
+
+/// DeepDanbooru lays its labels out as general, then character, then rating tags.
+const CHARACTER_START: usize = 6892 - 1;
+const RATING_START: usize = 9174 - 1;
+
+/// The DeepDanbooru tagger.
 pub struct Jarvis {
     session: Session,
+    descriptor: ModelDescriptor,
+    labels: Vec<Label>,
+    thresholds: RwLock<Thresholds>,
 }
 
-#[derive(thiserror::Error, Debug)]
-pub enum Error {
-    #[error("ORT {0}")]
-    Ort(#[from] ort::OrtError),
-    #[error("IO {0}")]
-    IO(#[from] std::io::Error),
-    #[error("ANY {0}")]
-    ANY(String),
-}
-
-pub type Result<T> = std::result::Result<T, Error>;
-
 impl Jarvis {
-    pub fn new(model_path: impl AsRef<Path>) -> Result<Self> {
-        let environment = Environment::builder()
-            .with_name("deepbooru")
-            .with_execution_providers([ExecutionProvider::CPU(Default::default())])
-            .build()?
-            .into_arc();
-        let session = SessionBuilder::new(&environment)?
-            .with_optimization_level(GraphOptimizationLevel::Level3)?
-            .with_intra_threads(std::thread::available_parallelism()?.get() as i16)?
-            .with_model_from_file(&model_path)?;
-
-        Ok(Self { session })
+    pub fn new(descriptor: ModelDescriptor) -> Result<Self> {
+        let session = tagger::session(&descriptor)?;
+        let labels = read_labels(&descriptor.labels_path)?;
+        let thresholds = Thresholds::new(descriptor.thresholds.clone(), &labels)?;
+        Ok(Self {
+            session,
+            descriptor,
+            labels,
+            thresholds: RwLock::new(thresholds),
+        })
     }
+}
 
-    pub fn infer_tags(&self, image: &DynamicImage) -> Result<Vec<(f32, usize)>> {
-        let resized = resize_padded(image, 512, 512);
-        let resized_vec = resized.to_vec();
-        let image =
-            Array4::from_shape_vec((1, 512, 512, 3), resized_vec).expect("This should never fail");
-        let mut image = image.mapv(|e| f32::from(e) / 255.0);
-        image.swap_axes(2, 1);
-        let image = CowArray::from(image).into_dyn();
-        let inputs = vec![Value::from_array(self.session.allocator(), &image)?];
-        let outputs: Vec<Value> = self.session.run(inputs)?;
-        let generated_tags: OrtOwnedTensor<f32, _> = outputs[0].try_extract()?;
-        let generated_tags = generated_tags.view();
-        let mut generated_tags: Vec<(f32, usize)> = generated_tags
-            .iter()
-            .zip(1usize..)
-            .map(|(f, s)| (*f, s))
-            .collect();
-        const CHARACTER_START: usize = 6892 - 1;
-        const RATING_START: usize = 9174 - 1;
-        let mut rating = generated_tags.split_off(RATING_START);
-        let mut characters = generated_tags.split_off(CHARACTER_START);
-        let mut attributes = generated_tags;
-        attributes.sort_unstable_by(|a, b| b.0.total_cmp(&a.0));
-        characters.sort_unstable_by(|a, b| b.0.total_cmp(&a.0));
-        rating.sort_unstable_by(|a, b| b.0.total_cmp(&a.0));
-        attributes.truncate(40);
-        characters.truncate(5);
-        rating.truncate(1);
-        let mut tags = attributes;
-        tags.append(&mut characters);
-        tags.append(&mut rating);
-        Ok(tags)
+impl Tagger for Jarvis {
+    fn descriptor(&self) -> &ModelDescriptor {
+        &self.descriptor
     }
-}
 
-fn resize_padded(
-    img: &DynamicImage,
-    max_width: u32,
-    max_height: u32,
-) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
-    let mut width = img.width();
-    let mut height = img.height();
-    let aspect_ratio = (width as f32) / (height as f32);
+    fn labels(&self) -> &[Label] {
+        &self.labels
+    }
 
-    if width > max_width || height < max_height {
-        width = max_width;
-        height = ((width as f32) / aspect_ratio) as u32;
+    fn thresholds(&self) -> &RwLock<Thresholds> {
+        &self.thresholds
     }
 
-    if height > max_height || width < max_width {
-        height = max_height;
-        width = ((height as f32) * aspect_ratio) as u32;
+    fn scores(&self, images: &[DynamicImage]) -> Result<Vec<Vec<f32>>> {
+        tagger::run(&self.session, tagger::to_tensor(images, &self.descriptor))
     }
+}
+
+/// Reads `tags.txt`, one label per line in output order.
+fn read_labels(path: impl AsRef<Path>) -> Result<Vec<Label>> {
+    let labels = std::fs::read_to_string(path)?;
+    Ok(labels
+        .lines()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .enumerate()
+        .map(|(index, name)| Label {
+            name: name.to_string(),
+            category: match index {
+                index if index >= RATING_START => TagCategory::Rating,
+                index if index >= CHARACTER_START => TagCategory::Character,
+                _ => TagCategory::General,
+            },
+        })
+        .collect())
+}
 
-    let thumbnail = img.resize_exact(width, height, FilterType::Gaussian);
-    let mut img = ImageBuffer::from_pixel(max_width, max_height, Rgb([255, 255, 255]));
-    image::imageops::overlay(
-        &mut img,
-        &thumbnail.to_rgb8(),
-        (max_width - width) as i64 / 2,
-        (max_height - height) as i64 / 2,
-    );
-    img
+#[test]
+fn label_categories() {
+    let labels = read_labels(concat!(env!("CARGO_MANIFEST_DIR"), "/tags.txt")).unwrap();
+    assert_eq!(labels.len(), 9176);
+    assert_eq!(labels[CHARACTER_START - 1].category, TagCategory::General);
+    assert_eq!(labels[CHARACTER_START].name, "2k-tan");
+    assert_eq!(labels[CHARACTER_START].category, TagCategory::Character);
+    assert_eq!(labels[RATING_START].name, "rating:safe");
+    assert_eq!(labels[RATING_START].category, TagCategory::Rating);
 }
@@ -0,0 +1,579 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde::Deserialize;
+
+const FFMPEG: &str = "ffmpeg";
+const FFPROBE: &str = "ffprobe";
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("ffmpeg {0}")]
+    FFM(String),
+    #[error("io")]
+    IO(#[from] tokio::io::Error),
+    #[error("ffprobe output: {0}")]
+    JSON(#[from] serde_json::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Grabs up to `count` frames, one every `every` frames from the start, as PNG files.
+pub async fn extract_frames(
+    path: impl AsRef<Path>,
+    count: u32,
+    every: u32,
+) -> Result<Vec<Vec<u8>>> {
+    let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+    tokio::fs::create_dir(&dir).await?;
+    let mut command = tokio::process::Command::new(FFMPEG);
+    command
+        .args(["-nostdin", "-loglevel", "error", "-i"])
+        .arg(path.as_ref())
+        .args([
+            "-vf",
+            &format!("select='not(mod(n\\,{}))'", every.max(1)),
+            "-vsync",
+            "vfr",
+            "-frames:v",
+            &count.to_string(),
+        ])
+        .arg(dir.join("%03d.png"));
+    let output = command.output().await;
+    let frames = match output {
+        Ok(output) if output.status.success() => read_frames(&dir).await,
+        Ok(output) => Err(Error::FFM(
+            String::from_utf8(output.stderr).unwrap_or("utf error".to_string()),
+        )),
+        Err(err) => Err(Error::IO(err)),
+    };
+    tokio::fs::remove_dir_all(&dir).await.ok();
+    frames
+}
+
+async fn read_frames(dir: &Path) -> Result<Vec<Vec<u8>>> {
+    let mut paths = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        paths.push(entry.path());
+    }
+    paths.sort();
+    let mut frames = Vec::with_capacity(paths.len());
+    for path in paths {
+        frames.push(tokio::fs::read(path).await?);
+    }
+    Ok(frames)
+}
+
+/// What ffprobe found in a media file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Probe {
+    /// ffprobe's names for the container, e.g. `mov,mp4,m4a,3gp,3g2,mj2`.
+    pub container: String,
+    /// Seconds.
+    pub duration: Option<f64>,
+    /// Bits per second over all streams.
+    pub bit_rate: Option<i64>,
+    pub streams: Vec<ProbeStream>,
+    /// Container tags with lowercase keys, filled in from the first audio stream's tags,
+    /// which is where Ogg keeps them.
+    pub tags: HashMap<String, String>,
+    /// Whether there is an embedded cover picture, see [`extract_cover`].
+    pub cover_art: bool,
+    pub chapters: Vec<ProbeChapter>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProbeStream {
+    pub index: i64,
+    /// `video`, `audio`, `subtitle`, ..
+    pub codec_type: String,
+    pub codec_name: Option<String>,
+    pub profile: Option<String>,
+    pub width: Option<i64>,
+    pub height: Option<i64>,
+    /// Frames per second.
+    pub frame_rate: Option<f64>,
+    pub bit_rate: Option<i64>,
+    pub sample_rate: Option<i64>,
+    pub channels: Option<i64>,
+    pub channel_layout: Option<String>,
+    pub language: Option<String>,
+    pub duration: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProbeChapter {
+    /// Seconds.
+    pub start: f64,
+    pub end: f64,
+    pub title: Option<String>,
+}
+
+/// ffprobe's JSON, which writes most numbers as strings.
+#[derive(Deserialize)]
+struct ProbeOutput {
+    #[serde(default)]
+    streams: Vec<StreamOutput>,
+    format: FormatOutput,
+    #[serde(default)]
+    chapters: Vec<ChapterOutput>,
+}
+
+#[derive(Deserialize)]
+struct ChapterOutput {
+    start_time: Option<String>,
+    end_time: Option<String>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct FormatOutput {
+    format_name: String,
+    duration: Option<String>,
+    bit_rate: Option<String>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct StreamOutput {
+    index: i64,
+    codec_type: Option<String>,
+    codec_name: Option<String>,
+    profile: Option<String>,
+    width: Option<i64>,
+    height: Option<i64>,
+    avg_frame_rate: Option<String>,
+    r_frame_rate: Option<String>,
+    bit_rate: Option<String>,
+    sample_rate: Option<String>,
+    channels: Option<i64>,
+    channel_layout: Option<String>,
+    duration: Option<String>,
+    #[serde(default)]
+    disposition: HashMap<String, i64>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+/// Reads the container and streams of a media file.
+pub async fn probe(path: impl AsRef<Path>) -> Result<Probe> {
+    let mut command = tokio::process::Command::new(FFPROBE);
+    command
+        .args([
+            "-v",
+            "error",
+            "-print_format",
+            "json",
+            "-show_format",
+            "-show_streams",
+            "-show_chapters",
+        ])
+        .arg(path.as_ref());
+    let output = command.output().await?;
+    if !output.status.success() {
+        return Err(Error::FFM(
+            String::from_utf8(output.stderr).unwrap_or("utf error".to_string()),
+        ));
+    }
+    parse_probe(&output.stdout)
+}
+
+fn parse_probe(json: &[u8]) -> Result<Probe> {
+    let output: ProbeOutput = serde_json::from_slice(json)?;
+    let cover_art = output
+        .streams
+        .iter()
+        .any(|stream| stream.disposition.get("attached_pic") == Some(&1));
+    let mut tags: HashMap<String, String> = output
+        .format
+        .tags
+        .into_iter()
+        .map(|(key, value)| (key.to_lowercase(), value))
+        .collect();
+    if let Some(audio) = output
+        .streams
+        .iter()
+        .find(|stream| stream.codec_type.as_deref() == Some("audio"))
+    {
+        for (key, value) in &audio.tags {
+            tags.entry(key.to_lowercase())
+                .or_insert_with(|| value.clone());
+        }
+    }
+    let streams = output
+        .streams
+        .into_iter()
+        // Cover art shows up as a one frame video stream.
+        .filter(|stream| stream.disposition.get("attached_pic") != Some(&1))
+        .map(|stream| ProbeStream {
+            index: stream.index,
+            codec_type: stream.codec_type.unwrap_or_else(|| "unknown".to_string()),
+            codec_name: stream.codec_name,
+            profile: stream.profile,
+            width: stream.width,
+            height: stream.height,
+            frame_rate: stream
+                .avg_frame_rate
+                .as_deref()
+                .and_then(parse_rate)
+                .or_else(|| stream.r_frame_rate.as_deref().and_then(parse_rate)),
+            bit_rate: parse_number(stream.bit_rate),
+            sample_rate: parse_number(stream.sample_rate),
+            channels: stream.channels,
+            channel_layout: stream.channel_layout,
+            language: stream
+                .tags
+                .get("language")
+                .filter(|language| *language != "und")
+                .cloned(),
+            duration: parse_number(stream.duration),
+        })
+        .collect();
+    let chapters = output
+        .chapters
+        .into_iter()
+        .filter_map(|chapter| {
+            Some(ProbeChapter {
+                start: parse_number(chapter.start_time)?,
+                end: parse_number(chapter.end_time)?,
+                title: chapter
+                    .tags
+                    .into_iter()
+                    .find(|(key, _)| key.eq_ignore_ascii_case("title"))
+                    .map(|(_, title)| title),
+            })
+        })
+        .collect();
+    Ok(Probe {
+        container: output.format.format_name,
+        duration: parse_number(output.format.duration),
+        bit_rate: parse_number(output.format.bit_rate),
+        streams,
+        tags,
+        cover_art,
+        chapters,
+    })
+}
+
+/// The embedded cover picture as stored, usually a JPEG or PNG.
+pub async fn extract_cover(path: impl AsRef<Path>) -> Result<Vec<u8>> {
+    let mut command = tokio::process::Command::new(FFMPEG);
+    command
+        .args(["-nostdin", "-loglevel", "error", "-i"])
+        .arg(path.as_ref())
+        .args([
+            "-an",
+            "-map",
+            "0:v:0",
+            "-frames:v",
+            "1",
+            "-c:v",
+            "copy",
+            "-f",
+            "image2pipe",
+            "-",
+        ]);
+    let output = command.output().await?;
+    if output.status.success() && !output.stdout.is_empty() {
+        Ok(output.stdout)
+    } else {
+        Err(Error::FFM(
+            String::from_utf8(output.stderr).unwrap_or("utf error".to_string()),
+        ))
+    }
+}
+
+/// Converts the text subtitle stream at `index` to WebVTT.
+pub async fn extract_subtitle(path: impl AsRef<Path>, index: i64) -> Result<String> {
+    let mut command = tokio::process::Command::new(FFMPEG);
+    command
+        .args(["-nostdin", "-loglevel", "error", "-i"])
+        .arg(path.as_ref())
+        .args(["-map", &format!("0:{index}"), "-f", "webvtt", "-"]);
+    let output = command.output().await?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(Error::FFM(
+            String::from_utf8(output.stderr).unwrap_or("utf error".to_string()),
+        ))
+    }
+}
+
+/// A JPEG of the frame `at` seconds in, scaled down to at most `max_height` pixels tall.
+pub async fn extract_thumbnail(
+    path: impl AsRef<Path>,
+    at: f64,
+    max_height: u32,
+) -> Result<Vec<u8>> {
+    let mut command = tokio::process::Command::new(FFMPEG);
+    command
+        .args([
+            "-nostdin",
+            "-loglevel",
+            "error",
+            "-ss",
+            &format!("{at:.3}"),
+            "-i",
+        ])
+        .arg(path.as_ref())
+        .args([
+            "-map",
+            "0:V:0",
+            "-frames:v",
+            "1",
+            "-vf",
+            &format!("scale=-2:'min({max_height},ih)'"),
+            "-c:v",
+            "mjpeg",
+            "-f",
+            "image2pipe",
+            "-",
+        ]);
+    let output = command.output().await?;
+    if output.status.success() && !output.stdout.is_empty() {
+        Ok(output.stdout)
+    } else {
+        Err(Error::FFM(
+            String::from_utf8(output.stderr).unwrap_or("utf error".to_string()),
+        ))
+    }
+}
+
+/// Starts copying the `duration` seconds from `start` out of a video without re-encoding, as
+/// Matroska on the child's stdout. The cut snaps to the keyframe before `start`.
+pub fn clip(path: impl AsRef<Path>, start: f64, duration: f64) -> Result<tokio::process::Child> {
+    let mut command = tokio::process::Command::new(FFMPEG);
+    command
+        .args([
+            "-nostdin",
+            "-loglevel",
+            "error",
+            "-ss",
+            &format!("{start:.3}"),
+            "-i",
+        ])
+        .arg(path.as_ref())
+        .args([
+            "-t",
+            &format!("{duration:.3}"),
+            "-map",
+            "0:V?",
+            "-map",
+            "0:a?",
+            "-c",
+            "copy",
+            "-f",
+            "matroska",
+            "pipe:1",
+        ])
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .kill_on_drop(true);
+    Ok(command.spawn()?)
+}
+
+/// Starts transcoding the first audio stream to `codec` at `bit_rate` kbps, muxed as `format`,
+/// with the output on the child's stdout as it is produced.
+pub fn transcode_audio(
+    path: impl AsRef<Path>,
+    codec: &str,
+    format: &str,
+    bit_rate: u32,
+) -> Result<tokio::process::Child> {
+    let mut command = tokio::process::Command::new(FFMPEG);
+    command
+        .args(["-nostdin", "-loglevel", "error", "-i"])
+        .arg(path.as_ref())
+        .args([
+            "-map",
+            "0:a:0",
+            "-vn",
+            "-c:a",
+            codec,
+            "-b:a",
+            &format!("{bit_rate}k"),
+            "-f",
+            format,
+            "pipe:1",
+        ])
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .kill_on_drop(true);
+    Ok(command.spawn()?)
+}
+
+/// Starts packaging a video as HLS into `dir`, scaled to `height` with `video_bit_rate` and
+/// `audio_bit_rate` kbps and keyframes every `segment_seconds`, so every rendition cuts its
+/// segments at the same times. The playlist, `index.m3u8`, grows as segments are written.
+pub fn package_hls(
+    path: impl AsRef<Path>,
+    dir: impl AsRef<Path>,
+    height: u32,
+    video_bit_rate: u32,
+    audio_bit_rate: u32,
+    segment_seconds: u32,
+) -> Result<tokio::process::Child> {
+    let dir = dir.as_ref();
+    let mut command = tokio::process::Command::new(FFMPEG);
+    command
+        .args(["-nostdin", "-loglevel", "error", "-i"])
+        .arg(path.as_ref())
+        .args([
+            "-map",
+            "0:v:0",
+            "-map",
+            "0:a:0?",
+            "-vf",
+            &format!("scale=-2:{height}"),
+            "-c:v",
+            "libx264",
+            "-preset",
+            "veryfast",
+            "-b:v",
+            &format!("{video_bit_rate}k"),
+            "-maxrate",
+            &format!("{}k", video_bit_rate * 107 / 100),
+            "-bufsize",
+            &format!("{}k", video_bit_rate * 3 / 2),
+            "-force_key_frames",
+            &format!("expr:gte(t,n_forced*{segment_seconds})"),
+            "-sc_threshold",
+            "0",
+            "-c:a",
+            "aac",
+            "-ac",
+            "2",
+            "-b:a",
+            &format!("{audio_bit_rate}k"),
+            "-f",
+            "hls",
+            "-hls_time",
+            &segment_seconds.to_string(),
+            "-hls_playlist_type",
+            "event",
+            "-hls_segment_filename",
+        ])
+        .arg(dir.join("segment_%05d.ts"))
+        .arg(dir.join("index.m3u8"))
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true);
+    Ok(command.spawn()?)
+}
+
+/// Transcodes the first video and audio stream of `input` into `output`, whose extension
+/// picks the container. Videos taller than `max_height` are scaled down to it.
+pub async fn transcode_video(
+    input: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    video_encoder: &str,
+    video_args: &[String],
+    max_height: Option<u32>,
+    audio_encoder: &str,
+    audio_bit_rate: u32,
+) -> Result<()> {
+    let mut command = tokio::process::Command::new(FFMPEG);
+    command
+        .args(["-nostdin", "-loglevel", "error", "-y", "-i"])
+        .arg(input.as_ref())
+        .args(["-map", "0:v:0", "-map", "0:a:0?", "-c:v", video_encoder])
+        .args(video_args);
+    if let Some(max_height) = max_height {
+        command.args(["-vf", &format!("scale=-2:min(ih\\,{max_height})")]);
+    }
+    command
+        .args([
+            "-c:a",
+            audio_encoder,
+            "-b:a",
+            &format!("{audio_bit_rate}k"),
+            "-map_metadata",
+            "0",
+        ])
+        .arg(output.as_ref());
+    let output = command.output().await?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(Error::FFM(
+            String::from_utf8(output.stderr).unwrap_or("utf error".to_string()),
+        ))
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(number: Option<String>) -> Option<T> {
+    number?.parse().ok()
+}
+
+/// `30000/1001` to frames per second; `0/0` means unknown.
+fn parse_rate(rate: &str) -> Option<f64> {
+    let (numerator, denominator) = rate.split_once('/')?;
+    let rate = numerator.parse::<f64>().ok()? / denominator.parse::<f64>().ok()?;
+    (rate.is_finite() && rate > 0.0).then_some(rate)
+}
+
+#[test]
+fn probe_output() {
+    let json = br#"{
+        "streams": [
+            {"index": 0, "codec_name": "h264", "codec_type": "video", "profile": "High",
+             "width": 1920, "height": 1080, "r_frame_rate": "30000/1001",
+             "avg_frame_rate": "30000/1001", "bit_rate": "4000000",
+             "disposition": {"default": 1, "attached_pic": 0}, "tags": {"language": "und"}},
+            {"index": 1, "codec_name": "aac", "codec_type": "audio", "sample_rate": "48000",
+             "channels": 2, "channel_layout": "stereo", "bit_rate": "128000",
+             "r_frame_rate": "0/0", "avg_frame_rate": "0/0",
+             "tags": {"language": "eng", "TITLE": "Other", "ALBUM": "From the stream"}},
+            {"index": 2, "codec_name": "mjpeg", "codec_type": "video", "width": 320,
+             "height": 180, "disposition": {"attached_pic": 1}}
+        ],
+        "format": {"format_name": "mov,mp4,m4a,3gp,3g2,mj2", "duration": "634.550000",
+                   "bit_rate": "4130000", "tags": {"title": "Song"}},
+        "chapters": [
+            {"id": 0, "time_base": "1/1000", "start": 0, "start_time": "0.000000",
+             "end": 90000, "end_time": "90.000000", "tags": {"title": "Intro"}},
+            {"id": 1, "time_base": "1/1000", "start": 90000, "start_time": "90.000000",
+             "end": 634550, "end_time": "634.550000"}
+        ]
+    }"#;
+    let probe = parse_probe(json).unwrap();
+    assert_eq!(probe.container, "mov,mp4,m4a,3gp,3g2,mj2");
+    assert_eq!(probe.duration, Some(634.55));
+    assert_eq!(probe.bit_rate, Some(4130000));
+    assert_eq!(probe.streams.len(), 2);
+    assert!(probe.cover_art);
+    assert_eq!(probe.tags["title"], "Song");
+    assert_eq!(probe.tags["album"], "From the stream");
+    let video = &probe.streams[0];
+    assert_eq!((video.width, video.height), (Some(1920), Some(1080)));
+    assert!((video.frame_rate.unwrap() - 29.97).abs() < 0.01);
+    assert_eq!(video.language, None);
+    let audio = &probe.streams[1];
+    assert_eq!(audio.codec_type, "audio");
+    assert_eq!(audio.frame_rate, None);
+    assert_eq!(audio.sample_rate, Some(48000));
+    assert_eq!(audio.channels, Some(2));
+    assert_eq!(audio.language.as_deref(), Some("eng"));
+    assert_eq!(
+        probe.chapters,
+        vec![
+            ProbeChapter {
+                start: 0.0,
+                end: 90.0,
+                title: Some("Intro".to_string())
+            },
+            ProbeChapter {
+                start: 90.0,
+                end: 634.55,
+                title: None
+            },
+        ]
+    );
+}
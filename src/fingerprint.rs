@@ -1,5 +1,9 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
 use image::DynamicImage;
 use image_hasher::{HashAlg, Hasher, HasherConfig};
+
 pub struct Fingerprint {
     hasher: Hasher,
 }
@@ -27,3 +31,52 @@ impl Fingerprint {
         u64::from_be_bytes(hash_num)
     }
 }
+
+/// Number of differing bits between two fingerprints; `0` for the same picture, small for
+/// resized or recompressed copies.
+pub fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Fingerprints of the library's images, kept in memory so looking for copies of a picture
+/// does not read and parse every fingerprint from the database.
+#[derive(Clone, Default)]
+pub struct FingerprintIndex {
+    fingerprints: Arc<RwLock<HashMap<i64, u64>>>,
+}
+
+impl FingerprintIndex {
+    /// Builds the index over stored hex fingerprints, see
+    /// [`Database::image_fingerprints`](crate::database::Database::image_fingerprints).
+    pub fn new(fingerprints: Vec<(i64, String)>) -> Self {
+        let fingerprints = fingerprints
+            .into_iter()
+            .filter_map(|(image_id, hex)| Some((image_id, u64::from_str_radix(&hex, 16).ok()?)))
+            .collect();
+        Self {
+            fingerprints: Arc::new(RwLock::new(fingerprints)),
+        }
+    }
+
+    pub fn insert(&self, image_id: i64, fingerprint: u64) {
+        self.fingerprints
+            .write()
+            .unwrap()
+            .insert(image_id, fingerprint);
+    }
+
+    pub fn get(&self, image_id: i64) -> Option<u64> {
+        self.fingerprints.read().unwrap().get(&image_id).copied()
+    }
+
+    /// Images at most `max_distance` bits away from `fingerprint`.
+    pub fn near(&self, fingerprint: u64, max_distance: u32) -> Vec<i64> {
+        self.fingerprints
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, other)| distance(fingerprint, **other) <= max_distance)
+            .map(|(image_id, _)| *image_id)
+            .collect()
+    }
+}
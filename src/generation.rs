@@ -0,0 +1,400 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::metadata::png_chunks;
+
+/// Links followed from one ComfyUI node to the next before giving up on a looping graph.
+const MAX_DEPTH: usize = 32;
+
+/// The program that wrote the parameters, stored as text in `generation_params.source`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum GenerationSource {
+    /// The AUTOMATIC1111 web UI and its forks, which write a `parameters` text block.
+    A1111,
+    /// ComfyUI, which writes its node graph as JSON.
+    ComfyUi,
+}
+
+/// Stable Diffusion settings an image was generated with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GenerationParams {
+    pub source: GenerationSource,
+    pub prompt: Option<String>,
+    pub negative_prompt: Option<String>,
+    pub seed: Option<i64>,
+    pub sampler: Option<String>,
+    pub steps: Option<i64>,
+    pub cfg_scale: Option<f64>,
+    /// Checkpoint name without folder or extension.
+    pub model: Option<String>,
+    pub model_hash: Option<String>,
+    pub loras: Vec<Lora>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Lora {
+    pub name: String,
+    pub weight: Option<f64>,
+}
+
+impl GenerationParams {
+    fn new(source: GenerationSource) -> Self {
+        Self {
+            source,
+            prompt: None,
+            negative_prompt: None,
+            seed: None,
+            sampler: None,
+            steps: None,
+            cfg_scale: None,
+            model: None,
+            model_hash: None,
+            loras: Vec::new(),
+        }
+    }
+}
+
+/// Generation parameters embedded in an image: the `parameters` (A1111) or `prompt`
+/// (ComfyUI) text chunks of a PNG, or an A1111 EXIF user comment as in its WebP and JPEG
+/// output. Compressed PNG text chunks are not read; neither program writes them.
+pub fn extract(data: &[u8]) -> Option<GenerationParams> {
+    let texts = png_texts(data);
+    if let Some(text) = texts.get("parameters") {
+        return Some(parse_a1111(text));
+    }
+    if let Some(params) = texts.get("prompt").and_then(|graph| parse_comfyui(graph)) {
+        return Some(params);
+    }
+    // Cameras write user comments too, only take ones that look like A1111's.
+    exif_user_comment(data)
+        .filter(|comment| comment.contains("Steps: "))
+        .map(|comment| parse_a1111(&comment))
+}
+
+/// Keywords and text of the uncompressed `tEXt` and `iTXt` chunks of a PNG.
+fn png_texts(data: &[u8]) -> HashMap<String, String> {
+    let mut texts = HashMap::new();
+    for (kind, chunk) in png_chunks(data) {
+        match &kind {
+            b"tEXt" => {
+                if let Some((keyword, text)) = split_nul(chunk) {
+                    // tEXt is Latin-1, whose code points are its bytes.
+                    texts.insert(latin1(keyword), latin1(text));
+                }
+            }
+            b"iTXt" => {
+                let parsed = split_nul(chunk).and_then(|(keyword, rest)| {
+                    // Compressed text, flag `1`, is skipped.
+                    let [0, _, rest @ ..] = rest else {
+                        return None;
+                    };
+                    let (_language, rest) = split_nul(rest)?;
+                    let (_translated, text) = split_nul(rest)?;
+                    Some((latin1(keyword), String::from_utf8_lossy(text).into_owned()))
+                });
+                if let Some((keyword, text)) = parsed {
+                    texts.insert(keyword, text);
+                }
+            }
+            _ => {}
+        }
+    }
+    texts
+}
+
+fn split_nul(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let nul = bytes.iter().position(|&byte| byte == 0)?;
+    Some((&bytes[..nul], &bytes[nul + 1..]))
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&byte| char::from(byte)).collect()
+}
+
+/// The EXIF user comment, which starts with an 8 byte character code.
+fn exif_user_comment(data: &[u8]) -> Option<String> {
+    let exif = exif::Reader::new()
+        .read_from_container(&mut Cursor::new(data))
+        .ok()?;
+    let field = exif.get_field(exif::Tag::UserComment, exif::In::PRIMARY)?;
+    let exif::Value::Undefined(bytes, _) = &field.value else {
+        return None;
+    };
+    if bytes.len() < 8 {
+        return None;
+    }
+    let (code, text) = bytes.split_at(8);
+    let text = match code {
+        b"UNICODE\0" => {
+            // The byte order is not recorded; ASCII text has its zero bytes first in big
+            // endian.
+            let big_endian = text.first() == Some(&0);
+            let units: Vec<u16> = text
+                .chunks_exact(2)
+                .map(|pair| match big_endian {
+                    true => u16::from_be_bytes([pair[0], pair[1]]),
+                    false => u16::from_le_bytes([pair[0], pair[1]]),
+                })
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        _ => String::from_utf8_lossy(text).into_owned(),
+    };
+    Some(text.trim_end_matches('\0').to_string())
+}
+
+/// Parses A1111's format: the prompt, an optional `Negative prompt: ` line and a last line
+/// of `Key: value` settings starting with `Steps: `.
+fn parse_a1111(text: &str) -> GenerationParams {
+    let mut params = GenerationParams::new(GenerationSource::A1111);
+    let lines: Vec<&str> = text.lines().collect();
+    let settings = lines
+        .iter()
+        .rposition(|line| line.starts_with("Steps: "))
+        .unwrap_or(lines.len());
+    let (mut prompt, mut negative) = (Vec::new(), Vec::new());
+    for line in &lines[..settings] {
+        match line.strip_prefix("Negative prompt:") {
+            Some(rest) => negative.push(rest.trim_start()),
+            None if negative.is_empty() => prompt.push(*line),
+            None => negative.push(*line),
+        }
+    }
+    params.prompt = non_empty(prompt.join("\n"));
+    params.negative_prompt = non_empty(negative.join("\n"));
+    params.loras = prompt_loras(params.prompt.as_deref().unwrap_or_default());
+    for setting in lines
+        .get(settings)
+        .map_or(Vec::new(), |line| split_settings(line))
+    {
+        let Some((key, value)) = setting.split_once(':') else {
+            continue;
+        };
+        let value = value.trim().trim_matches('"');
+        match key.trim() {
+            "Steps" => params.steps = value.parse().ok(),
+            "Sampler" => params.sampler = non_empty(value.to_string()),
+            "CFG scale" => params.cfg_scale = value.parse().ok(),
+            "Seed" => params.seed = value.parse().ok(),
+            "Model" => params.model = non_empty(value.to_string()),
+            "Model hash" => params.model_hash = non_empty(value.to_string()),
+            _ => {}
+        }
+    }
+    params
+}
+
+/// Splits a settings line on commas outside of double quotes.
+fn split_settings(line: &str) -> Vec<&str> {
+    let mut settings = Vec::new();
+    let (mut start, mut quoted) = (0, false);
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                settings.push(line[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    settings.push(line[start..].trim());
+    settings
+}
+
+/// The `<lora:name:weight>` extra networks of an A1111 prompt.
+fn prompt_loras(prompt: &str) -> Vec<Lora> {
+    prompt
+        .split("<lora:")
+        .skip(1)
+        .filter_map(|rest| {
+            let network = &rest[..rest.find('>')?];
+            let (name, weight) = match network.split_once(':') {
+                Some((name, weight)) => (name, weight.split(':').next()?.trim().parse().ok()),
+                None => (network, None),
+            };
+            Some(Lora {
+                name: name.trim().to_string(),
+                weight,
+            })
+        })
+        .collect()
+}
+
+/// Parses a ComfyUI API graph, `{"id": {"class_type": .., "inputs": {..}}}`, reading the
+/// settings off its first sampler node and following its links to the prompts and the
+/// checkpoint. `None` when the graph has no sampler.
+fn parse_comfyui(graph: &str) -> Option<GenerationParams> {
+    let nodes: Map<String, Value> = serde_json::from_str(graph).ok()?;
+    let sampler = nodes.values().find(|node| {
+        node["class_type"]
+            .as_str()
+            .is_some_and(|class| class.starts_with("KSampler"))
+    })?;
+    let inputs = &sampler["inputs"];
+    let mut params = GenerationParams::new(GenerationSource::ComfyUi);
+    params.seed = inputs["seed"]
+        .as_i64()
+        .or_else(|| inputs["noise_seed"].as_i64());
+    params.steps = inputs["steps"].as_i64();
+    params.cfg_scale = inputs["cfg"].as_f64();
+    params.sampler = inputs["sampler_name"].as_str().map(str::to_string);
+    params.prompt = comfyui_text(&nodes, &inputs["positive"], 0);
+    params.negative_prompt = comfyui_text(&nodes, &inputs["negative"], 0);
+    // The model input runs back through the LoRA loaders to the checkpoint loader.
+    let mut link = &inputs["model"];
+    for _ in 0..MAX_DEPTH {
+        let Some(inputs) = linked_node(&nodes, link).map(|node| &node["inputs"]) else {
+            break;
+        };
+        if let Some(name) = inputs["lora_name"].as_str() {
+            params.loras.push(Lora {
+                name: file_stem(name),
+                weight: inputs["strength_model"].as_f64(),
+            });
+        }
+        if let Some(name) = inputs["ckpt_name"]
+            .as_str()
+            .or_else(|| inputs["unet_name"].as_str())
+        {
+            params.model = Some(file_stem(name));
+            break;
+        }
+        link = &inputs["model"];
+    }
+    params.loras.reverse();
+    Some(params)
+}
+
+/// The prompt text a conditioning link leads to, going through nodes that pass
+/// conditioning on.
+fn comfyui_text(nodes: &Map<String, Value>, link: &Value, depth: usize) -> Option<String> {
+    let inputs = &linked_node(nodes, link)?["inputs"];
+    if let Some(text) = inputs["text"]
+        .as_str()
+        .or_else(|| inputs["text_g"].as_str())
+    {
+        return non_empty(text.to_string());
+    }
+    if depth >= MAX_DEPTH {
+        return None;
+    }
+    ["conditioning", "conditioning_1", "conditioning_to"]
+        .iter()
+        .find_map(|input| comfyui_text(nodes, &inputs[*input], depth + 1))
+}
+
+/// The node a `["id", output]` link points at.
+fn linked_node<'a>(nodes: &'a Map<String, Value>, link: &Value) -> Option<&'a Value> {
+    let id = match &link[0] {
+        Value::String(id) => id.clone(),
+        Value::Number(id) => id.to_string(),
+        _ => return None,
+    };
+    nodes.get(&id)
+}
+
+/// `SDXL/model.safetensors` to `model`, the way A1111 names checkpoints.
+fn file_stem(path: &str) -> String {
+    let name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+    match name.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => stem.to_string(),
+        _ => name.to_string(),
+    }
+}
+
+fn non_empty(text: String) -> Option<String> {
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+#[test]
+fn a1111_parameters() {
+    let text = "masterpiece, 1girl, <lora:add_detail:0.8>, \"quoted\"\nsecond line\n\
+        Negative prompt: lowres, bad hands\n\
+        Steps: 28, Sampler: DPM++ 2M Karras, CFG scale: 7.5, Seed: 1234567890, Size: 512x768, \
+        Model hash: 6ce0161689, Model: v1-5-pruned-emaonly, Lora hashes: \"add_detail: 7c6bad76eb54\", \
+        Version: v1.6.0";
+    let params = parse_a1111(text);
+    assert_eq!(params.source, GenerationSource::A1111);
+    assert_eq!(
+        params.prompt.as_deref(),
+        Some("masterpiece, 1girl, <lora:add_detail:0.8>, \"quoted\"\nsecond line")
+    );
+    assert_eq!(params.negative_prompt.as_deref(), Some("lowres, bad hands"));
+    assert_eq!(params.steps, Some(28));
+    assert_eq!(params.sampler.as_deref(), Some("DPM++ 2M Karras"));
+    assert_eq!(params.cfg_scale, Some(7.5));
+    assert_eq!(params.seed, Some(1234567890));
+    assert_eq!(params.model.as_deref(), Some("v1-5-pruned-emaonly"));
+    assert_eq!(params.model_hash.as_deref(), Some("6ce0161689"));
+    assert_eq!(
+        params.loras,
+        vec![Lora {
+            name: "add_detail".to_string(),
+            weight: Some(0.8)
+        }]
+    );
+    assert_eq!(
+        parse_a1111("just a prompt").prompt.as_deref(),
+        Some("just a prompt")
+    );
+}
+
+#[test]
+fn comfyui_graph() {
+    let graph = r#"{
+        "3": {"class_type": "KSampler", "inputs": {"seed": 42, "steps": 20, "cfg": 8.0,
+            "sampler_name": "euler", "scheduler": "normal", "denoise": 1.0,
+            "model": ["10", 0], "positive": ["6", 0], "negative": ["7", 0], "latent_image": ["5", 0]}},
+        "4": {"class_type": "CheckpointLoaderSimple", "inputs": {"ckpt_name": "SDXL/juggernaut.safetensors"}},
+        "6": {"class_type": "CLIPTextEncode", "inputs": {"text": "a castle at dusk", "clip": ["10", 1]}},
+        "7": {"class_type": "CLIPTextEncode", "inputs": {"text": "blurry", "clip": ["10", 1]}},
+        "10": {"class_type": "LoraLoader", "inputs": {"lora_name": "detail.safetensors",
+            "strength_model": 0.6, "strength_clip": 1.0, "model": ["4", 0], "clip": ["4", 1]}}
+    }"#;
+    let params = parse_comfyui(graph).unwrap();
+    assert_eq!(params.source, GenerationSource::ComfyUi);
+    assert_eq!(params.seed, Some(42));
+    assert_eq!(params.steps, Some(20));
+    assert_eq!(params.cfg_scale, Some(8.0));
+    assert_eq!(params.sampler.as_deref(), Some("euler"));
+    assert_eq!(params.prompt.as_deref(), Some("a castle at dusk"));
+    assert_eq!(params.negative_prompt.as_deref(), Some("blurry"));
+    assert_eq!(params.model.as_deref(), Some("juggernaut"));
+    assert_eq!(
+        params.loras,
+        vec![Lora {
+            name: "detail".to_string(),
+            weight: Some(0.6)
+        }]
+    );
+    assert!(parse_comfyui(r#"{"1": {"class_type": "LoadImage", "inputs": {}}}"#).is_none());
+}
+
+#[test]
+fn png_text_chunks() {
+    fn chunk(kind: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(kind);
+        chunk.extend_from_slice(data);
+        chunk.extend_from_slice(&[0; 4]);
+        chunk
+    }
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    png.extend(chunk(b"IHDR", &[0; 13]));
+    png.extend(chunk(b"tEXt", b"parameters\0caf\xe9\nSteps: 20, Seed: 7"));
+    png.extend(chunk(b"iTXt", "Title\0\0\0en\0\0café".as_bytes()));
+    png.extend(chunk(b"IEND", b""));
+    let texts = png_texts(&png);
+    assert_eq!(texts["Title"], "café");
+    let params = extract(&png).unwrap();
+    assert_eq!(params.prompt.as_deref(), Some("café"));
+    assert_eq!(params.seed, Some(7));
+    assert!(extract(b"not an image").is_none());
+}
@@ -0,0 +1,351 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use log::{info, warn};
+use tokio::sync::Semaphore;
+
+use crate::ffmpeg;
+
+/// Seconds per segment, and between the keyframes every rendition shares.
+const SEGMENT_SECONDS: u32 = 6;
+/// Audio bitrate of every rendition in kbps.
+const AUDIO_BIT_RATE: u32 = 128;
+/// How long a request waits for the first segment of a rendition being packaged.
+const FIRST_SEGMENT_WAIT: Duration = Duration::from_secs(30);
+const POLL_EVERY: Duration = Duration::from_millis(250);
+/// Marks a rendition ffmpeg finished.
+const COMPLETE: &str = "complete";
+/// Touched whenever a rendition's playlist is asked for, for cleanup.
+const LAST_USED: &str = "last_used";
+const PLAYLIST: &str = "index.m3u8";
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("too many videos are being packaged")]
+    Busy,
+    #[error("unknown rendition {0}")]
+    UnknownRendition(String),
+    #[error("packaging failed: {0}")]
+    Failed(String),
+    #[error("the first segment is not ready yet")]
+    NotReady,
+    #[error(transparent)]
+    Ffmpeg(#[from] ffmpeg::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// One step of the bitrate ladder.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rendition {
+    pub name: &'static str,
+    pub height: u32,
+    /// kbps.
+    pub video_bit_rate: u32,
+}
+
+/// Renditions from best to worst.
+pub const LADDER: &[Rendition] = &[
+    Rendition {
+        name: "1080p",
+        height: 1080,
+        video_bit_rate: 5000,
+    },
+    Rendition {
+        name: "720p",
+        height: 720,
+        video_bit_rate: 2800,
+    },
+    Rendition {
+        name: "480p",
+        height: 480,
+        video_bit_rate: 1400,
+    },
+    Rendition {
+        name: "360p",
+        height: 360,
+        video_bit_rate: 800,
+    },
+];
+
+/// The renditions worth making of a video `height` pixels tall: those no taller than it, or
+/// the smallest one for a video smaller than all of them.
+pub fn renditions(height: u32) -> Vec<Rendition> {
+    let renditions: Vec<Rendition> = LADDER
+        .iter()
+        .filter(|rendition| rendition.height <= height)
+        .copied()
+        .collect();
+    match renditions.is_empty() {
+        true => LADDER.last().copied().into_iter().collect(),
+        false => renditions,
+    }
+}
+
+/// The master playlist of a `width` by `height` video, pointing at each rendition's
+/// playlist.
+pub fn master_playlist(width: u32, height: u32) -> String {
+    let mut playlist = String::from("#EXTM3U\n#EXT-X-VERSION:3\n");
+    for rendition in renditions(height) {
+        let scaled_width = (u64::from(width) * u64::from(rendition.height))
+            .checked_div(u64::from(height))
+            .unwrap_or(0);
+        // Rounded down to even, as `scale=-2` does.
+        let scaled_width = scaled_width / 2 * 2;
+        let bandwidth = (rendition.video_bit_rate + AUDIO_BIT_RATE) * 1100;
+        playlist.push_str(&format!(
+            "#EXT-X-STREAM-INF:BANDWIDTH={},RESOLUTION={}x{}\n{}/{}\n",
+            bandwidth, scaled_width, rendition.height, rendition.name, PLAYLIST
+        ));
+    }
+    playlist
+}
+
+/// A segment file name ffmpeg could have written, so requests cannot reach other files.
+pub fn is_segment_name(name: &str) -> bool {
+    name.strip_prefix("segment_")
+        .and_then(|rest| rest.strip_suffix(".ts"))
+        .is_some_and(|number| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()))
+}
+
+#[derive(Debug, Clone)]
+pub struct HlsConfig {
+    /// Where renditions are packaged, one directory per video and rendition.
+    pub dir: PathBuf,
+    /// Renditions packaged at once; more are turned away as busy.
+    pub max_running: usize,
+    /// Renditions nobody asked for in this long are deleted.
+    pub max_unused: Duration,
+}
+
+impl Default for HlsConfig {
+    fn default() -> Self {
+        Self {
+            dir: std::env::temp_dir().join("mediamon-hls"),
+            max_running: 2,
+            max_unused: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+/// Packages videos as HLS the first time a rendition is played and keeps the segments until
+/// the rendition goes unused.
+#[derive(Clone)]
+pub struct HlsPackager {
+    config: Arc<HlsConfig>,
+    permits: Arc<Semaphore>,
+    /// Rendition directories ffmpeg is writing to.
+    running: Arc<Mutex<HashSet<PathBuf>>>,
+}
+
+impl HlsPackager {
+    pub fn new(config: HlsConfig) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(config.max_running.max(1))),
+            config: Arc::new(config),
+            running: Arc::default(),
+        }
+    }
+
+    fn rendition_dir(&self, video_id: i64, rendition: &str) -> PathBuf {
+        self.config.dir.join(video_id.to_string()).join(rendition)
+    }
+
+    /// The playlist of a rendition of the video at `path`, packaging it if needed and waiting
+    /// for its first segment. Playlists of renditions still being packaged are event
+    /// playlists that players reload as they grow.
+    pub async fn playlist(
+        &self,
+        video_id: i64,
+        path: &str,
+        source_height: u32,
+        name: &str,
+    ) -> Result<PathBuf> {
+        let rendition = renditions(source_height)
+            .into_iter()
+            .find(|rendition| rendition.name == name)
+            .ok_or_else(|| Error::UnknownRendition(name.to_string()))?;
+        let dir = self.rendition_dir(video_id, name);
+        let playlist = dir.join(PLAYLIST);
+        if !tokio::fs::try_exists(dir.join(COMPLETE)).await? {
+            self.start(&dir, path, rendition).await?;
+        }
+        touch(&dir.join(LAST_USED)).await;
+        let waited = tokio::time::Instant::now();
+        loop {
+            if tokio::fs::try_exists(&playlist).await? {
+                return Ok(playlist);
+            }
+            if !self.running.lock().unwrap().contains(&dir) {
+                return Err(Error::Failed(format!("{name} of video {video_id}")));
+            }
+            if waited.elapsed() > FIRST_SEGMENT_WAIT {
+                return Err(Error::NotReady);
+            }
+            tokio::time::sleep(POLL_EVERY).await;
+        }
+    }
+
+    /// A finished or growing segment of a rendition.
+    pub fn segment(&self, video_id: i64, rendition: &str, name: &str) -> Option<PathBuf> {
+        let known = LADDER.iter().any(|known| known.name == rendition);
+        (known && is_segment_name(name)).then(|| self.rendition_dir(video_id, rendition).join(name))
+    }
+
+    /// Starts ffmpeg on a rendition unless it is already running. Left over output of a
+    /// packaging that did not finish is thrown away first.
+    async fn start(&self, dir: &Path, path: &str, rendition: Rendition) -> Result<()> {
+        {
+            let mut running = self.running.lock().unwrap();
+            if running.contains(dir) {
+                return Ok(());
+            }
+            running.insert(dir.to_path_buf());
+        }
+        let started = self.spawn(dir, path, rendition).await;
+        if started.is_err() {
+            self.running.lock().unwrap().remove(dir);
+        }
+        started
+    }
+
+    async fn spawn(&self, dir: &Path, path: &str, rendition: Rendition) -> Result<()> {
+        let permit = self
+            .permits
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| Error::Busy)?;
+        if tokio::fs::try_exists(dir).await? {
+            tokio::fs::remove_dir_all(dir).await?;
+        }
+        tokio::fs::create_dir_all(dir).await?;
+        let child = ffmpeg::package_hls(
+            path,
+            dir,
+            rendition.height,
+            rendition.video_bit_rate,
+            AUDIO_BIT_RATE,
+            SEGMENT_SECONDS,
+        )?;
+        info!("packaging {}", dir.display());
+        let running = self.running.clone();
+        let dir = dir.to_path_buf();
+        tokio::spawn(async move {
+            let finished = match child.wait_with_output().await {
+                Ok(output) if output.status.success() => {
+                    tokio::fs::write(dir.join(COMPLETE), b"").await.is_ok()
+                }
+                Ok(output) => {
+                    warn!(
+                        "packaging {} failed: {}",
+                        dir.display(),
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    );
+                    false
+                }
+                Err(err) => {
+                    warn!("packaging {} failed: {}", dir.display(), err);
+                    false
+                }
+            };
+            if !finished {
+                tokio::fs::remove_dir_all(&dir).await.ok();
+            }
+            running.lock().unwrap().remove(&dir);
+            drop(permit);
+        });
+        Ok(())
+    }
+
+    /// Deletes renditions unused for longer than the configured age, and unfinished ones
+    /// nothing is writing to, such as those cut short by a restart. Returns how many went.
+    pub async fn cleanup(&self) -> Result<usize> {
+        let mut removed = 0;
+        let Ok(mut videos) = tokio::fs::read_dir(&self.config.dir).await else {
+            return Ok(0);
+        };
+        let now = SystemTime::now();
+        while let Some(video) = videos.next_entry().await? {
+            if !video.file_type().await?.is_dir() {
+                continue;
+            }
+            let mut renditions = tokio::fs::read_dir(video.path()).await?;
+            let mut kept = 0;
+            while let Some(rendition) = renditions.next_entry().await? {
+                let dir = rendition.path();
+                if self.running.lock().unwrap().contains(&dir) {
+                    kept += 1;
+                    continue;
+                }
+                let complete = tokio::fs::try_exists(dir.join(COMPLETE)).await?;
+                let last_used = match tokio::fs::metadata(dir.join(LAST_USED)).await {
+                    Ok(metadata) => metadata.modified()?,
+                    Err(_) => rendition.metadata().await?.modified()?,
+                };
+                let unused = now.duration_since(last_used).unwrap_or_default();
+                if complete && unused <= self.config.max_unused {
+                    kept += 1;
+                    continue;
+                }
+                tokio::fs::remove_dir_all(&dir).await?;
+                removed += 1;
+            }
+            if kept == 0 {
+                tokio::fs::remove_dir(video.path()).await.ok();
+            }
+        }
+        Ok(removed)
+    }
+}
+
+async fn touch(path: &Path) {
+    if let Err(err) = tokio::fs::write(path, b"").await {
+        warn!("failed to touch {}: {}", path.display(), err);
+    }
+}
+
+#[test]
+fn master_playlists() {
+    assert_eq!(
+        renditions(1080)
+            .iter()
+            .map(|rendition| rendition.name)
+            .collect::<Vec<_>>(),
+        vec!["1080p", "720p", "480p", "360p"]
+    );
+    assert_eq!(renditions(240), vec![LADDER[3]]);
+    assert_eq!(
+        master_playlist(1280, 720),
+        "#EXTM3U\n#EXT-X-VERSION:3\n\
+         #EXT-X-STREAM-INF:BANDWIDTH=3220800,RESOLUTION=1280x720\n720p/index.m3u8\n\
+         #EXT-X-STREAM-INF:BANDWIDTH=1680800,RESOLUTION=852x480\n480p/index.m3u8\n\
+         #EXT-X-STREAM-INF:BANDWIDTH=1020800,RESOLUTION=640x360\n360p/index.m3u8\n"
+    );
+    assert!(is_segment_name("segment_00012.ts"));
+    assert!(!is_segment_name("segment_.ts"));
+    assert!(!is_segment_name("../index.m3u8"));
+}
+
+#[tokio::test]
+async fn cleanup_removes_unfinished_renditions() {
+    let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+    let packager = HlsPackager::new(HlsConfig {
+        dir: dir.clone(),
+        ..HlsConfig::default()
+    });
+    let finished = packager.rendition_dir(1, "720p");
+    let unfinished = packager.rendition_dir(1, "480p");
+    for rendition in [&finished, &unfinished] {
+        tokio::fs::create_dir_all(rendition).await.unwrap();
+        touch(&rendition.join(LAST_USED)).await;
+    }
+    touch(&finished.join(COMPLETE)).await;
+    assert_eq!(packager.cleanup().await.unwrap(), 1);
+    assert!(finished.exists());
+    assert!(!unfinished.exists());
+    tokio::fs::remove_dir_all(&dir).await.unwrap();
+}
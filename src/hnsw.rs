@@ -0,0 +1,367 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+/// Something with a distance to others of its kind; smaller is closer.
+pub trait Point {
+    fn distance(&self, other: &Self) -> f32;
+}
+
+/// Hierarchical navigable small world graph for approximate nearest neighbour search, as in
+/// Malkov and Yashunin (2016). Points are keyed by an `i64` id; replacing or removing one
+/// leaves a tombstone that is skipped in results, so ids can be reinserted freely. Once
+/// tombstones outnumber live points the graph is rebuilt without them.
+pub struct Hnsw<P> {
+    /// Links per node on the upper layers, twice that on the bottom layer.
+    m: usize,
+    ef_construction: usize,
+    nodes: Vec<Node<P>>,
+    ids: HashMap<i64, usize>,
+    entry: Option<usize>,
+}
+
+struct Node<P> {
+    id: i64,
+    point: P,
+    /// Neighbours on each layer the node is part of, bottom layer first.
+    links: Vec<Vec<usize>>,
+    removed: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Candidate {
+    distance: f32,
+    node: usize,
+}
+
+impl Eq for Candidate {}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance
+            .total_cmp(&other.distance)
+            .then(self.node.cmp(&other.node))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<P: Point> Default for Hnsw<P> {
+    fn default() -> Self {
+        Self::new(16, 100)
+    }
+}
+
+impl<P: Point> Hnsw<P> {
+    pub fn new(m: usize, ef_construction: usize) -> Self {
+        Self {
+            m: m.max(2),
+            ef_construction: ef_construction.max(1),
+            nodes: Vec::new(),
+            ids: HashMap::new(),
+            entry: None,
+        }
+    }
+
+    /// Number of live points.
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    pub fn get(&self, id: i64) -> Option<&P> {
+        self.ids.get(&id).map(|&node| &self.nodes[node].point)
+    }
+
+    pub fn insert(&mut self, id: i64, point: P) {
+        // Re-tagging reinserts every point, mostly unchanged.
+        if self.get(id).is_some_and(|old| old.distance(&point) == 0.0) {
+            return;
+        }
+        self.remove(id);
+        let node = self.nodes.len();
+        let level = level_for(id, self.m);
+        self.nodes.push(Node {
+            id,
+            point,
+            links: vec![Vec::new(); level + 1],
+            removed: false,
+        });
+        self.ids.insert(id, node);
+        let Some(entry) = self.entry else {
+            self.entry = Some(node);
+            return;
+        };
+        let top = self.nodes[entry].links.len() - 1;
+        let mut entries = vec![entry];
+        for layer in (level + 1..=top).rev() {
+            entries = self.search_layer(&self.nodes[node].point, &entries, 1, layer);
+        }
+        for layer in (0..=level.min(top)).rev() {
+            let found = self.search_layer(
+                &self.nodes[node].point,
+                &entries,
+                self.ef_construction,
+                layer,
+            );
+            let neighbours = self.select_neighbours(
+                &self.nodes[node].point,
+                found.clone(),
+                self.max_links(layer),
+            );
+            for &neighbour in &neighbours {
+                self.nodes[neighbour].links[layer].push(node);
+                self.prune(neighbour, layer);
+            }
+            self.nodes[node].links[layer] = neighbours;
+            entries = found;
+        }
+        if level > top {
+            self.entry = Some(node);
+        }
+    }
+
+    /// Hides a point from results. Returns false when there was none with that id.
+    pub fn remove(&mut self, id: i64) -> bool {
+        match self.ids.remove(&id) {
+            Some(node) => {
+                self.nodes[node].removed = true;
+                if self.nodes.len() > 2 * self.ids.len() {
+                    self.compact();
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Up to `k` live points closest to `query`, closest first, looking at `ef` candidates
+    /// per step of the bottom layer; a larger `ef` is slower but misses fewer neighbours.
+    /// Tombstones take up candidates too, so `ef` grows until `k` live points are found.
+    pub fn search(&self, query: &P, k: usize, ef: usize) -> Vec<(i64, f32)> {
+        let Some(entry) = self.entry else {
+            return Vec::new();
+        };
+        let mut entries = vec![entry];
+        for layer in (1..self.nodes[entry].links.len()).rev() {
+            entries = self.search_layer(query, &entries, 1, layer);
+        }
+        let mut ef = ef.max(k);
+        loop {
+            let found = self.search_layer(query, &entries, ef, 0);
+            let exhausted = found.len() < ef;
+            let live: Vec<usize> = found
+                .into_iter()
+                .filter(|&node| !self.nodes[node].removed)
+                .take(k)
+                .collect();
+            if live.len() == k || exhausted {
+                return live
+                    .into_iter()
+                    .map(|node| (self.nodes[node].id, query.distance(&self.nodes[node].point)))
+                    .collect();
+            }
+            ef *= 2;
+        }
+    }
+
+    /// Rebuilds the graph from the live points.
+    fn compact(&mut self) {
+        let nodes = std::mem::take(&mut self.nodes);
+        self.ids.clear();
+        self.entry = None;
+        for node in nodes.into_iter().filter(|node| !node.removed) {
+            self.insert(node.id, node.point);
+        }
+    }
+
+    fn max_links(&self, layer: usize) -> usize {
+        if layer == 0 {
+            self.m * 2
+        } else {
+            self.m
+        }
+    }
+
+    /// Keeps only the closest links of a node that has too many.
+    fn prune(&mut self, node: usize, layer: usize) {
+        let max_links = self.max_links(layer);
+        if self.nodes[node].links[layer].len() <= max_links {
+            return;
+        }
+        let point = &self.nodes[node].point;
+        let mut links: Vec<Candidate> = self.nodes[node].links[layer]
+            .iter()
+            .map(|&link| Candidate {
+                distance: point.distance(&self.nodes[link].point),
+                node: link,
+            })
+            .collect();
+        links.sort_unstable();
+        let links = links.into_iter().map(|link| link.node).collect();
+        self.nodes[node].links[layer] = self.select_neighbours(point, links, max_links);
+    }
+
+    /// Picks up to `max_links` of `candidates`, which are sorted closest to `point` first,
+    /// preferring ones that are not closer to an already picked neighbour than to `point`.
+    /// Spreading links out like this keeps outliers and separate clusters reachable.
+    fn select_neighbours(&self, point: &P, candidates: Vec<usize>, max_links: usize) -> Vec<usize> {
+        let mut picked: Vec<usize> = Vec::with_capacity(max_links);
+        let mut skipped = Vec::new();
+        for candidate in candidates {
+            if picked.len() == max_links {
+                break;
+            }
+            let candidate_point = &self.nodes[candidate].point;
+            let distance = candidate_point.distance(point);
+            let diverse = picked
+                .iter()
+                .all(|&other| candidate_point.distance(&self.nodes[other].point) > distance);
+            if diverse {
+                picked.push(candidate);
+            } else {
+                skipped.push(candidate);
+            }
+        }
+        let missing = max_links - picked.len();
+        picked.extend(skipped.into_iter().take(missing));
+        picked
+    }
+
+    /// The `ef` nodes closest to `query` reachable on one layer from `entries`, closest first.
+    fn search_layer(&self, query: &P, entries: &[usize], ef: usize, layer: usize) -> Vec<usize> {
+        let mut visited: HashSet<usize> = entries.iter().copied().collect();
+        let mut candidates = BinaryHeap::new();
+        let mut found = BinaryHeap::new();
+        for &node in entries {
+            let candidate = Candidate {
+                distance: query.distance(&self.nodes[node].point),
+                node,
+            };
+            candidates.push(Reverse(candidate));
+            found.push(candidate);
+        }
+        while found.len() > ef {
+            found.pop();
+        }
+        while let Some(Reverse(closest)) = candidates.pop() {
+            let furthest = found
+                .peek()
+                .map_or(f32::INFINITY, |c: &Candidate| c.distance);
+            if closest.distance > furthest && found.len() >= ef {
+                break;
+            }
+            for &neighbour in self.nodes[closest.node]
+                .links
+                .get(layer)
+                .into_iter()
+                .flatten()
+            {
+                if !visited.insert(neighbour) {
+                    continue;
+                }
+                let candidate = Candidate {
+                    distance: query.distance(&self.nodes[neighbour].point),
+                    node: neighbour,
+                };
+                let furthest = found
+                    .peek()
+                    .map_or(f32::INFINITY, |c: &Candidate| c.distance);
+                if found.len() < ef || candidate.distance < furthest {
+                    candidates.push(Reverse(candidate));
+                    found.push(candidate);
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+        found
+            .into_sorted_vec()
+            .into_iter()
+            .map(|candidate| candidate.node)
+            .collect()
+    }
+}
+
+/// Draws a node's top layer from the usual exponential distribution, seeded by its id so
+/// rebuilding the index from the same points gives the same graph.
+fn level_for(id: i64, m: usize) -> usize {
+    let mut x = (id as u64).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^= x >> 31;
+    let uniform = ((x >> 11) as f64 + 1.0) / (1u64 << 53) as f64;
+    (-uniform.ln() / (m as f64).ln()).floor() as usize
+}
+
+#[cfg(test)]
+impl Point for [f32; 2] {
+    fn distance(&self, other: &Self) -> f32 {
+        (self[0] - other[0]).hypot(self[1] - other[1])
+    }
+}
+
+#[test]
+fn finds_nearest_neighbours() {
+    let mut index = Hnsw::new(4, 32);
+    for x in 0..30 {
+        for y in 0..30 {
+            index.insert(x * 30 + y, [x as f32, y as f32]);
+        }
+    }
+    assert_eq!(index.len(), 900);
+    let nearest = index.search(&[10.2, 20.05], 5, 32);
+    assert_eq!(nearest[0].0, 10 * 30 + 20);
+    let mut ids: Vec<i64> = nearest.iter().map(|(id, _)| *id).collect();
+    ids.sort();
+    assert_eq!(
+        ids,
+        vec![
+            9 * 30 + 20,
+            10 * 30 + 19,
+            10 * 30 + 20,
+            10 * 30 + 21,
+            11 * 30 + 20
+        ]
+    );
+
+    index.remove(10 * 30 + 20);
+    assert_eq!(index.search(&[10.2, 20.05], 1, 32)[0].0, 11 * 30 + 20);
+    index.insert(10 * 30 + 20, [100.0, 100.0]);
+    assert_eq!(index.search(&[99.0, 99.0], 1, 32)[0].0, 10 * 30 + 20);
+    assert_eq!(index.len(), 900);
+}
+
+#[test]
+fn reinserts_do_not_pile_up() {
+    let mut index = Hnsw::new(4, 32);
+    for id in 0..200 {
+        index.insert(id, [id as f32, 0.0]);
+    }
+    for id in 0..200 {
+        index.insert(id, [id as f32, 0.0]);
+    }
+    assert_eq!(index.nodes.len(), 200);
+    for round in 1..=3 {
+        for id in 0..200 {
+            index.insert(id, [id as f32, round as f32]);
+        }
+        assert_eq!(index.len(), 200);
+        assert!(index.nodes.len() <= 400);
+    }
+    // The points left of the query moved away, leaving tombstones among its candidates.
+    for id in 0..150 {
+        index.insert(id, [id as f32, 50.0]);
+    }
+    let nearest = index.search(&[152.0, 3.0], 10, 10);
+    let mut ids: Vec<i64> = nearest.iter().map(|(id, _)| *id).collect();
+    ids.sort();
+    assert_eq!(ids, (150..160).collect::<Vec<i64>>());
+}
@@ -0,0 +1,159 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+
+use log::warn;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::database::{self, Database};
+
+/// Hex SHA-256 of a file's bytes, the key cached scores are stored under.
+pub fn content_hash(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// [`content_hash`] of a file too large to read into memory at once.
+pub async fn file_content_hash(path: impl AsRef<std::path::Path>) -> tokio::io::Result<String> {
+    use tokio::io::AsyncReadExt;
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 1 << 20];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Raw model scores keyed by content hash and model version, so the same bytes never go
+/// through the same model twice. Scores are kept before thresholds are applied, so changing
+/// thresholds does not invalidate them.
+#[derive(Clone)]
+pub struct InferenceCache {
+    db: Database,
+    stats: Arc<Mutex<BTreeMap<String, CacheStats>>>,
+}
+
+/// Lookups since the server started.
+#[derive(Serialize, Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CacheReport {
+    pub model: String,
+    pub entries: i64,
+    pub bytes: i64,
+    pub hits: u64,
+    pub misses: u64,
+    /// Share of lookups that were hits, `None` before the first lookup.
+    pub hit_rate: Option<f64>,
+}
+
+impl InferenceCache {
+    pub fn new(db: Database) -> Self {
+        Self {
+            db,
+            stats: Arc::default(),
+        }
+    }
+
+    /// Cached scores, counting the lookup as a hit or a miss. A failed lookup is a miss.
+    pub async fn get(&self, content_hash: &str, model: &str) -> Option<Vec<f32>> {
+        let scores = match self.db.cached_scores(content_hash, model).await {
+            Ok(scores) => scores.map(|scores| decode_scores(&scores)),
+            Err(err) => {
+                warn!("inference cache lookup failed: {}", err);
+                None
+            }
+        };
+        let mut stats = self.stats.lock().unwrap();
+        let stats = stats.entry(model.to_string()).or_default();
+        match scores {
+            Some(_) => stats.hits += 1,
+            None => stats.misses += 1,
+        }
+        scores
+    }
+
+    /// Stores scores, only logging failures since the scores were already computed.
+    pub async fn put(&self, content_hash: &str, model: &str, scores: &[f32]) {
+        if let Err(err) = self
+            .db
+            .cache_scores(content_hash, model, &encode_scores(scores))
+            .await
+        {
+            warn!("failed to cache scores: {}", err);
+        }
+    }
+
+    /// Drops cached scores of `model`, or of every model, returning how many were dropped.
+    pub async fn invalidate(&self, model: Option<&str>) -> database::Result<u64> {
+        self.db.invalidate_cached_scores(model).await
+    }
+
+    /// Size and hit rate per model, covering models that were only looked up as well.
+    pub async fn report(&self) -> database::Result<Vec<CacheReport>> {
+        let usage: BTreeMap<String, (i64, i64)> = self
+            .db
+            .cached_scores_usage()
+            .await?
+            .into_iter()
+            .map(|row| (row.model().to_string(), (row.entries(), row.bytes())))
+            .collect();
+        let stats = self.stats.lock().unwrap().clone();
+        let models: BTreeSet<&String> = usage.keys().chain(stats.keys()).collect();
+        Ok(models
+            .into_iter()
+            .map(|model| {
+                let (entries, bytes) = usage.get(model).copied().unwrap_or_default();
+                let stats = stats.get(model).copied().unwrap_or_default();
+                CacheReport {
+                    model: model.clone(),
+                    entries,
+                    bytes,
+                    hits: stats.hits,
+                    misses: stats.misses,
+                    hit_rate: stats.hit_rate(),
+                }
+            })
+            .collect())
+    }
+}
+
+impl CacheStats {
+    fn hit_rate(&self) -> Option<f64> {
+        let lookups = self.hits + self.misses;
+        (lookups > 0).then(|| self.hits as f64 / lookups as f64)
+    }
+}
+
+/// Little endian `f32`s, much smaller than the same scores as JSON text.
+fn encode_scores(scores: &[f32]) -> Vec<u8> {
+    scores
+        .iter()
+        .flat_map(|score| score.to_le_bytes())
+        .collect()
+}
+
+fn decode_scores(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|chunk| f32::from_le_bytes(chunk.try_into().expect("chunks are 4 bytes")))
+        .collect()
+}
+
+#[test]
+fn scores_round_trip() {
+    let scores = [0.0, 0.25, 1.0, f32::MIN_POSITIVE];
+    assert_eq!(decode_scores(&encode_scores(&scores)), scores);
+    assert_eq!(
+        content_hash(b""),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+}
@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum JobStatus {
+    Running,
+    Completed,
+    Cancelled,
+    Failed,
+}
+
+/// Background jobs running in this process, so they can be cancelled. Their progress lives in
+/// the `job` table, which is what lets a job resume after a restart.
+#[derive(Clone, Default)]
+pub struct Jobs {
+    running: Arc<Mutex<HashMap<i64, CancellationToken>>>,
+}
+
+impl Jobs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Spawns the job built by `job`, handing it a token that fires on [`Jobs::cancel`]. Does
+    /// nothing and returns `None` when the job is already running here.
+    pub fn spawn<F, Fut>(&self, job_id: i64, job: F) -> Option<JoinHandle<()>>
+    where
+        F: FnOnce(CancellationToken) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let token = CancellationToken::new();
+        {
+            let mut running = self.running.lock().unwrap();
+            if running.contains_key(&job_id) {
+                return None;
+            }
+            running.insert(job_id, token.clone());
+        }
+        let job = job(token);
+        let running = self.running.clone();
+        Some(tokio::spawn(async move {
+            job.await;
+            running.lock().unwrap().remove(&job_id);
+        }))
+    }
+
+    /// Asks a job to stop after its current batch. Returns false when it is not running here.
+    pub fn cancel(&self, job_id: i64) -> bool {
+        match self.running.lock().unwrap().get(&job_id) {
+            Some(token) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_running(&self, job_id: i64) -> bool {
+        self.running.lock().unwrap().contains_key(&job_id)
+    }
+}
+
+#[tokio::test]
+async fn cancels_running_job() {
+    let jobs = Jobs::new();
+    let (started, running) = tokio::sync::oneshot::channel();
+    let job = jobs
+        .spawn(1, |token| async move {
+            started.send(()).ok();
+            token.cancelled().await;
+        })
+        .unwrap();
+    running.await.unwrap();
+    assert!(jobs.is_running(1));
+    assert!(jobs.spawn(1, |_| async {}).is_none());
+    assert!(!jobs.cancel(2));
+    assert!(jobs.cancel(1));
+    job.await.unwrap();
+    assert!(!jobs.is_running(1));
+}
@@ -1,7 +1,30 @@
 pub mod api;
+pub mod chapters;
 pub mod database;
 pub mod deepbooru;
+pub mod ffmpeg;
 pub mod fingerprint;
 pub mod gallerydl;
+pub mod generation;
+pub mod hls;
+pub mod hnsw;
+pub mod inference_cache;
+pub mod jobs;
+pub mod media;
+pub mod metadata;
+pub mod music;
+pub mod preprocess;
+pub mod renditions;
+pub mod retag;
+pub mod search;
+pub mod similarity;
 pub mod storage;
+pub mod streaming;
+pub mod subsonic;
+pub mod subtitles;
+pub mod tagger;
+pub mod tagging;
+pub mod transcode;
+pub mod wd14;
+pub mod workers;
 pub mod ytdlp;
@@ -1,11 +1,169 @@
-use log::info;
-use mediamon::{api::router, database::Database, deepbooru::Jarvis};
+use std::time::Duration;
+
+use log::{error, info};
+use mediamon::{
+    api::router,
+    database::Database,
+    fingerprint::FingerprintIndex,
+    hls::{HlsConfig, HlsPackager},
+    inference_cache::InferenceCache,
+    jobs::Jobs,
+    renditions::{RenditionWorker, VideoProfile},
+    retag::Retagger,
+    similarity::SimilarityIndex,
+    tagger::{self, ModelDescriptor},
+    tagging::{BatchConfig, TaggingService},
+    transcode::{TranscodeConfig, Transcoder},
+    workers::CpuPool,
+    ytdlp::{DownloadKind, DownloadProfile},
+};
 
 #[tokio::main]
 async fn main() {
-    let jarvis = Jarvis::new("deepdanbooru.onnx").unwrap();
+    let descriptors = match dotenv::var("TAGGERS") {
+        Ok(path) => ModelDescriptor::load_all(path).unwrap(),
+        Err(_) => vec![ModelDescriptor::deepdanbooru()],
+    };
     let db = Database::new().await.unwrap();
-    let router = router(jarvis, db);
+    let mut batch_config = BatchConfig::default();
+    if let Ok(max_batch) = dotenv::var("TAGGER_BATCH_SIZE") {
+        batch_config.max_batch = max_batch.parse().unwrap();
+    }
+    if let Ok(max_wait) = dotenv::var("TAGGER_BATCH_WAIT_MS") {
+        batch_config.max_wait = Duration::from_millis(max_wait.parse().unwrap());
+    }
+    let cpu_workers = match dotenv::var("CPU_WORKERS") {
+        Ok(cpu_workers) => cpu_workers.parse().unwrap(),
+        Err(_) => std::thread::available_parallelism().unwrap().get(),
+    };
+    let cpu_queue = match dotenv::var("CPU_QUEUE") {
+        Ok(cpu_queue) => cpu_queue.parse().unwrap(),
+        Err(_) => 32,
+    };
+    let cpu = CpuPool::new(cpu_workers, cpu_queue);
+    let inference_cache = InferenceCache::new(db.clone());
+    let mut taggers = Vec::with_capacity(descriptors.len());
+    for descriptor in descriptors {
+        let tagger = tagger::load(descriptor).unwrap();
+        let model = tagger.descriptor().id();
+        let tag_ids = match db.sync_model_labels(&model, tagger.labels()).await {
+            Ok(tag_ids) => tag_ids,
+            Err(err) => {
+                error!(
+                    "cannot load tagger {}: {}; a changed label file needs a new model version",
+                    model, err
+                );
+                std::process::exit(1);
+            }
+        };
+        taggers.push(TaggingService::new(
+            tagger,
+            tag_ids,
+            cpu.clone(),
+            batch_config,
+            inference_cache.clone(),
+        ));
+    }
+    let model = taggers[0].tagger().descriptor().id();
+    let embeddings = db.list_embeddings(&model).await.unwrap();
+    let similarity = cpu
+        .run(move || SimilarityIndex::build(&model, embeddings))
+        .await
+        .unwrap();
+    let fingerprints = FingerprintIndex::new(db.image_fingerprints().await.unwrap());
+    let retagger = Retagger::new(
+        db.clone(),
+        taggers.clone(),
+        cpu.clone(),
+        Jobs::new(),
+        similarity.clone(),
+        fingerprints.clone(),
+    );
+    retagger.resume().await.unwrap();
+    let mut transcode_config = TranscodeConfig::default();
+    if let Ok(max_running) = dotenv::var("TRANSCODE_LIMIT") {
+        transcode_config.max_running = max_running.parse().unwrap();
+    }
+    if let Ok(cache_dir) = dotenv::var("TRANSCODE_CACHE_DIR") {
+        std::fs::create_dir_all(&cache_dir).unwrap();
+        transcode_config.cache_dir = Some(cache_dir.into());
+    }
+    if let Ok(cache_after) = dotenv::var("TRANSCODE_CACHE_AFTER") {
+        transcode_config.cache_after = cache_after.parse().unwrap();
+    }
+    if let Ok(cache_mb) = dotenv::var("TRANSCODE_CACHE_MB") {
+        transcode_config.cache_max_bytes = cache_mb.parse::<u64>().unwrap() * 1024 * 1024;
+    }
+    let transcoder = Transcoder::new(transcode_config);
+    let mut hls_config = HlsConfig::default();
+    if let Ok(dir) = dotenv::var("HLS_DIR") {
+        hls_config.dir = dir.into();
+    }
+    if let Ok(max_running) = dotenv::var("HLS_LIMIT") {
+        hls_config.max_running = max_running.parse().unwrap();
+    }
+    if let Ok(hours) = dotenv::var("HLS_MAX_UNUSED_HOURS") {
+        hls_config.max_unused = Duration::from_secs(hours.parse::<u64>().unwrap() * 60 * 60);
+    }
+    let hls = HlsPackager::new(hls_config);
+    {
+        let hls = hls.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+            loop {
+                interval.tick().await;
+                match hls.cleanup().await {
+                    Ok(0) => {}
+                    Ok(removed) => info!("removed {} stale HLS renditions", removed),
+                    Err(err) => error!("HLS cleanup failed: {}", err),
+                }
+            }
+        });
+    }
+    let profiles = match dotenv::var("VIDEO_PROFILES") {
+        Ok(path) => VideoProfile::load_all(path).unwrap(),
+        Err(_) => vec![VideoProfile::h264_1080p()],
+    };
+    let rendition_dir = dotenv::var("RENDITION_DIR").unwrap_or("renditions".to_string());
+    let renditions = RenditionWorker::new(db.clone(), profiles, rendition_dir.into());
+    renditions.start().await.unwrap();
+    let download_profiles = match dotenv::var("DOWNLOAD_PROFILES") {
+        Ok(path) => DownloadProfile::load_all(path).unwrap(),
+        Err(_) => {
+            let mut profiles = vec![
+                DownloadProfile::video(),
+                DownloadProfile::music(),
+                DownloadProfile::video_h264_1080p(),
+            ];
+            if let Ok(languages) = dotenv::var("SUBTITLE_LANGUAGES") {
+                let languages: Vec<String> = languages
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|language| !language.is_empty())
+                    .map(String::from)
+                    .collect();
+                for profile in &mut profiles {
+                    if profile.kind == DownloadKind::Video {
+                        profile.subtitle_languages = languages.clone();
+                    }
+                }
+            }
+            profiles
+        }
+    };
+    let router = router(
+        taggers,
+        cpu,
+        db,
+        retagger,
+        inference_cache,
+        similarity,
+        fingerprints,
+        transcoder,
+        hls,
+        renditions,
+        download_profiles,
+    );
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
     info!("starting server...");
     axum::serve(listener, router).await.unwrap();
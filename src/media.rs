@@ -0,0 +1,142 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Kinds of library item that can carry tags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaKind {
+    Image,
+    Video,
+    /// Tracks, in the `track` table.
+    Music,
+    /// Video chapters, in the `video_chapter` table.
+    Chapter,
+}
+
+impl MediaKind {
+    pub(crate) fn table(self) -> &'static str {
+        match self {
+            MediaKind::Image => "image",
+            MediaKind::Video => "video",
+            MediaKind::Music => "track",
+            MediaKind::Chapter => "video_chapter",
+        }
+    }
+
+    pub(crate) fn primary_key(self) -> &'static str {
+        match self {
+            MediaKind::Image => "img_id",
+            MediaKind::Video => "video_id",
+            MediaKind::Music => "track_id",
+            MediaKind::Chapter => "chapter_id",
+        }
+    }
+
+    pub(crate) fn tag_table(self) -> &'static str {
+        match self {
+            MediaKind::Image => "image_tag",
+            MediaKind::Video => "video_tag",
+            MediaKind::Music => "track_tag",
+            MediaKind::Chapter => "video_chapter_tag",
+        }
+    }
+
+    /// Tags a person removed, which models may not attach again.
+    pub(crate) fn removed_tag_table(self) -> &'static str {
+        match self {
+            MediaKind::Image => "image_tag_removed",
+            MediaKind::Video => "video_tag_removed",
+            MediaKind::Music => "track_tag_removed",
+            MediaKind::Chapter => "video_chapter_tag_removed",
+        }
+    }
+
+    pub(crate) fn tag_foreign_key(self) -> &'static str {
+        match self {
+            MediaKind::Image => "image_id",
+            MediaKind::Video => "video_id",
+            MediaKind::Music => "track_id",
+            MediaKind::Chapter => "chapter_id",
+        }
+    }
+}
+
+/// Danbooru style tag categories. Models only produce general, character and rating tags, the
+/// rest exist for tags added by hand.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, sqlx::Type,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum TagCategory {
+    General,
+    Character,
+    Copyright,
+    Artist,
+    Meta,
+    Rating,
+}
+
+impl TagCategory {
+    /// Parses the `character` in `character:hatsune_miku`.
+    pub fn from_namespace(namespace: &str) -> Option<Self> {
+        match namespace {
+            "general" => Some(TagCategory::General),
+            "character" => Some(TagCategory::Character),
+            "copyright" => Some(TagCategory::Copyright),
+            "artist" => Some(TagCategory::Artist),
+            "meta" => Some(TagCategory::Meta),
+            "rating" => Some(TagCategory::Rating),
+            _ => None,
+        }
+    }
+}
+
+/// Where a media tag came from, stored as text in the `source` column.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TagSource {
+    /// A tagger, identified by model name and version.
+    Model(String),
+    /// Added by hand through the API.
+    User,
+    /// Copied from another booru or archive.
+    Import(String),
+}
+
+impl TagSource {
+    pub fn is_model(&self) -> bool {
+        matches!(self, TagSource::Model(_))
+    }
+
+    pub fn parse(source: &str) -> Option<Self> {
+        match source.split_once(':') {
+            Some(("model", model)) => Some(TagSource::Model(model.to_string())),
+            Some(("import", origin)) => Some(TagSource::Import(origin.to_string())),
+            None if source == "user" => Some(TagSource::User),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TagSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TagSource::Model(model) => write!(f, "model:{model}"),
+            TagSource::User => f.write_str("user"),
+            TagSource::Import(origin) => write!(f, "import:{origin}"),
+        }
+    }
+}
+
+#[test]
+fn tag_source_round_trip() {
+    for source in [
+        TagSource::Model("deepdanbooru".to_string()),
+        TagSource::User,
+        TagSource::Import("danbooru".to_string()),
+    ] {
+        assert_eq!(TagSource::parse(&source.to_string()), Some(source));
+    }
+    assert_eq!(TagSource::parse("model"), None);
+}
@@ -0,0 +1,294 @@
+use std::collections::BTreeMap;
+use std::io::Cursor;
+
+use chrono::NaiveDateTime;
+use image::{DynamicImage, ImageFormat};
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Technical details of an image file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageMetadata {
+    /// Size as shown, after the EXIF orientation is applied.
+    pub width: u32,
+    pub height: u32,
+    /// Lowercase format name, e.g. `jpeg` or `png`.
+    pub format: String,
+    /// Lowercase pixel layout as decoded, e.g. `rgb8` or `rgba16`.
+    pub color_type: String,
+    /// Bits per channel.
+    pub bit_depth: u16,
+    /// `sRGB`, the name of an embedded ICC profile, or `icc` when the profile is not named.
+    pub color_space: Option<String>,
+    pub has_alpha: bool,
+    pub animated: bool,
+    pub frame_count: u32,
+    pub exif: ExifMetadata,
+}
+
+/// The EXIF fields people search by, plus every field of the main image as display text.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExifMetadata {
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub lens_model: Option<String>,
+    pub date_taken: Option<NaiveDateTime>,
+    pub gps_latitude: Option<f64>,
+    pub gps_longitude: Option<f64>,
+    /// Field name to value, e.g. `"ExposureTime": "1/250 s"`.
+    pub fields: BTreeMap<String, String>,
+}
+
+/// Reads the metadata of a file whose bytes are `data` and that decoded to `image`.
+pub fn read(data: &[u8], format: ImageFormat, image: &DynamicImage) -> ImageMetadata {
+    let color = image.color();
+    let frame_count = match format {
+        ImageFormat::Png => apng_frames(data),
+        ImageFormat::Gif => gif_frames(data),
+        ImageFormat::WebP => webp_frames(data),
+        _ => None,
+    };
+    let exif = exif::Reader::new()
+        .read_from_container(&mut Cursor::new(data))
+        .ok();
+    let color_space = embedded_color_space(data, format).or_else(|| {
+        let field = exif
+            .as_ref()?
+            .get_field(exif::Tag::ColorSpace, exif::In::PRIMARY)?;
+        match field.value.get_uint(0)? {
+            1 => Some("sRGB".to_string()),
+            _ => None,
+        }
+    });
+    ImageMetadata {
+        width: image.width(),
+        height: image.height(),
+        format: format!("{format:?}").to_lowercase(),
+        color_type: format!("{color:?}").to_lowercase(),
+        bit_depth: color.bits_per_pixel() / u16::from(color.channel_count()),
+        color_space,
+        has_alpha: color.has_alpha(),
+        animated: frame_count.is_some_and(|frames| frames > 1),
+        frame_count: frame_count.unwrap_or(1).max(1),
+        exif: exif.map(|exif| read_exif(&exif)).unwrap_or_default(),
+    }
+}
+
+fn read_exif(exif: &exif::Exif) -> ExifMetadata {
+    let text = |tag| {
+        let field = exif.get_field(tag, exif::In::PRIMARY)?;
+        let exif::Value::Ascii(values) = &field.value else {
+            return None;
+        };
+        let text = String::from_utf8_lossy(values.first()?);
+        let text = text.trim_matches(|c: char| c.is_whitespace() || c == '\0');
+        (!text.is_empty()).then(|| text.to_string())
+    };
+    let date_taken = text(exif::Tag::DateTimeOriginal)
+        .or_else(|| text(exif::Tag::DateTime))
+        .and_then(|date| NaiveDateTime::parse_from_str(&date, "%Y:%m:%d %H:%M:%S").ok());
+    let coordinate = |tag, reference, negative: &str| {
+        let field = exif.get_field(tag, exif::In::PRIMARY)?;
+        let exif::Value::Rational(parts) = &field.value else {
+            return None;
+        };
+        let degrees = parts
+            .iter()
+            .zip([1.0, 60.0, 3600.0])
+            .map(|(part, scale)| part.to_f64() / scale)
+            .sum::<f64>();
+        let negative = text(reference).is_some_and(|reference| reference == negative);
+        degrees
+            .is_finite()
+            .then_some(if negative { -degrees } else { degrees })
+    };
+    let fields = exif
+        .fields()
+        .filter(|field| field.ifd_num == exif::In::PRIMARY && field.tag != exif::Tag::MakerNote)
+        .map(|field| {
+            (
+                field.tag.to_string(),
+                field.display_value().with_unit(exif).to_string(),
+            )
+        })
+        .collect();
+    ExifMetadata {
+        camera_make: text(exif::Tag::Make),
+        camera_model: text(exif::Tag::Model),
+        lens_model: text(exif::Tag::LensModel),
+        date_taken,
+        gps_latitude: coordinate(exif::Tag::GPSLatitude, exif::Tag::GPSLatitudeRef, "S"),
+        gps_longitude: coordinate(exif::Tag::GPSLongitude, exif::Tag::GPSLongitudeRef, "W"),
+        fields,
+    }
+}
+
+/// The color space a PNG or WebP declares in its own chunks.
+fn embedded_color_space(data: &[u8], format: ImageFormat) -> Option<String> {
+    match format {
+        ImageFormat::Png => png_chunks(data)
+            .into_iter()
+            .find_map(|(kind, chunk)| match &kind {
+                b"sRGB" => Some("sRGB".to_string()),
+                b"iCCP" => {
+                    let name = chunk.split(|&byte| byte == 0).next()?;
+                    Some(String::from_utf8_lossy(name).into_owned())
+                }
+                _ => None,
+            }),
+        ImageFormat::WebP => riff_chunks(data)
+            .iter()
+            .any(|(kind, _)| kind == b"ICCP")
+            .then(|| "icc".to_string()),
+        _ => None,
+    }
+}
+
+/// Type and data of each chunk of a PNG, empty for anything else. Stops at a truncated
+/// chunk.
+pub(crate) fn png_chunks(data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut chunks = Vec::new();
+    let Some(mut rest) = data.strip_prefix(PNG_SIGNATURE) else {
+        return chunks;
+    };
+    while rest.len() >= 12 {
+        let length = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        let kind = [rest[4], rest[5], rest[6], rest[7]];
+        let Some(chunk) = rest.get(8..8 + length) else {
+            break;
+        };
+        chunks.push((kind, chunk));
+        if &kind == b"IEND" {
+            break;
+        }
+        rest = &rest[(12 + length).min(rest.len())..];
+    }
+    chunks
+}
+
+/// Type and data of each chunk of a RIFF container such as WebP.
+fn riff_chunks(data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut chunks = Vec::new();
+    let Some(mut rest) = data.get(12..) else {
+        return chunks;
+    };
+    while rest.len() >= 8 {
+        let kind = [rest[0], rest[1], rest[2], rest[3]];
+        let length = u32::from_le_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+        let Some(chunk) = rest.get(8..8 + length) else {
+            break;
+        };
+        chunks.push((kind, chunk));
+        // Chunks are padded to an even length.
+        rest = &rest[(8 + length + length % 2).min(rest.len())..];
+    }
+    chunks
+}
+
+/// Frames of an animated PNG, `None` for a still one.
+fn apng_frames(data: &[u8]) -> Option<u32> {
+    let (_, control) = png_chunks(data)
+        .into_iter()
+        .find(|(kind, _)| kind == b"acTL")?;
+    Some(u32::from_be_bytes(control.get(..4)?.try_into().ok()?))
+}
+
+fn webp_frames(data: &[u8]) -> Option<u32> {
+    if data.get(..4)? != b"RIFF" || data.get(8..12)? != b"WEBP" {
+        return None;
+    }
+    let frames = riff_chunks(data)
+        .iter()
+        .filter(|(kind, _)| kind == b"ANMF")
+        .count();
+    (frames > 0).then_some(frames as u32)
+}
+
+/// Counts the image descriptors of a GIF without decoding them. A truncated file counts the
+/// frames before the cut.
+fn gif_frames(data: &[u8]) -> Option<u32> {
+    let rest = data
+        .strip_prefix(b"GIF89a")
+        .or_else(|| data.strip_prefix(b"GIF87a"))?;
+    let color_table = |flags: u8| match flags & 0x80 {
+        0 => 0,
+        _ => 3 << ((flags & 0x07) + 1),
+    };
+    // Data sub-blocks run until an empty one.
+    let skip_blocks = |mut pos: usize| loop {
+        let length = usize::from(*rest.get(pos)?);
+        pos += 1 + length;
+        if length == 0 {
+            return Some(pos);
+        }
+    };
+    // The logical screen descriptor is 7 bytes, its flags the fifth.
+    let mut pos = 7 + color_table(*rest.get(4)?);
+    let mut frames = 0;
+    loop {
+        let next = match rest.get(pos) {
+            Some(0x2c) => {
+                frames += 1;
+                // The image descriptor is 10 bytes, its flags the last; then comes the LZW
+                // code size byte.
+                let Some(&flags) = rest.get(pos + 9) else {
+                    break;
+                };
+                skip_blocks(pos + 10 + color_table(flags) + 1)
+            }
+            Some(0x21) => skip_blocks(pos + 2),
+            _ => None,
+        };
+        match next {
+            Some(next) => pos = next,
+            None => break,
+        }
+    }
+    Some(frames)
+}
+
+#[test]
+fn still_image() {
+    use image::{ImageOutputFormat, Rgba, RgbaImage};
+    let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(30, 20, Rgba([1, 2, 3, 4])));
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+        .unwrap();
+    let metadata = read(&png, ImageFormat::Png, &image);
+    assert_eq!((metadata.width, metadata.height), (30, 20));
+    assert_eq!(metadata.format, "png");
+    assert_eq!(metadata.color_type, "rgba8");
+    assert_eq!(metadata.bit_depth, 8);
+    assert!(metadata.has_alpha);
+    assert!(!metadata.animated);
+    assert_eq!(metadata.frame_count, 1);
+    assert_eq!(metadata.exif, ExifMetadata::default());
+}
+
+#[test]
+fn animated_gif() {
+    use image::codecs::gif::GifEncoder;
+    use image::{Delay, Frame, Rgba, RgbaImage};
+    let mut gif = Vec::new();
+    {
+        let mut encoder = GifEncoder::new(&mut gif);
+        for shade in [0, 128, 255] {
+            let frame = RgbaImage::from_pixel(4, 4, Rgba([shade, shade, shade, 255]));
+            encoder
+                .encode_frame(Frame::from_parts(
+                    frame,
+                    0,
+                    0,
+                    Delay::from_numer_denom_ms(100, 1),
+                ))
+                .unwrap();
+        }
+    }
+    assert_eq!(gif_frames(&gif), Some(3));
+    assert_eq!(
+        gif_frames(&gif[..gif.len() / 2]).map(|frames| frames < 3),
+        Some(true)
+    );
+    assert_eq!(gif_frames(b"not a gif"), None);
+}
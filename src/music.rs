@@ -0,0 +1,109 @@
+use std::path::Path;
+
+use crate::ffmpeg::Probe;
+
+/// The tags of an audio file that place it in the library. ffprobe reads ID3, Vorbis
+/// comments (Ogg, Opus and FLAC) and MP4 atoms alike; the key names differ a little between
+/// them, so each field is looked up under every name it goes by.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AudioTags {
+    pub title: String,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    /// Falls back to the track artist.
+    pub album_artist: Option<String>,
+    pub track_number: Option<i64>,
+    pub disc_number: Option<i64>,
+    pub year: Option<i64>,
+    pub genre: Option<String>,
+}
+
+impl AudioTags {
+    /// Reads the tags from a probe of the file at `path`, titling untagged files after the
+    /// file name.
+    pub fn from_probe(probe: &Probe, path: &Path) -> Self {
+        let tag = |keys: &[&str]| {
+            keys.iter().find_map(|key| {
+                let value = probe.tags.get(*key)?.trim();
+                (!value.is_empty()).then(|| value.to_string())
+            })
+        };
+        let artist = tag(&["artist", "album_artist", "albumartist"]);
+        Self {
+            title: tag(&["title"]).unwrap_or_else(|| {
+                path.file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            }),
+            album: tag(&["album"]),
+            album_artist: tag(&["album_artist", "albumartist", "album artist"])
+                .or_else(|| artist.clone()),
+            artist,
+            track_number: tag(&["track", "tracknumber"]).and_then(|track| position(&track)),
+            disc_number: tag(&["disc", "discnumber"]).and_then(|disc| position(&disc)),
+            year: tag(&["date", "year", "originaldate"]).and_then(|date| year(&date)),
+            genre: tag(&["genre"]),
+        }
+    }
+}
+
+/// `3` from `3` or `3/12`.
+fn position(value: &str) -> Option<i64> {
+    value.split('/').next()?.trim().parse().ok()
+}
+
+/// The year from `2020`, `2020-05-17` or yt-dlp's `20200517`.
+fn year(date: &str) -> Option<i64> {
+    let year = date.get(..4)?;
+    year.chars()
+        .all(|c| c.is_ascii_digit())
+        .then(|| year.parse().ok())
+        .flatten()
+}
+
+#[test]
+fn audio_tags() {
+    use std::collections::HashMap;
+    let tags: HashMap<String, String> = [
+        ("title", "Song"),
+        ("artist", "Someone"),
+        ("album", "Record"),
+        ("track", "3/12"),
+        ("discnumber", "1"),
+        ("date", "20231128"),
+        ("genre", "Jazz"),
+    ]
+    .into_iter()
+    .map(|(key, value)| (key.to_string(), value.to_string()))
+    .collect();
+    let probe = Probe {
+        container: "ogg".to_string(),
+        duration: None,
+        bit_rate: None,
+        streams: Vec::new(),
+        tags,
+        cover_art: false,
+        chapters: Vec::new(),
+    };
+    let parsed = AudioTags::from_probe(&probe, Path::new("/tmp/x.opus"));
+    assert_eq!(
+        parsed,
+        AudioTags {
+            title: "Song".to_string(),
+            artist: Some("Someone".to_string()),
+            album: Some("Record".to_string()),
+            album_artist: Some("Someone".to_string()),
+            track_number: Some(3),
+            disc_number: Some(1),
+            year: Some(2023),
+            genre: Some("Jazz".to_string()),
+        }
+    );
+    let untagged = Probe {
+        tags: HashMap::new(),
+        ..probe
+    };
+    let parsed = AudioTags::from_probe(&untagged, Path::new("/tmp/Some Title_abc.opus"));
+    assert_eq!(parsed.title, "Some Title_abc");
+    assert_eq!(parsed.artist, None);
+}
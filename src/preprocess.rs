@@ -0,0 +1,213 @@
+use std::io::Cursor;
+
+use image::{imageops::FilterType, DynamicImage, ImageFormat, ImageResult, Rgb, RgbImage};
+
+const WHITE: Rgb<u8> = Rgb([255, 255, 255]);
+
+/// Decodes an image the way it is meant to be shown, with its EXIF orientation applied.
+/// Guesses the format when none is given.
+pub fn decode(data: &[u8], format: Option<ImageFormat>) -> ImageResult<DynamicImage> {
+    let image = match format {
+        Some(format) => image::load_from_memory_with_format(data, format)?,
+        None => image::load_from_memory(data)?,
+    };
+    Ok(orient(image, exif_orientation(data)))
+}
+
+/// The EXIF orientation tag, `1` (upright) when missing or unreadable.
+fn exif_orientation(data: &[u8]) -> u32 {
+    exif::Reader::new()
+        .read_from_container(&mut Cursor::new(data))
+        .ok()
+        .and_then(|exif| {
+            exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)
+                .and_then(|field| field.value.get_uint(0))
+        })
+        .unwrap_or(1)
+}
+
+/// Turns an image stored with EXIF `orientation` upright.
+pub fn orient(image: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+/// Composites the image onto white, so transparent areas look like the padding around them
+/// rather than turning black.
+pub fn flatten_alpha(image: &DynamicImage) -> RgbImage {
+    if !image.color().has_alpha() {
+        return image.to_rgb8();
+    }
+    let rgba = image.to_rgba8();
+    RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let blend =
+            |c: u8| ((u32::from(c) * u32::from(a) + 255 * (255 - u32::from(a)) + 127) / 255) as u8;
+        Rgb([blend(r), blend(g), blend(b)])
+    })
+}
+
+/// Scales the image to fit `width` x `height` with its aspect ratio kept, rounding to the
+/// nearest pixel, and centers it on white.
+pub fn letterbox(image: &DynamicImage, width: u32, height: u32) -> RgbImage {
+    let (fitted_width, fitted_height) = fit(image.width(), image.height(), width, height);
+    let flat = DynamicImage::ImageRgb8(flatten_alpha(image));
+    let fitted = flat
+        .resize_exact(fitted_width, fitted_height, FilterType::Gaussian)
+        .to_rgb8();
+    let mut canvas = RgbImage::from_pixel(width, height, WHITE);
+    image::imageops::replace(
+        &mut canvas,
+        &fitted,
+        i64::from((width - fitted_width) / 2),
+        i64::from((height - fitted_height) / 2),
+    );
+    canvas
+}
+
+/// Size of an `image_width` x `image_height` image scaled to touch the box's edges.
+fn fit(image_width: u32, image_height: u32, width: u32, height: u32) -> (u32, u32) {
+    let (image_width, image_height) = (
+        u64::from(image_width.max(1)),
+        u64::from(image_height.max(1)),
+    );
+    let (box_width, box_height) = (u64::from(width), u64::from(height));
+    let rounded_div = |a: u64, b: u64| ((a + b / 2) / b) as u32;
+    if image_width * box_height >= image_height * box_width {
+        let fitted = rounded_div(image_height * box_width, image_width);
+        (width, fitted.clamp(1, height))
+    } else {
+        let fitted = rounded_div(image_width * box_height, image_height);
+        (fitted.clamp(1, width), height)
+    }
+}
+
+#[cfg(test)]
+const RED: Rgb<u8> = Rgb([255, 0, 0]);
+
+/// Asserts `actual` matches the golden image, allowing for resampling rounding.
+#[cfg(test)]
+fn assert_golden(actual: &RgbImage, golden: &RgbImage) {
+    assert_eq!(actual.dimensions(), golden.dimensions());
+    for (x, y, pixel) in actual.enumerate_pixels() {
+        let expected = golden.get_pixel(x, y);
+        let close = pixel
+            .0
+            .iter()
+            .zip(expected.0)
+            .all(|(a, b)| a.abs_diff(b) <= 2);
+        assert!(close, "pixel {x},{y} is {pixel:?}, expected {expected:?}");
+    }
+}
+
+/// White `width` x `height` canvas with a red `w` x `h` box at `x`, `y`.
+#[cfg(test)]
+fn golden(width: u32, height: u32, (x, y, w, h): (u32, u32, u32, u32)) -> RgbImage {
+    RgbImage::from_fn(width, height, |px, py| {
+        if (x..x + w).contains(&px) && (y..y + h).contains(&py) {
+            RED
+        } else {
+            WHITE
+        }
+    })
+}
+
+#[cfg(test)]
+fn solid(width: u32, height: u32) -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_pixel(width, height, RED))
+}
+
+#[test]
+fn letterbox_portrait() {
+    assert_golden(
+        &letterbox(&solid(30, 60), 64, 64),
+        &golden(64, 64, (16, 0, 32, 64)),
+    );
+}
+
+#[test]
+fn letterbox_landscape() {
+    assert_golden(
+        &letterbox(&solid(60, 30), 64, 64),
+        &golden(64, 64, (0, 16, 64, 32)),
+    );
+}
+
+#[test]
+fn letterbox_square() {
+    assert_golden(
+        &letterbox(&solid(100, 100), 64, 64),
+        &golden(64, 64, (0, 0, 64, 64)),
+    );
+}
+
+#[test]
+fn letterbox_tiny() {
+    assert_golden(
+        &letterbox(&solid(3, 1), 64, 64),
+        &golden(64, 64, (0, 21, 64, 21)),
+    );
+    assert_golden(
+        &letterbox(&solid(1, 1), 64, 64),
+        &golden(64, 64, (0, 0, 64, 64)),
+    );
+}
+
+#[test]
+fn letterbox_transparent() {
+    use image::{Rgba, RgbaImage};
+    let image = RgbaImage::from_fn(40, 20, |x, _| match x {
+        0..=19 => Rgba([0, 0, 0, 0]),
+        _ => Rgba([0, 0, 0, 128]),
+    });
+    let golden = RgbImage::from_fn(64, 64, |x, y| match (x, y) {
+        (_, 0..=15) | (_, 48..) => WHITE,
+        (0..=31, _) => WHITE,
+        _ => Rgb([127, 127, 127]),
+    });
+    let letterboxed = letterbox(&DynamicImage::ImageRgba8(image), 64, 64);
+    // The seam between the halves is blurred by resampling, so only compare away from it.
+    for x in [0, 16, 24, 44, 48, 63] {
+        for y in [0, 15, 16, 32, 47, 48, 63] {
+            let (actual, expected) = (letterboxed.get_pixel(x, y), golden.get_pixel(x, y));
+            let close = actual
+                .0
+                .iter()
+                .zip(expected.0)
+                .all(|(a, b)| a.abs_diff(b) <= 2);
+            assert!(close, "pixel {x},{y} is {actual:?}, expected {expected:?}");
+        }
+    }
+}
+
+#[test]
+fn exif_orientations() {
+    let blue = Rgb([0, 0, 255]);
+    let image = DynamicImage::ImageRgb8(RgbImage::from_fn(2, 1, |x, _| match x {
+        0 => RED,
+        _ => blue,
+    }));
+    let rotated = orient(image.clone(), 6).to_rgb8();
+    assert_eq!(rotated.dimensions(), (1, 2));
+    assert_eq!(
+        (*rotated.get_pixel(0, 0), *rotated.get_pixel(0, 1)),
+        (RED, blue)
+    );
+    let rotated = orient(image.clone(), 8).to_rgb8();
+    assert_eq!(
+        (*rotated.get_pixel(0, 0), *rotated.get_pixel(0, 1)),
+        (blue, RED)
+    );
+    let mirrored = orient(image.clone(), 2).to_rgb8();
+    assert_eq!(*mirrored.get_pixel(0, 0), blue);
+    assert_eq!(orient(image.clone(), 1).to_rgb8(), image.to_rgb8());
+    assert_eq!(exif_orientation(b"not an image"), 1);
+}
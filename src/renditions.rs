@@ -0,0 +1,304 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use crate::database::{self, Database, RenditionRow};
+use crate::ffmpeg;
+
+/// `profile` of the rendition that is the ingested file itself.
+pub const ORIGINAL: &str = "original";
+/// How often the worker looks for queued renditions without being woken.
+const POLL_EVERY: Duration = Duration::from_secs(60);
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Database(#[from] database::Error),
+    #[error(transparent)]
+    Ffmpeg(#[from] ffmpeg::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Config(#[from] serde_json::Error),
+    #[error("unknown profile {0}")]
+    UnknownProfile(String),
+    #[error("video {0} not found")]
+    UnknownVideo(i64),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum RenditionStatus {
+    Pending,
+    Running,
+    Done,
+    Failed,
+    /// The original already fits the profile.
+    Skipped,
+}
+
+/// A transcode made of every ingested video.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct VideoProfile {
+    pub name: String,
+    /// ffprobe's name for the video codec made, e.g. `h264`.
+    pub video_codec: String,
+    /// ffmpeg encoder, e.g. `libx264`.
+    pub video_encoder: String,
+    /// More ffmpeg output options, e.g. quality and preset.
+    #[serde(default)]
+    pub video_args: Vec<String>,
+    /// Taller videos are scaled down to this.
+    pub max_height: Option<u32>,
+    /// ffprobe's name for the audio codec made, e.g. `aac`.
+    pub audio_codec: String,
+    pub audio_encoder: String,
+    /// kbps.
+    pub audio_bit_rate: u32,
+    /// File extension, which picks the container.
+    pub container: String,
+}
+
+impl VideoProfile {
+    /// Plays nearly everywhere.
+    pub fn h264_1080p() -> Self {
+        Self {
+            name: "h264-1080p".to_string(),
+            video_codec: "h264".to_string(),
+            video_encoder: "libx264".to_string(),
+            video_args: ["-crf", "20", "-preset", "medium", "-pix_fmt", "yuv420p"]
+                .map(String::from)
+                .to_vec(),
+            max_height: Some(1080),
+            audio_codec: "aac".to_string(),
+            audio_encoder: "aac".to_string(),
+            audio_bit_rate: 160,
+            container: "mp4".to_string(),
+        }
+    }
+
+    /// Small files at full resolution, slow to make.
+    pub fn av1_archive() -> Self {
+        Self {
+            name: "av1-archive".to_string(),
+            video_codec: "av1".to_string(),
+            video_encoder: "libsvtav1".to_string(),
+            video_args: ["-crf", "30", "-preset", "6"].map(String::from).to_vec(),
+            max_height: None,
+            audio_codec: "opus".to_string(),
+            audio_encoder: "libopus".to_string(),
+            audio_bit_rate: 128,
+            container: "mkv".to_string(),
+        }
+    }
+
+    /// Reads a JSON list of profiles.
+    pub fn load_all(path: impl AsRef<Path>) -> Result<Vec<Self>> {
+        let profiles = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&profiles)?)
+    }
+
+    /// Whether a video with these codecs and height already is what the profile makes.
+    fn fits(
+        &self,
+        video_codec: Option<&str>,
+        audio_codec: Option<&str>,
+        height: Option<i64>,
+    ) -> bool {
+        let height_fits = match (self.max_height, height) {
+            (Some(max_height), Some(height)) => height <= i64::from(max_height),
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        video_codec == Some(self.video_codec.as_str())
+            && audio_codec.map_or(true, |codec| codec == self.audio_codec)
+            && height_fits
+    }
+}
+
+/// Transcodes queued renditions one at a time in the background. The queue is the
+/// `video_rendition` table, so renditions interrupted by a restart run again.
+#[derive(Clone)]
+pub struct RenditionWorker {
+    db: Database,
+    profiles: Arc<Vec<VideoProfile>>,
+    dir: PathBuf,
+    wake: Arc<Notify>,
+}
+
+impl RenditionWorker {
+    /// Renditions are written under `dir`, one directory per video.
+    pub fn new(db: Database, profiles: Vec<VideoProfile>, dir: PathBuf) -> Self {
+        Self {
+            db,
+            profiles: Arc::new(profiles),
+            dir,
+            wake: Arc::default(),
+        }
+    }
+
+    pub fn profiles(&self) -> &[VideoProfile] {
+        &self.profiles
+    }
+
+    /// Queues the named profiles, or all of them, for a video. Renditions already made or
+    /// queued are left alone and failed ones are retried.
+    pub async fn queue(&self, video_id: i64, profiles: Option<&[String]>) -> Result<()> {
+        let names: Vec<&str> = match profiles {
+            Some(names) => {
+                for name in names {
+                    if !self.profiles.iter().any(|profile| &profile.name == name) {
+                        return Err(Error::UnknownProfile(name.clone()));
+                    }
+                }
+                names.iter().map(String::as_str).collect()
+            }
+            None => self
+                .profiles
+                .iter()
+                .map(|profile| profile.name.as_str())
+                .collect(),
+        };
+        self.db.queue_renditions(video_id, &names).await?;
+        self.wake.notify_one();
+        Ok(())
+    }
+
+    /// Starts working through the queue, first requeuing renditions a previous run left
+    /// running.
+    pub async fn start(&self) -> Result<()> {
+        let requeued = self.db.requeue_running_renditions().await?;
+        if requeued > 0 {
+            info!("requeued {} interrupted renditions", requeued);
+        }
+        let worker = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match worker.db.next_rendition().await {
+                    Ok(Some(rendition)) => worker.run(rendition).await,
+                    Ok(None) => {
+                        tokio::time::timeout(POLL_EVERY, worker.wake.notified())
+                            .await
+                            .ok();
+                    }
+                    Err(err) => {
+                        error!("failed to fetch the next rendition: {}", err);
+                        tokio::time::sleep(POLL_EVERY).await;
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+
+    async fn run(&self, rendition: RenditionRow) {
+        let rendition_id = rendition.rendition_id();
+        let finished = match self.transcode(&rendition).await {
+            Ok(Some((path, size, probe))) => {
+                self.db
+                    .complete_rendition(rendition_id, &path, size, &probe)
+                    .await
+            }
+            Ok(None) => {
+                self.db
+                    .finish_rendition(rendition_id, RenditionStatus::Skipped, None)
+                    .await
+            }
+            Err(err) => {
+                warn!(
+                    "rendition {} of video {} failed: {}",
+                    rendition.profile(),
+                    rendition.video_id(),
+                    err
+                );
+                self.db
+                    .finish_rendition(
+                        rendition_id,
+                        RenditionStatus::Failed,
+                        Some(&err.to_string()),
+                    )
+                    .await
+            }
+        };
+        if let Err(err) = finished {
+            error!("failed to record rendition {}: {}", rendition_id, err);
+        }
+    }
+
+    /// Makes a rendition, returning its path, size and probe, or `None` when the original
+    /// already fits the profile.
+    async fn transcode(
+        &self,
+        rendition: &RenditionRow,
+    ) -> Result<Option<(String, i64, ffmpeg::Probe)>> {
+        let profile = self
+            .profiles
+            .iter()
+            .find(|profile| profile.name == rendition.profile())
+            .ok_or_else(|| Error::UnknownProfile(rendition.profile().to_string()))?;
+        let video_id = rendition.video_id();
+        let video = self
+            .db
+            .get_video(video_id)
+            .await?
+            .ok_or(Error::UnknownVideo(video_id))?;
+        if let Some(summary) = self.db.get_video_summary(video_id).await? {
+            if profile.fits(
+                summary.video_codec(),
+                summary.audio_codec(),
+                summary.height(),
+            ) {
+                return Ok(None);
+            }
+        }
+        let dir = self.dir.join(video_id.to_string());
+        tokio::fs::create_dir_all(&dir).await?;
+        let path = dir.join(format!("{}.{}", profile.name, profile.container));
+        // ffmpeg picks the container from the extension, so the temporary name keeps it.
+        let partial = dir.join(format!("{}.partial.{}", profile.name, profile.container));
+        info!("making rendition {} of video {}", profile.name, video_id);
+        let transcoded = ffmpeg::transcode_video(
+            video.path(),
+            &partial,
+            &profile.video_encoder,
+            &profile.video_args,
+            profile.max_height,
+            &profile.audio_encoder,
+            profile.audio_bit_rate,
+        )
+        .await;
+        if let Err(err) = transcoded {
+            tokio::fs::remove_file(&partial).await.ok();
+            return Err(err.into());
+        }
+        tokio::fs::rename(&partial, &path).await?;
+        let probe = ffmpeg::probe(&path).await?;
+        let size = tokio::fs::metadata(&path).await?.len() as i64;
+        Ok(Some((path.to_string_lossy().into_owned(), size, probe)))
+    }
+}
+
+#[test]
+fn profile_fits() {
+    let profile = VideoProfile::h264_1080p();
+    assert!(profile.fits(Some("h264"), Some("aac"), Some(720)));
+    assert!(profile.fits(Some("h264"), None, Some(1080)));
+    assert!(!profile.fits(Some("hevc"), Some("aac"), Some(720)));
+    assert!(!profile.fits(Some("h264"), Some("opus"), Some(720)));
+    assert!(!profile.fits(Some("h264"), Some("aac"), Some(2160)));
+    assert!(VideoProfile::av1_archive().fits(Some("av1"), Some("opus"), Some(2160)));
+    let profiles: Vec<VideoProfile> = serde_json::from_str(
+        r#"[{"name": "vp9", "video_codec": "vp9", "video_encoder": "libvpx-vp9",
+            "max_height": 720, "audio_codec": "opus", "audio_encoder": "libopus",
+            "audio_bit_rate": 96, "container": "webm"}]"#,
+    )
+    .unwrap();
+    assert_eq!(profiles[0].video_args, Vec::<String>::new());
+}
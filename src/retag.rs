@@ -0,0 +1,327 @@
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use crate::database::{self, Database, JobRow};
+use crate::ffmpeg;
+use crate::fingerprint::{Fingerprint, FingerprintIndex};
+use crate::inference_cache;
+use crate::jobs::{JobStatus, Jobs};
+use crate::media::MediaKind;
+use crate::preprocess;
+use crate::similarity::{Embedding, SimilarityIndex};
+use crate::tagging::{self, TaggingService};
+use crate::workers::{self, CpuPool};
+
+/// `kind` of re-tagging rows in the `job` table.
+pub const JOB_KIND: &str = "retag";
+
+/// Items fetched and recorded as progress at a time; a resumed job repeats at most one batch.
+const BATCH_SIZE: i64 = 32;
+/// Frames tagged per video, the score of a tag being its best score over these frames.
+const VIDEO_FRAMES: u32 = 8;
+/// Gap in frames between sampled video frames.
+const VIDEO_FRAME_EVERY: u32 = 300;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Database(#[from] database::Error),
+    #[error(transparent)]
+    Tagging(#[from] tagging::Error),
+    #[error(transparent)]
+    Worker(#[from] workers::Error),
+    #[error(transparent)]
+    Ffmpeg(#[from] ffmpeg::Error),
+    #[error(transparent)]
+    Io(#[from] tokio::io::Error),
+    #[error(transparent)]
+    Decode(#[from] image::ImageError),
+    #[error(transparent)]
+    Params(#[from] serde_json::Error),
+    #[error("unknown tagger {0}")]
+    UnknownModel(String),
+    #[error("only the primary tagger can re-tag videos")]
+    VideoNeedsPrimary,
+    #[error("the taggers only tag pictures, not music")]
+    MusicNotTaggable,
+    #[error("chapters are only tagged by hand")]
+    ChapterNotTaggable,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetagParams {
+    /// Tagger name, the primary tagger when missing.
+    pub model: Option<String>,
+    /// What to re-tag, in order.
+    pub kinds: Vec<MediaKind>,
+    /// Skip items the primary tagger's current version already tagged. Leave off after a
+    /// threshold change, which keeps the version.
+    pub only_stale: bool,
+}
+
+impl Default for RetagParams {
+    fn default() -> Self {
+        Self {
+            model: None,
+            kinds: vec![MediaKind::Image, MediaKind::Video],
+            only_stale: false,
+        }
+    }
+}
+
+/// Runs library re-tagging as resumable jobs.
+///
+/// The primary tagger's predictions replace an item's model tags and stamp it with the model
+/// version in `tagged_by`; the other taggers only refresh their stored per-model predictions.
+/// Tags people added or removed are never touched.
+#[derive(Clone)]
+pub struct Retagger {
+    db: Database,
+    taggers: Vec<TaggingService>,
+    cpu: CpuPool,
+    jobs: Jobs,
+    similarity: SimilarityIndex,
+    fingerprints: FingerprintIndex,
+}
+
+impl Retagger {
+    pub fn new(
+        db: Database,
+        taggers: Vec<TaggingService>,
+        cpu: CpuPool,
+        jobs: Jobs,
+        similarity: SimilarityIndex,
+        fingerprints: FingerprintIndex,
+    ) -> Self {
+        Self {
+            db,
+            taggers,
+            cpu,
+            jobs,
+            similarity,
+            fingerprints,
+        }
+    }
+
+    pub fn jobs(&self) -> &Jobs {
+        &self.jobs
+    }
+
+    /// Records a new job and starts it.
+    pub async fn start(&self, params: RetagParams) -> Result<JobRow> {
+        let (primary, tagging) = self.tagging(params.model.as_deref())?;
+        if params.kinds.contains(&MediaKind::Music) {
+            return Err(Error::MusicNotTaggable);
+        }
+        if params.kinds.contains(&MediaKind::Chapter) {
+            return Err(Error::ChapterNotTaggable);
+        }
+        if !primary && params.kinds.contains(&MediaKind::Video) {
+            return Err(Error::VideoNeedsPrimary);
+        }
+        let model = tagging.tagger().descriptor().id();
+        let skip_model = (primary && params.only_stale).then_some(model.as_str());
+        let mut total = 0;
+        for kind in &params.kinds {
+            total += self.db.count_media(*kind, skip_model).await?;
+        }
+        let stage = params.kinds.first().map_or("", |kind| kind.table());
+        let job = self
+            .db
+            .create_job(JOB_KIND, &serde_json::to_value(&params)?, stage, total)
+            .await?;
+        info!("retag job {} started for {} items", job.job_id(), total);
+        self.spawn(job.job_id(), params, stage.to_string(), 0);
+        Ok(job)
+    }
+
+    /// Picks up jobs that were still running when the server stopped.
+    pub async fn resume(&self) -> Result<()> {
+        for job in self.db.running_jobs(JOB_KIND).await? {
+            let params = match serde_json::from_value::<RetagParams>(job.params().clone()) {
+                Ok(params) => params,
+                Err(err) => {
+                    self.db
+                        .finish_job(job.job_id(), JobStatus::Failed, Some(&err.to_string()))
+                        .await?;
+                    continue;
+                }
+            };
+            let (stage, cursor) = job.position();
+            info!(
+                "resuming retag job {} at {} {}",
+                job.job_id(),
+                stage,
+                cursor
+            );
+            self.spawn(job.job_id(), params, stage.to_string(), cursor);
+        }
+        Ok(())
+    }
+
+    fn spawn(&self, job_id: i64, params: RetagParams, stage: String, cursor: i64) {
+        let retagger = self.clone();
+        self.jobs.spawn(job_id, move |token| async move {
+            let (status, err) = match retagger.run(job_id, &params, &stage, cursor, token).await {
+                Ok(status) => (status, None),
+                Err(err) => {
+                    error!("retag job {} failed: {}", job_id, err);
+                    (JobStatus::Failed, Some(err.to_string()))
+                }
+            };
+            if let Err(err) = retagger.db.finish_job(job_id, status, err.as_deref()).await {
+                error!("failed to finish retag job {}: {}", job_id, err);
+            }
+        });
+    }
+
+    /// The tagger called `model`, or the primary one, and whether it is the primary one.
+    fn tagging(&self, model: Option<&str>) -> Result<(bool, &TaggingService)> {
+        let Some(model) = model else {
+            return Ok((true, &self.taggers[0]));
+        };
+        self.taggers
+            .iter()
+            .enumerate()
+            .find(|(_, tagging)| tagging.tagger().descriptor().name == model)
+            .map(|(i, tagging)| (i == 0, tagging))
+            .ok_or_else(|| Error::UnknownModel(model.to_string()))
+    }
+
+    async fn run(
+        &self,
+        job_id: i64,
+        params: &RetagParams,
+        stage: &str,
+        cursor: i64,
+        token: CancellationToken,
+    ) -> Result<JobStatus> {
+        let (primary, tagging) = self.tagging(params.model.as_deref())?;
+        let model = tagging.tagger().descriptor().id();
+        let skip_model = (primary && params.only_stale).then_some(model.as_str());
+        let start = params
+            .kinds
+            .iter()
+            .position(|kind| kind.table() == stage)
+            .unwrap_or(0);
+        for (i, kind) in params.kinds[start..].iter().enumerate() {
+            let mut cursor = if i == 0 { cursor } else { 0 };
+            loop {
+                if token.is_cancelled() {
+                    return Ok(JobStatus::Cancelled);
+                }
+                let batch = self
+                    .db
+                    .media_after(*kind, cursor, BATCH_SIZE, skip_model)
+                    .await?;
+                if batch.is_empty() {
+                    break;
+                }
+                let (mut processed, mut failed) = (0, 0);
+                for (media_id, path) in batch {
+                    cursor = media_id;
+                    match self
+                        .retag(*kind, media_id, &path, tagging, primary, &model)
+                        .await
+                    {
+                        Ok(()) => processed += 1,
+                        Err(err) => {
+                            warn!("failed to retag {} {}: {}", kind.table(), media_id, err);
+                            failed += 1;
+                        }
+                    }
+                }
+                self.db
+                    .advance_job(job_id, kind.table(), cursor, processed, failed)
+                    .await?;
+            }
+        }
+        Ok(JobStatus::Completed)
+    }
+
+    async fn retag(
+        &self,
+        kind: MediaKind,
+        media_id: i64,
+        path: &str,
+        tagging: &TaggingService,
+        primary: bool,
+        model: &str,
+    ) -> Result<()> {
+        let files = match kind {
+            MediaKind::Image => vec![tokio::fs::read(path).await?],
+            MediaKind::Video => {
+                ffmpeg::extract_frames(path, VIDEO_FRAMES, VIDEO_FRAME_EVERY).await?
+            }
+            MediaKind::Music => return Err(Error::MusicNotTaggable),
+            MediaKind::Chapter => return Err(Error::ChapterNotTaggable),
+        };
+        // A video's score for a label is its best score over the sampled frames.
+        let mut scores: Vec<f32> = Vec::new();
+        for file in files {
+            // Hashing is cheap next to decoding, which a cache hit skips along with inference.
+            let content_hash = inference_cache::content_hash(&file);
+            if kind == MediaKind::Image {
+                // Images uploaded before their content hash was kept get it here.
+                self.db.set_image_hash(media_id, &content_hash).await?;
+            }
+            // Images uploaded before fingerprints had their own column get one here.
+            let fingerprint = kind == MediaKind::Image && self.fingerprints.get(media_id).is_none();
+            let frame_scores = match tagging.cached_scores(&content_hash).await {
+                Some(scores) if !fingerprint => scores,
+                cached => {
+                    let (image, fingerprint) = self
+                        .cpu
+                        .run(move || {
+                            let image = preprocess::decode(&file, None)?;
+                            let fingerprint =
+                                fingerprint.then(|| Fingerprint::new().fingerprint(&image));
+                            Ok::<_, image::ImageError>((image, fingerprint))
+                        })
+                        .await??;
+                    if let Some(fingerprint) = fingerprint {
+                        self.db
+                            .set_image_fingerprint(media_id, &format!("{fingerprint:016x}"))
+                            .await?;
+                        self.fingerprints.insert(media_id, fingerprint);
+                    }
+                    match cached {
+                        Some(scores) => scores,
+                        None => tagging.infer_scores(&content_hash, image).await?,
+                    }
+                }
+            };
+            if scores.is_empty() {
+                scores = frame_scores;
+            } else {
+                for (best, score) in scores.iter_mut().zip(frame_scores) {
+                    *best = best.max(score);
+                }
+            }
+        }
+        let tags: Vec<(i64, f64)> = tagging
+            .select(&scores)
+            .into_iter()
+            .map(|(score, index)| (tagging.tag_id(index), f64::from(score)))
+            .collect();
+        if primary {
+            self.db
+                .replace_model_tags(kind, media_id, &tags, model)
+                .await?;
+        }
+        if kind == MediaKind::Image {
+            self.db.save_model_tags(media_id, model, &tags).await?;
+            let embedding = Embedding::from_scores(&scores);
+            self.db
+                .save_embedding(media_id, model, &embedding.to_bytes())
+                .await?;
+            if model == self.similarity.model() {
+                self.similarity.insert(media_id, embedding);
+            }
+        }
+        Ok(())
+    }
+}
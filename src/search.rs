@@ -0,0 +1,361 @@
+use crate::media::TagCategory;
+
+/// A single whitespace separated term of a search query, e.g. `cat_ears`, `-"long hair"` or
+/// `prompt:"red dress"`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    pub negated: bool,
+    pub term: Term,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    Tag(String),
+    /// Text anywhere in the generation prompt, ignoring case.
+    Prompt(String),
+    /// Checkpoint name or hash of the generation parameters, ignoring case.
+    Model(String),
+    /// Text anywhere in a video's subtitles, ignoring case.
+    Said(String),
+    /// A measurement compared to a value, e.g. `width:>=1920` or `aspect:16:9`.
+    Number(NumberField, Comparison, f64),
+    /// Text in a metadata field, e.g. `format:png` or `camera:canon`.
+    Text(TextField, String),
+    /// Whether something is true of the file, e.g. `animated:yes`.
+    Flag(FlagField, bool),
+    /// Capture date compared to a `2020`, `2020-05` or `2020-05-17` prefix, e.g. `taken:>=2020-05`.
+    Taken(Comparison, String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumberField {
+    Width,
+    Height,
+    /// Width over height.
+    Aspect,
+    Megapixels,
+    Frames,
+    /// Bits per channel.
+    BitDepth,
+    /// Seconds.
+    Duration,
+    /// Frames per second.
+    FrameRate,
+    /// Kilobits per second.
+    Bitrate,
+    /// Audio channels.
+    Channels,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextField {
+    /// Exact format name, e.g. `png`.
+    Format,
+    /// Text anywhere in the camera make and model.
+    Camera,
+    /// Text anywhere in the lens model.
+    Lens,
+    /// Exact video or audio codec name, e.g. `h264`.
+    Codec,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlagField {
+    Animated,
+    Alpha,
+    /// Has a GPS position.
+    Gps,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Less,
+    AtMost,
+    Equal,
+    AtLeast,
+    Greater,
+}
+
+impl Comparison {
+    /// Splits a leading `<`, `<=`, `=`, `>=` or `>` off a value; no operator means `=`.
+    fn split(value: &str) -> (Self, &str) {
+        let operators = [
+            ("<=", Comparison::AtMost),
+            (">=", Comparison::AtLeast),
+            ("<", Comparison::Less),
+            (">", Comparison::Greater),
+            ("=", Comparison::Equal),
+        ];
+        operators
+            .into_iter()
+            .find_map(|(operator, comparison)| {
+                value
+                    .strip_prefix(operator)
+                    .map(|rest| (comparison, rest.trim()))
+            })
+            .unwrap_or((Comparison::Equal, value))
+    }
+
+    /// The SQL operator.
+    pub fn operator(self) -> &'static str {
+        match self {
+            Comparison::Less => "<",
+            Comparison::AtMost => "<=",
+            Comparison::Equal => "=",
+            Comparison::AtLeast => ">=",
+            Comparison::Greater => ">",
+        }
+    }
+}
+
+/// Tag names are stored lowercase with `_` for spaces.
+pub fn canonical_tag(name: &str) -> String {
+    name.trim().to_lowercase().replace(' ', "_")
+}
+
+/// Splits `character:hatsune_miku` into its category and name. Colons that do not follow a
+/// known category, like in `3:`, are part of the name.
+pub fn split_namespace(name: &str) -> (Option<TagCategory>, &str) {
+    match name.split_once(':') {
+        Some((namespace, rest)) if !rest.is_empty() => match TagCategory::from_namespace(namespace)
+        {
+            Some(category) => (Some(category), rest),
+            None => (None, name),
+        },
+        _ => (None, name),
+    }
+}
+
+pub fn parse(query: &str) -> Vec<Filter> {
+    tokenize(query)
+        .into_iter()
+        .filter_map(|token| {
+            let (negated, token) = match token.strip_prefix('-') {
+                Some(rest) => (true, rest.to_string()),
+                None => (false, token),
+            };
+            let term = token
+                .split_once(':')
+                .and_then(|(field, value)| field_term(&field.to_lowercase(), value.trim()));
+            if let Some(term) = term {
+                return Some(Filter { negated, term });
+            }
+            let name = canonical_tag(&token);
+            if name.is_empty() {
+                return None;
+            }
+            Some(Filter {
+                negated,
+                term: Term::Tag(name),
+            })
+        })
+        .collect()
+}
+
+/// A `field:value` term other than a tag. `None` when the field is unknown or the value does
+/// not fit it, leaving the token to be read as a tag.
+fn field_term(field: &str, value: &str) -> Option<Term> {
+    if value.is_empty() {
+        return None;
+    }
+    let number = |field| {
+        let (comparison, value) = Comparison::split(value);
+        Some(Term::Number(field, comparison, value.parse().ok()?))
+    };
+    let flag = |field| match value.to_lowercase().as_str() {
+        "yes" | "true" => Some(Term::Flag(field, true)),
+        "no" | "false" => Some(Term::Flag(field, false)),
+        _ => None,
+    };
+    match field {
+        "prompt" => Some(Term::Prompt(value.to_string())),
+        "model" => Some(Term::Model(value.to_string())),
+        "said" => Some(Term::Said(value.to_string())),
+        "width" => number(NumberField::Width),
+        "height" => number(NumberField::Height),
+        "megapixels" => number(NumberField::Megapixels),
+        "frames" => number(NumberField::Frames),
+        "bitdepth" => number(NumberField::BitDepth),
+        "fps" => number(NumberField::FrameRate),
+        "bitrate" => number(NumberField::Bitrate),
+        "channels" => number(NumberField::Channels),
+        "duration" => {
+            let (comparison, value) = Comparison::split(value);
+            Some(Term::Number(
+                NumberField::Duration,
+                comparison,
+                parse_duration(value)?,
+            ))
+        }
+        "aspect" => {
+            let (comparison, value) = Comparison::split(value);
+            Some(Term::Number(
+                NumberField::Aspect,
+                comparison,
+                parse_ratio(value)?,
+            ))
+        }
+        "format" => Some(Term::Text(TextField::Format, value.to_lowercase())),
+        "camera" => Some(Term::Text(TextField::Camera, value.to_string())),
+        "lens" => Some(Term::Text(TextField::Lens, value.to_string())),
+        "codec" => Some(Term::Text(TextField::Codec, value.to_lowercase())),
+        "animated" => flag(FlagField::Animated),
+        "alpha" => flag(FlagField::Alpha),
+        "gps" => flag(FlagField::Gps),
+        "taken" => {
+            let (comparison, value) = Comparison::split(value);
+            // Year, then optionally month and day.
+            let parts: Vec<&str> = value.split('-').collect();
+            let valid = parts.len() <= 3
+                && parts.iter().enumerate().all(|(i, part)| {
+                    part.len() == if i == 0 { 4 } else { 2 }
+                        && part.chars().all(|c| c.is_ascii_digit())
+                });
+            valid.then(|| Term::Taken(comparison, value.to_string()))
+        }
+        _ => None,
+    }
+}
+
+/// Seconds, `600`, `10:00` or `1:00:00`.
+fn parse_duration(value: &str) -> Option<f64> {
+    let mut seconds = 0.0;
+    for (i, part) in value.split(':').enumerate() {
+        if i > 2 {
+            return None;
+        }
+        seconds = seconds * 60.0 + part.parse::<f64>().ok().filter(|part| *part >= 0.0)?;
+    }
+    Some(seconds)
+}
+
+/// `16:9`, `16/9` or `1.78`.
+fn parse_ratio(value: &str) -> Option<f64> {
+    let ratio = match value.split_once([':', '/']) {
+        Some((width, height)) => width.parse::<f64>().ok()? / height.parse::<f64>().ok()?,
+        None => value.parse().ok()?,
+    };
+    (ratio.is_finite() && ratio > 0.0).then_some(ratio)
+}
+
+/// Splits on whitespace, keeping double quoted runs together and dropping the quotes.
+fn tokenize(query: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut quoted = false;
+    for c in query.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+            }
+            c => token.push(c),
+        }
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    tokens
+}
+
+#[test]
+fn parse_tags() {
+    let filters = parse(r#"Cat_Ears  -solo "long hair" -"red eyes""#);
+    assert_eq!(
+        filters,
+        vec![
+            Filter {
+                negated: false,
+                term: Term::Tag("cat_ears".to_string())
+            },
+            Filter {
+                negated: true,
+                term: Term::Tag("solo".to_string())
+            },
+            Filter {
+                negated: false,
+                term: Term::Tag("long_hair".to_string())
+            },
+            Filter {
+                negated: true,
+                term: Term::Tag("red_eyes".to_string())
+            },
+        ]
+    );
+}
+
+#[test]
+fn parse_generation_fields() {
+    assert_eq!(
+        parse(r#"prompt:"Red Dress" -Model:sdxl_base model: solo"#),
+        vec![
+            Filter {
+                negated: false,
+                term: Term::Prompt("Red Dress".to_string())
+            },
+            Filter {
+                negated: true,
+                term: Term::Model("sdxl_base".to_string())
+            },
+            Filter {
+                negated: false,
+                term: Term::Tag("model:".to_string())
+            },
+            Filter {
+                negated: false,
+                term: Term::Tag("solo".to_string())
+            },
+        ]
+    );
+}
+
+#[test]
+fn parse_metadata_fields() {
+    let terms: Vec<Term> = parse(
+        "width:>=1920 aspect:16:9 -animated:yes format:PNG taken:<2021-06 width:wide taken:June",
+    )
+    .into_iter()
+    .map(|filter| filter.term)
+    .collect();
+    assert_eq!(
+        terms,
+        vec![
+            Term::Number(NumberField::Width, Comparison::AtLeast, 1920.0),
+            Term::Number(NumberField::Aspect, Comparison::Equal, 16.0 / 9.0),
+            Term::Flag(FlagField::Animated, true),
+            Term::Text(TextField::Format, "png".to_string()),
+            Term::Taken(Comparison::Less, "2021-06".to_string()),
+            Term::Tag("width:wide".to_string()),
+            Term::Tag("taken:june".to_string()),
+        ]
+    );
+    assert_eq!(parse_ratio("0:0"), None);
+    assert_eq!(
+        parse("duration:>10:00 codec:H264")
+            .into_iter()
+            .map(|filter| filter.term)
+            .collect::<Vec<_>>(),
+        vec![
+            Term::Number(NumberField::Duration, Comparison::Greater, 600.0),
+            Term::Text(TextField::Codec, "h264".to_string()),
+        ]
+    );
+    assert_eq!(parse_duration("1:00:00"), Some(3600.0));
+    assert_eq!(parse_duration("1:2:3:4"), None);
+}
+
+#[test]
+fn namespaces() {
+    assert_eq!(
+        split_namespace("character:hatsune_miku"),
+        (Some(TagCategory::Character), "hatsune_miku")
+    );
+    assert_eq!(
+        split_namespace("rating:safe"),
+        (Some(TagCategory::Rating), "safe")
+    );
+    assert_eq!(split_namespace("3:"), (None, "3:"));
+    assert_eq!(split_namespace("re:zero"), (None, "re:zero"));
+}
@@ -0,0 +1,192 @@
+use std::sync::{Arc, RwLock};
+
+use crate::hnsw::{Hnsw, Point};
+
+/// Most labels kept per image. Tag scores are mostly near zero, so this rarely drops anything.
+const MAX_LABELS: usize = 512;
+/// Candidates looked at per search step; higher finds more true neighbours, slower.
+const EF_SEARCH: usize = 64;
+
+/// A model's scores for one image with each score quantized to a byte and near-zero scores
+/// dropped, which is 3 bytes per kept label instead of 4 bytes per label.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Embedding {
+    /// Label indices, ascending.
+    labels: Vec<u16>,
+    values: Vec<u8>,
+    norm: f32,
+}
+
+impl Embedding {
+    pub fn from_scores(scores: &[f32]) -> Self {
+        let mut kept: Vec<(u16, u8)> = scores
+            .iter()
+            .enumerate()
+            .filter_map(|(label, score)| {
+                let value = (score.clamp(0.0, 1.0) * 255.0).round() as u8;
+                let label = u16::try_from(label).ok()?;
+                (value > 0).then_some((label, value))
+            })
+            .collect();
+        if kept.len() > MAX_LABELS {
+            kept.sort_unstable_by_key(|(_, value)| std::cmp::Reverse(*value));
+            kept.truncate(MAX_LABELS);
+            kept.sort_unstable();
+        }
+        let (labels, values) = kept.into_iter().unzip();
+        Self::new(labels, values)
+    }
+
+    fn new(labels: Vec<u16>, values: Vec<u8>) -> Self {
+        let norm = values
+            .iter()
+            .map(|&value| f32::from(value).powi(2))
+            .sum::<f32>()
+            .sqrt();
+        Self {
+            labels,
+            values,
+            norm,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.labels
+            .iter()
+            .zip(&self.values)
+            .flat_map(|(label, value)| {
+                let [low, high] = label.to_le_bytes();
+                [low, high, *value]
+            })
+            .collect()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let (labels, values) = bytes
+            .chunks_exact(3)
+            .map(|chunk| (u16::from_le_bytes([chunk[0], chunk[1]]), chunk[2]))
+            .unzip();
+        Self::new(labels, values)
+    }
+
+    /// Cosine similarity, `0.0` for unrelated images and `1.0` for the same scores.
+    pub fn similarity(&self, other: &Self) -> f32 {
+        if self.norm == 0.0 || other.norm == 0.0 {
+            return 0.0;
+        }
+        let (mut i, mut j, mut dot) = (0, 0, 0u32);
+        while i < self.labels.len() && j < other.labels.len() {
+            match self.labels[i].cmp(&other.labels[j]) {
+                std::cmp::Ordering::Less => i += 1,
+                std::cmp::Ordering::Greater => j += 1,
+                std::cmp::Ordering::Equal => {
+                    dot += u32::from(self.values[i]) * u32::from(other.values[j]);
+                    i += 1;
+                    j += 1;
+                }
+            }
+        }
+        dot as f32 / (self.norm * other.norm)
+    }
+}
+
+impl Point for Embedding {
+    fn distance(&self, other: &Self) -> f32 {
+        1.0 - self.similarity(other)
+    }
+}
+
+/// Nearest neighbour index over the primary tagger's embeddings, kept in memory and rebuilt
+/// from the `image_embedding` table at startup.
+#[derive(Clone)]
+pub struct SimilarityIndex {
+    model: Arc<str>,
+    index: Arc<RwLock<Hnsw<Embedding>>>,
+}
+
+impl SimilarityIndex {
+    /// Builds the index over stored embeddings, see
+    /// [`Database::list_embeddings`](crate::database::Database::list_embeddings). Takes a while
+    /// on large libraries, so run it on the CPU pool.
+    pub fn build(model: &str, embeddings: Vec<(i64, Vec<u8>)>) -> Self {
+        let mut index = Hnsw::default();
+        for (image_id, bytes) in embeddings {
+            index.insert(image_id, Embedding::from_bytes(&bytes));
+        }
+        Self {
+            model: model.into(),
+            index: Arc::new(RwLock::new(index)),
+        }
+    }
+
+    /// The model whose embeddings are indexed.
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    pub fn insert(&self, image_id: i64, embedding: Embedding) {
+        self.index.write().unwrap().insert(image_id, embedding);
+    }
+
+    /// Up to `limit` images most similar to an embedding that need not be in the index.
+    pub fn similar_to(&self, embedding: &Embedding, limit: usize) -> Vec<(i64, f32)> {
+        self.index
+            .read()
+            .unwrap()
+            .search(embedding, limit, EF_SEARCH.max(limit))
+            .into_iter()
+            .map(|(id, distance)| (id, 1.0 - distance))
+            .collect()
+    }
+
+    /// Up to `limit` images most similar to `image_id` with their similarity, most similar
+    /// first. `None` when the image has no embedding.
+    pub fn related(&self, image_id: i64, limit: usize) -> Option<Vec<(i64, f32)>> {
+        let index = self.index.read().unwrap();
+        let embedding = index.get(image_id)?;
+        Some(
+            index
+                .search(embedding, limit + 1, EF_SEARCH.max(limit + 1))
+                .into_iter()
+                .filter(|(id, _)| *id != image_id)
+                .take(limit)
+                .map(|(id, distance)| (id, 1.0 - distance))
+                .collect(),
+        )
+    }
+}
+
+#[test]
+fn embeddings() {
+    let mut scores = vec![0.0; 1000];
+    scores[3] = 1.0;
+    scores[500] = 0.5;
+    scores[999] = 0.001;
+    let embedding = Embedding::from_scores(&scores);
+    assert_eq!(embedding.labels, vec![3, 500]);
+    assert_eq!(embedding.values, vec![255, 128]);
+    assert_eq!(Embedding::from_bytes(&embedding.to_bytes()), embedding);
+    assert!((embedding.similarity(&embedding) - 1.0).abs() < 1e-6);
+
+    let mut other = vec![0.0; 1000];
+    other[4] = 1.0;
+    assert_eq!(embedding.similarity(&Embedding::from_scores(&other)), 0.0);
+    other[500] = 0.5;
+    let partly = embedding.similarity(&Embedding::from_scores(&other));
+    assert!(partly > 0.0 && partly < 1.0);
+}
+
+#[test]
+fn related_images() {
+    let index = SimilarityIndex::build("test@v1", Vec::new());
+    for image_id in 0..20 {
+        let mut scores = vec![0.0; 100];
+        scores[(image_id % 4) as usize] = 1.0;
+        scores[50 + image_id as usize] = 0.2;
+        index.insert(image_id, Embedding::from_scores(&scores));
+    }
+    let related = index.related(1, 4).unwrap();
+    assert_eq!(related.len(), 4);
+    assert!(related.iter().all(|(id, _)| id % 4 == 1 && *id != 1));
+    assert!(index.related(100, 4).is_none());
+}
@@ -0,0 +1,147 @@
+use axum::body::Body;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+/// A satisfiable `Range` request, as first and last byte.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ByteRange {
+    start: u64,
+    end: u64,
+}
+
+/// The range a `Range` header asks for in a file of `len` bytes. `None` means the whole file,
+/// which is also the answer to a header that is not a single byte range; `Some(Err(()))`
+/// means the range lies past the end.
+fn parse_range(value: &str, len: u64) -> Option<Result<ByteRange, ()>> {
+    let spec = value.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+    let range = match (start.is_empty(), end.is_empty()) {
+        // `bytes=-500` is the last 500 bytes.
+        (true, false) => {
+            let suffix: u64 = end.parse().ok()?;
+            if suffix == 0 || len == 0 {
+                return Some(Err(()));
+            }
+            ByteRange {
+                start: len.saturating_sub(suffix),
+                end: len - 1,
+            }
+        }
+        (false, _) => {
+            let start: u64 = start.parse().ok()?;
+            let end = match end.is_empty() {
+                true => u64::MAX,
+                false => end.parse().ok()?,
+            };
+            if start > end {
+                return None;
+            }
+            if start >= len {
+                return Some(Err(()));
+            }
+            ByteRange {
+                start,
+                end: end.min(len - 1),
+            }
+        }
+        (true, true) => return None,
+    };
+    Some(Ok(range))
+}
+
+/// Serves a file, or the part of it a `Range` header asks for, so players can seek.
+pub async fn serve_file(path: &str, content_type: &str, headers: &HeaderMap) -> Response {
+    let mut file = match tokio::fs::File::open(path).await {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return (StatusCode::NOT_FOUND, "file not found").into_response()
+        }
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    };
+    let len = match file.metadata().await {
+        Ok(metadata) => metadata.len(),
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    };
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| parse_range(value, len));
+    let range = match range {
+        Some(Ok(range)) => Some(range),
+        Some(Err(())) => {
+            return (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(header::CONTENT_RANGE, format!("bytes */{len}"))],
+            )
+                .into_response()
+        }
+        None => None,
+    };
+    let Some(range) = range else {
+        return (
+            [
+                (header::CONTENT_TYPE, content_type.to_string()),
+                (header::CONTENT_LENGTH, len.to_string()),
+                (header::ACCEPT_RANGES, "bytes".to_string()),
+            ],
+            Body::from_stream(ReaderStream::new(file)),
+        )
+            .into_response();
+    };
+    if let Err(err) = file.seek(std::io::SeekFrom::Start(range.start)).await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response();
+    }
+    let part = range.end - range.start + 1;
+    (
+        StatusCode::PARTIAL_CONTENT,
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_LENGTH, part.to_string()),
+            (header::ACCEPT_RANGES, "bytes".to_string()),
+            (
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", range.start, range.end, len),
+            ),
+        ],
+        Body::from_stream(ReaderStream::new(file.take(part))),
+    )
+        .into_response()
+}
+
+/// Content type of an audio or video file from its extension.
+pub fn content_type(path: &str) -> &'static str {
+    let extension = std::path::Path::new(path)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    match extension.as_deref() {
+        Some("mp3") => "audio/mpeg",
+        Some("flac") => "audio/flac",
+        Some("ogg" | "oga" | "opus") => "audio/ogg",
+        Some("m4a" | "aac") => "audio/mp4",
+        Some("wav") => "audio/wav",
+        Some("mp4" | "m4v") => "video/mp4",
+        Some("webm") => "video/webm",
+        Some("mkv") => "video/x-matroska",
+        _ => "application/octet-stream",
+    }
+}
+
+#[test]
+fn byte_ranges() {
+    let range = |start, end| Some(Ok(ByteRange { start, end }));
+    assert_eq!(parse_range("bytes=0-99", 1000), range(0, 99));
+    assert_eq!(parse_range("bytes=500-", 1000), range(500, 999));
+    assert_eq!(parse_range("bytes=900-2000", 1000), range(900, 999));
+    assert_eq!(parse_range("bytes=-100", 1000), range(900, 999));
+    assert_eq!(parse_range("bytes=-5000", 1000), range(0, 999));
+    assert_eq!(parse_range("bytes=1000-", 1000), Some(Err(())));
+    assert_eq!(parse_range("bytes=0-1,5-9", 1000), None);
+    assert_eq!(parse_range("bytes=9-1", 1000), None);
+    assert_eq!(parse_range("items=0-1", 1000), None);
+}
@@ -0,0 +1,597 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use axum::extract::{FromRequest, Path, Query, Request, State};
+use axum::http::{header, HeaderMap, Method};
+use axum::response::{IntoResponse, Response};
+use axum::{async_trait, routing, Form, Router};
+use chrono::NaiveDateTime;
+use md5::{Digest, Md5};
+use serde_json::{json, Map, Value};
+
+use crate::api::{self, AppState};
+use crate::database::{self, AlbumRow, ArtistRow, Database, TrackRow};
+use crate::streaming;
+use crate::transcode::AudioFormat;
+
+/// The Subsonic API version the implemented methods follow.
+pub const API_VERSION: &str = "1.16.1";
+/// Leading words left out when indexing artists by letter.
+const IGNORED_ARTICLES: &[&str] = &["The", "A", "An"];
+
+#[derive(thiserror::Error, Debug)]
+enum Error {
+    #[error("required parameter {0} is missing")]
+    Missing(&'static str),
+    #[error("wrong username or password")]
+    Unauthorized,
+    #[error("the Subsonic API is off, set SUBSONIC_USER and SUBSONIC_PASSWORD to turn it on")]
+    Disabled,
+    #[error("{0} not found")]
+    NotFound(&'static str),
+    #[error("unknown method {0}")]
+    UnknownMethod(String),
+    #[error(transparent)]
+    Database(#[from] database::Error),
+}
+
+impl Error {
+    /// Subsonic error code.
+    fn code(&self) -> u32 {
+        match self {
+            Error::Missing(_) => 10,
+            Error::Unauthorized | Error::Disabled => 40,
+            Error::NotFound(_) => 70,
+            Error::UnknownMethod(_) | Error::Database(_) => 0,
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// The one Subsonic user, the only account the API accepts.
+#[derive(Clone)]
+pub struct Credentials {
+    user: String,
+    password: String,
+}
+
+impl Credentials {
+    /// From `SUBSONIC_USER` and `SUBSONIC_PASSWORD`, `None` unless both are set.
+    pub fn from_env() -> Option<Self> {
+        Some(Self {
+            user: dotenv::var("SUBSONIC_USER").ok()?,
+            password: dotenv::var("SUBSONIC_PASSWORD").ok()?,
+        })
+    }
+
+    /// Checks `u` with either the token `t`, the md5 of the password and salt `s`, or the
+    /// password `p`, plain or hex encoded after `enc:`.
+    fn check(&self, params: &HashMap<String, String>) -> Result<()> {
+        let user = params.get("u").ok_or(Error::Missing("u"))?;
+        let matches = match (params.get("t"), params.get("s"), params.get("p")) {
+            (Some(token), Some(salt), _) => {
+                let digest = Md5::digest(format!("{}{}", self.password, salt));
+                token.eq_ignore_ascii_case(&format!("{digest:x}"))
+            }
+            (_, _, Some(password)) => match password.strip_prefix("enc:") {
+                Some(hex) => decode_hex(hex).is_some_and(|password| password == self.password),
+                None => *password == self.password,
+            },
+            _ => return Err(Error::Missing("t")),
+        };
+        match matches && *user == self.user {
+            true => Ok(()),
+            false => Err(Error::Unauthorized),
+        }
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<String> {
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+/// Response format asked for with `f`; Subsonic defaults to XML.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Xml,
+    Json,
+}
+
+impl Format {
+    fn of(params: &HashMap<String, String>) -> Self {
+        match params.get("f").map(String::as_str) {
+            Some("json") => Format::Json,
+            _ => Format::Xml,
+        }
+    }
+
+    /// Wraps a method's result in a `subsonic-response`. Failures are still HTTP 200, as
+    /// clients expect.
+    fn respond(self, result: Result<Value>) -> Response {
+        let mut body = match result {
+            Ok(Value::Object(body)) => body,
+            Ok(_) => Map::new(),
+            Err(err) => {
+                let mut body = Map::new();
+                body.insert(
+                    "error".to_string(),
+                    json!({ "code": err.code(), "message": err.to_string() }),
+                );
+                body
+            }
+        };
+        let status = match body.contains_key("error") {
+            true => "failed",
+            false => "ok",
+        };
+        body.insert("status".to_string(), status.into());
+        body.insert("version".to_string(), API_VERSION.into());
+        body.insert("type".to_string(), "mediamon".into());
+        body.insert(
+            "serverVersion".to_string(),
+            env!("CARGO_PKG_VERSION").into(),
+        );
+        body.insert("openSubsonic".to_string(), true.into());
+        let mut body = Value::Object(body);
+        strip_nulls(&mut body);
+        match self {
+            Format::Json => {
+                let body = json!({ "subsonic-response": body });
+                (
+                    [(header::CONTENT_TYPE, "application/json")],
+                    body.to_string(),
+                )
+                    .into_response()
+            }
+            Format::Xml => {
+                if let Value::Object(body) = &mut body {
+                    body.insert("xmlns".to_string(), "http://subsonic.org/restapi".into());
+                }
+                let mut xml = String::from(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+                write_xml(&mut xml, "subsonic-response", &body);
+                ([(header::CONTENT_TYPE, "text/xml; charset=utf-8")], xml).into_response()
+            }
+        }
+    }
+}
+
+/// Missing optional fields are left out rather than sent as `null`.
+fn strip_nulls(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            map.retain(|_, value| !value.is_null());
+            map.values_mut().for_each(strip_nulls);
+        }
+        Value::Array(items) => items.iter_mut().for_each(strip_nulls),
+        _ => {}
+    }
+}
+
+/// Writes JSON the way Subsonic maps it to XML: objects are elements, their scalar fields
+/// attributes, and arrays repeated elements.
+fn write_xml(xml: &mut String, name: &str, value: &Value) {
+    match value {
+        Value::Object(map) => {
+            xml.push('<');
+            xml.push_str(name);
+            let mut children = Vec::new();
+            for (key, value) in map {
+                match value {
+                    Value::Object(_) | Value::Array(_) => children.push((key, value)),
+                    Value::String(text) => push_attribute(xml, key, text),
+                    Value::Null => {}
+                    _ => push_attribute(xml, key, &value.to_string()),
+                }
+            }
+            if children.is_empty() {
+                xml.push_str("/>");
+                return;
+            }
+            xml.push('>');
+            for (key, value) in children {
+                write_xml(xml, key, value);
+            }
+            xml.push_str("</");
+            xml.push_str(name);
+            xml.push('>');
+        }
+        Value::Array(items) => {
+            for item in items {
+                write_xml(xml, name, item);
+            }
+        }
+        Value::Null => {}
+        Value::String(text) => {
+            xml.push_str(&format!("<{name}>"));
+            push_escaped(xml, text);
+            xml.push_str(&format!("</{name}>"));
+        }
+        _ => xml.push_str(&format!("<{name}>{value}</{name}>")),
+    }
+}
+
+fn push_attribute(xml: &mut String, key: &str, value: &str) {
+    xml.push(' ');
+    xml.push_str(key);
+    xml.push_str("=\"");
+    push_escaped(xml, value);
+    xml.push('"');
+}
+
+fn push_escaped(xml: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => xml.push_str("&amp;"),
+            '<' => xml.push_str("&lt;"),
+            '>' => xml.push_str("&gt;"),
+            '"' => xml.push_str("&quot;"),
+            '\'' => xml.push_str("&apos;"),
+            _ => xml.push(c),
+        }
+    }
+}
+
+/// The `/rest/*` routes, each method also answering with the `.view` suffix older clients
+/// add.
+pub(crate) fn router() -> Router<Arc<AppState>> {
+    Router::new().route("/rest/:method", routing::get(rest).post(rest))
+}
+
+/// Request parameters, from the query string and, for POST requests, a form body. The
+/// Subsonic API allows either.
+struct Params(HashMap<String, String>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequest<S> for Params {
+    type Rejection = Response;
+
+    async fn from_request(request: Request, state: &S) -> std::result::Result<Self, Response> {
+        let Query(mut params) = Query::<HashMap<String, String>>::try_from_uri(request.uri())
+            .map_err(IntoResponse::into_response)?;
+        let form = request
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .is_some_and(|content_type| {
+                content_type
+                    .as_bytes()
+                    .starts_with(b"application/x-www-form-urlencoded")
+            });
+        if request.method() == Method::POST && form {
+            let Form(body) = Form::<HashMap<String, String>>::from_request(request, state)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            params.extend(body);
+        }
+        Ok(Self(params))
+    }
+}
+
+async fn rest(
+    State(state): State<Arc<AppState>>,
+    Path(method): Path<String>,
+    headers: HeaderMap,
+    Params(params): Params,
+) -> Response {
+    let format = Format::of(&params);
+    let authenticated = match &state.subsonic {
+        Some(credentials) => credentials.check(&params),
+        None => Err(Error::Disabled),
+    };
+    if let Err(err) = authenticated {
+        return format.respond(Err(err));
+    }
+    let db = &state.db;
+    let method = method.strip_suffix(".view").unwrap_or(&method);
+    let result = match method {
+        "ping" => Ok(json!({})),
+        "getArtists" => get_artists(db).await,
+        "getArtist" => get_artist(db, &params).await,
+        "getAlbum" => get_album(db, &params).await,
+        "getSong" => get_song(db, &params).await,
+        "search3" => search3(db, &params).await,
+        "getPlaylists" => Ok(json!({ "playlists": { "playlist": [] } })),
+        "stream" | "download" => match id(&params, "id", "song") {
+            Ok(id) => match db.get_track(id).await {
+                Ok(Some(track)) => {
+                    let format = params
+                        .get("format")
+                        .and_then(|format| AudioFormat::from_name(format));
+                    // `maxBitRate` 0 means no limit.
+                    let max_bit_rate = params
+                        .get("maxBitRate")
+                        .and_then(|bit_rate| bit_rate.parse::<u32>().ok())
+                        .filter(|bit_rate| *bit_rate > 0);
+                    let too_high = max_bit_rate.is_some_and(|max| {
+                        track
+                            .bit_rate()
+                            .is_some_and(|bit_rate| bit_rate > i64::from(max) * 1000)
+                    });
+                    let format = match (format, too_high) {
+                        (Some(format), _) => Some(format),
+                        (None, true) if method == "stream" => Some(AudioFormat::Mp3),
+                        _ => None,
+                    };
+                    return match format {
+                        Some(format) => {
+                            let bit_rate = max_bit_rate.unwrap_or(128).clamp(32, 320);
+                            api::transcode_track(&state, &track, format, bit_rate, &headers).await
+                        }
+                        None => {
+                            let path = track.path();
+                            streaming::serve_file(path, streaming::content_type(path), &headers)
+                                .await
+                        }
+                    };
+                }
+                Ok(None) => Err(Error::NotFound("song")),
+                Err(err) => Err(err.into()),
+            },
+            Err(err) => Err(err),
+        },
+        "getCoverArt" => match id(&params, "id", "cover art") {
+            Ok(id) => match db.get_cover(id).await {
+                Ok(Some((mime, data))) => {
+                    return ([(header::CONTENT_TYPE, mime)], data).into_response()
+                }
+                Ok(None) => Err(Error::NotFound("cover art")),
+                Err(err) => Err(err.into()),
+            },
+            Err(err) => Err(err),
+        },
+        _ => Err(Error::UnknownMethod(method.to_string())),
+    };
+    format.respond(result)
+}
+
+/// A numeric id parameter. Ids that are not numbers cannot exist, so they are not found.
+fn id(params: &HashMap<String, String>, name: &'static str, what: &'static str) -> Result<i64> {
+    params
+        .get(name)
+        .ok_or(Error::Missing(name))?
+        .parse()
+        .map_err(|_| Error::NotFound(what))
+}
+
+fn count(params: &HashMap<String, String>, name: &str, default: i64) -> i64 {
+    params
+        .get(name)
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+        .clamp(0, 500)
+}
+
+async fn get_artists(db: &Database) -> Result<Value> {
+    let mut index: BTreeMap<String, Vec<Value>> = BTreeMap::new();
+    for artist in db.list_artists(i64::MAX, 0).await? {
+        let name = artist.name();
+        let sort_name = IGNORED_ARTICLES
+            .iter()
+            .find_map(|article| {
+                name.strip_prefix(article)
+                    .and_then(|rest| rest.strip_prefix(' '))
+            })
+            .unwrap_or(name);
+        let letter = match sort_name.chars().next() {
+            Some(c) if c.is_alphabetic() => c.to_uppercase().to_string(),
+            _ => "#".to_string(),
+        };
+        index.entry(letter).or_default().push(artist_json(&artist));
+    }
+    let index: Vec<Value> = index
+        .into_iter()
+        .map(|(name, artists)| json!({ "name": name, "artist": artists }))
+        .collect();
+    Ok(json!({
+        "artists": {
+            "ignoredArticles": IGNORED_ARTICLES.join(" "),
+            "index": index,
+        }
+    }))
+}
+
+async fn get_artist(db: &Database, params: &HashMap<String, String>) -> Result<Value> {
+    let artist_id = id(params, "id", "artist")?;
+    let artist = db
+        .get_artist(artist_id)
+        .await?
+        .ok_or(Error::NotFound("artist"))?;
+    let albums: Vec<Value> = db
+        .list_artist_albums(artist_id)
+        .await?
+        .iter()
+        .map(album_json)
+        .collect();
+    let mut artist = artist_json(&artist);
+    artist["album"] = albums.into();
+    Ok(json!({ "artist": artist }))
+}
+
+async fn get_album(db: &Database, params: &HashMap<String, String>) -> Result<Value> {
+    let album_id = id(params, "id", "album")?;
+    let album = db
+        .get_album(album_id)
+        .await?
+        .ok_or(Error::NotFound("album"))?;
+    let songs: Vec<Value> = db
+        .list_album_tracks(album_id)
+        .await?
+        .iter()
+        .map(song_json)
+        .collect();
+    let mut album = album_json(&album);
+    album["song"] = songs.into();
+    Ok(json!({ "album": album }))
+}
+
+async fn get_song(db: &Database, params: &HashMap<String, String>) -> Result<Value> {
+    let track = db
+        .get_track(id(params, "id", "song")?)
+        .await?
+        .ok_or(Error::NotFound("song"))?;
+    Ok(json!({ "song": song_json(&track) }))
+}
+
+async fn search3(db: &Database, params: &HashMap<String, String>) -> Result<Value> {
+    let query = params.get("query").ok_or(Error::Missing("query"))?;
+    // Clients list the whole library with an empty query, some sending it as `""`.
+    let query = query.trim().trim_matches('"');
+    let artists = db
+        .search_artists(
+            query,
+            count(params, "artistCount", 20),
+            count(params, "artistOffset", 0),
+        )
+        .await?;
+    let albums = db
+        .search_albums(
+            query,
+            count(params, "albumCount", 20),
+            count(params, "albumOffset", 0),
+        )
+        .await?;
+    let tracks = db
+        .search_tracks(
+            query,
+            count(params, "songCount", 20),
+            count(params, "songOffset", 0),
+        )
+        .await?;
+    Ok(json!({
+        "searchResult3": {
+            "artist": artists.iter().map(artist_json).collect::<Vec<_>>(),
+            "album": albums.iter().map(album_json).collect::<Vec<_>>(),
+            "song": tracks.iter().map(song_json).collect::<Vec<_>>(),
+        }
+    }))
+}
+
+fn created(date: NaiveDateTime) -> String {
+    date.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+fn seconds(duration: Option<f64>) -> Option<i64> {
+    duration.map(|duration| duration.round() as i64)
+}
+
+fn artist_json(artist: &ArtistRow) -> Value {
+    json!({
+        "id": artist.artist_id().to_string(),
+        "name": artist.name(),
+        "albumCount": artist.album_count(),
+    })
+}
+
+fn album_json(album: &AlbumRow) -> Value {
+    json!({
+        "id": album.album_id().to_string(),
+        "name": album.title(),
+        "artist": album.artist(),
+        "artistId": album.artist_id().map(|id| id.to_string()),
+        "coverArt": album.cover_id().map(|id| id.to_string()),
+        "songCount": album.track_count(),
+        "duration": seconds(album.duration()).unwrap_or(0),
+        "created": created(album.date_added()),
+        "year": album.year(),
+        "genre": album.genre(),
+    })
+}
+
+fn song_json(track: &TrackRow) -> Value {
+    let path = std::path::Path::new(track.path());
+    json!({
+        "id": track.track_id().to_string(),
+        "parent": track.album_id().map(|id| id.to_string()),
+        "isDir": false,
+        "title": track.title(),
+        "album": track.album(),
+        "artist": track.artist(),
+        "track": track.track_number(),
+        "discNumber": track.disc_number(),
+        "year": track.year(),
+        "genre": track.genre(),
+        "coverArt": track.cover_id().map(|id| id.to_string()),
+        "size": track.size(),
+        "contentType": streaming::content_type(track.path()),
+        "suffix": path.extension().map(|extension| extension.to_string_lossy()),
+        "duration": seconds(track.duration()),
+        "bitRate": track.bit_rate().map(|bit_rate| bit_rate / 1000),
+        "path": path.file_name().map(|name| name.to_string_lossy()),
+        "albumId": track.album_id().map(|id| id.to_string()),
+        "artistId": track.artist_id().map(|id| id.to_string()),
+        "type": "music",
+        "created": created(track.date_added()),
+    })
+}
+
+#[test]
+fn token_authentication() {
+    let credentials = Credentials {
+        user: "admin".to_string(),
+        password: "sesame".to_string(),
+    };
+    let params = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    };
+    let token = format!("{:x}", Md5::digest("sesamec19b2d"));
+    assert!(credentials
+        .check(&params(&[("u", "admin"), ("t", &token), ("s", "c19b2d")]))
+        .is_ok());
+    assert!(credentials
+        .check(&params(&[("u", "admin"), ("t", &token), ("s", "other")]))
+        .is_err());
+    assert!(credentials
+        .check(&params(&[("u", "admin"), ("p", "enc:736573616d65")]))
+        .is_ok());
+    assert!(credentials
+        .check(&params(&[("u", "someone"), ("p", "sesame")]))
+        .is_err());
+    assert!(matches!(
+        credentials.check(&params(&[("u", "admin")])),
+        Err(Error::Missing("t"))
+    ));
+}
+
+#[tokio::test]
+async fn form_parameters() {
+    let credentials = Credentials {
+        user: "admin".to_string(),
+        password: "sesame".to_string(),
+    };
+    let token = format!("{:x}", Md5::digest("sesamec19b2d"));
+    let request = Request::post("/rest/getSong.view?u=admin&f=json")
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(axum::body::Body::from(format!("t={token}&s=c19b2d&id=7")))
+        .unwrap();
+    let Params(params) = Params::from_request(request, &()).await.unwrap();
+    assert!(credentials.check(&params).is_ok());
+    assert_eq!(params["f"], "json");
+    assert_eq!(params["id"], "7");
+    // Parameters can stay in the query string of a POST without a form body.
+    let request = Request::post("/rest/ping.view?u=admin")
+        .body(axum::body::Body::empty())
+        .unwrap();
+    let Params(params) = Params::from_request(request, &()).await.unwrap();
+    assert_eq!(params.len(), 1);
+}
+
+#[test]
+fn xml_rendering() {
+    let mut xml = String::new();
+    write_xml(
+        &mut xml,
+        "album",
+        &json!({ "id": "1", "name": "A & B", "song": [{ "id": "2" }, { "id": "3" }] }),
+    );
+    assert_eq!(
+        xml,
+        r#"<album id="1" name="A &amp; B"><song id="2"/><song id="3"/></album>"#
+    );
+}
@@ -0,0 +1,223 @@
+use std::path::Path;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::ffmpeg::{self, Probe};
+
+/// Subtitle codecs ffmpeg can convert to WebVTT; bitmap subtitles have no text.
+const TEXT_CODECS: &[&str] = &["subrip", "ass", "ssa", "mov_text", "webvtt", "text"];
+/// Language of embedded subtitles that do not name one.
+const UNDETERMINED: &str = "und";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum SubtitleSource {
+    /// A `<name>.<language>.vtt` file next to the video, as yt-dlp writes them.
+    Sidecar,
+    /// A text stream in the video file.
+    Embedded,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Subtitle {
+    pub language: String,
+    pub source: SubtitleSource,
+    pub vtt: String,
+}
+
+/// A cue with its markup removed.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Cue {
+    /// Seconds.
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+}
+
+/// Whether `language` looks like a language tag, e.g. `en` or `pt-BR`, so it can be used in
+/// file names and URLs.
+pub fn is_language(language: &str) -> bool {
+    (1..=35).contains(&language.len())
+        && language
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// The subtitles of the video at `path`: sidecar files first, then embedded text streams in
+/// languages no sidecar has. Subtitles that cannot be read are skipped.
+pub async fn collect(path: &str, probe: &Probe) -> Vec<Subtitle> {
+    let mut subtitles = sidecars(Path::new(path)).await;
+    for stream in &probe.streams {
+        let text = stream
+            .codec_name
+            .as_deref()
+            .is_some_and(|codec| TEXT_CODECS.contains(&codec));
+        if stream.codec_type != "subtitle" || !text {
+            continue;
+        }
+        let language = stream
+            .language
+            .as_deref()
+            .filter(|language| is_language(language))
+            .unwrap_or(UNDETERMINED);
+        if subtitles
+            .iter()
+            .any(|subtitle| subtitle.language == language)
+        {
+            continue;
+        }
+        match ffmpeg::extract_subtitle(path, stream.index).await {
+            Ok(vtt) => subtitles.push(Subtitle {
+                language: language.to_string(),
+                source: SubtitleSource::Embedded,
+                vtt,
+            }),
+            Err(err) => warn!(
+                "failed to extract subtitle stream {} of {}: {}",
+                stream.index, path, err
+            ),
+        }
+    }
+    subtitles
+}
+
+async fn sidecars(path: &Path) -> Vec<Subtitle> {
+    let mut subtitles = Vec::new();
+    let (Some(dir), Some(stem)) = (path.parent(), path.file_stem()) else {
+        return subtitles;
+    };
+    let prefix = format!("{}.", stem.to_string_lossy());
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return subtitles;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let Some(language) = name
+            .strip_prefix(&prefix)
+            .and_then(|rest| rest.strip_suffix(".vtt"))
+            .filter(|language| is_language(language))
+        else {
+            continue;
+        };
+        match tokio::fs::read(entry.path()).await {
+            Ok(vtt) => subtitles.push(Subtitle {
+                language: language.to_string(),
+                source: SubtitleSource::Sidecar,
+                vtt: String::from_utf8_lossy(&vtt).into_owned(),
+            }),
+            Err(err) => warn!("failed to read {}: {}", entry.path().display(), err),
+        }
+    }
+    subtitles.sort_by(|a, b| a.language.cmp(&b.language));
+    subtitles
+}
+
+/// The cues of a WebVTT file. Auto-captions repeat each line in the cue after it as they
+/// roll, so a line that repeats the one before is dropped, and so are cues left empty.
+pub fn cues(vtt: &str) -> Vec<Cue> {
+    let mut cues = Vec::new();
+    let mut last_line = String::new();
+    let vtt = vtt.replace("\r\n", "\n");
+    for block in vtt.split("\n\n") {
+        let mut lines = block.lines().skip_while(|line| !line.contains("-->"));
+        // Headers, notes, styles and regions have no timing line.
+        let Some(timing) = lines.next() else {
+            continue;
+        };
+        let Some((start, end)) = timing.split_once("-->") else {
+            continue;
+        };
+        let end = end.split_whitespace().next().unwrap_or_default();
+        let (Some(start), Some(end)) = (parse_timestamp(start.trim()), parse_timestamp(end)) else {
+            continue;
+        };
+        let mut text = Vec::new();
+        for line in lines {
+            let line = strip_markup(line);
+            if line.is_empty() || line == last_line {
+                continue;
+            }
+            last_line = line.clone();
+            text.push(line);
+        }
+        if !text.is_empty() {
+            cues.push(Cue {
+                start,
+                end,
+                text: text.join("\n"),
+            });
+        }
+    }
+    cues
+}
+
+/// What is said in a WebVTT file, a line per line of text.
+pub fn text(vtt: &str) -> String {
+    cues(vtt)
+        .into_iter()
+        .map(|cue| cue.text)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// `hh:mm:ss.ttt` or `mm:ss.ttt` in seconds.
+fn parse_timestamp(timestamp: &str) -> Option<f64> {
+    let mut seconds = 0.0;
+    for part in timestamp.split(':') {
+        seconds = seconds * 60.0 + part.parse::<f64>().ok()?;
+    }
+    Some(seconds)
+}
+
+/// Drops tags such as `<i>`, `<c>` and the word timings of auto-captions, and decodes the
+/// entities WebVTT escapes text with.
+fn strip_markup(line: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in line.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+        .trim()
+        .to_string()
+}
+
+#[test]
+fn vtt_text() {
+    let vtt = "WEBVTT\r\nKind: captions\r\nLanguage: en\r\n\r\n\
+        NOTE made by hand\r\n\r\n\
+        1\r\n00:00:01.000 --> 00:00:03.500 align:start position:0%\r\n\
+        <i>Hello</i> &amp; welcome\r\n\r\n\
+        00:03.500 --> 00:00:03.510\r\nHello &amp; welcome\r\n\r\n\
+        00:00:03.510 --> 00:00:06.000\r\nHello &amp; welcome\r\n\
+        to<00:00:04.000><c> the</c><00:00:04.500><c> show</c>\r\n";
+    assert_eq!(
+        cues(vtt),
+        vec![
+            Cue {
+                start: 1.0,
+                end: 3.5,
+                text: "Hello & welcome".to_string()
+            },
+            Cue {
+                start: 3.51,
+                end: 6.0,
+                text: "to the show".to_string()
+            },
+        ]
+    );
+    assert_eq!(text(vtt), "Hello & welcome\nto the show");
+    assert!(is_language("pt-BR"));
+    assert!(!is_language("../en"));
+    assert!(!is_language(""));
+}
@@ -0,0 +1,473 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use image::DynamicImage;
+use ndarray::{Array4, Axis, CowArray};
+use ort::{
+    tensor::OrtOwnedTensor, Environment, ExecutionProvider, GraphOptimizationLevel, Session,
+    SessionBuilder, Value,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::deepbooru::Jarvis;
+use crate::media::TagCategory;
+use crate::preprocess;
+use crate::wd14::Wd14;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("ORT {0}")]
+    Ort(#[from] ort::OrtError),
+    #[error("IO {0}")]
+    IO(#[from] std::io::Error),
+    #[error("CONFIG {0}")]
+    Config(#[from] serde_json::Error),
+    #[error("LABELS {0}")]
+    Labels(String),
+    #[error("unknown label {0}")]
+    UnknownLabel(String),
+    #[error("ANY {0}")]
+    ANY(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Tags images with a fixed label vocabulary, keeping only confident labels.
+pub trait Tagger: Send + Sync {
+    fn descriptor(&self) -> &ModelDescriptor;
+
+    /// One label per model output, in output order.
+    fn labels(&self) -> &[Label];
+
+    fn thresholds(&self) -> &RwLock<Thresholds>;
+
+    /// Raw model output for each image, one score per label.
+    fn scores(&self, images: &[DynamicImage]) -> Result<Vec<Vec<f32>>>;
+
+    /// Kept `(score, label index)` pairs for each image, in the same order as `images`.
+    fn infer_batch(&self, images: &[DynamicImage]) -> Result<Vec<Vec<(f32, usize)>>> {
+        if images.is_empty() {
+            return Ok(Vec::new());
+        }
+        let scores = self.scores(images)?;
+        Ok(scores.iter().map(|scores| self.select(scores)).collect())
+    }
+
+    /// Applies the current thresholds to one image's raw scores.
+    fn select(&self, scores: &[f32]) -> Vec<(f32, usize)> {
+        self.thresholds()
+            .read()
+            .unwrap()
+            .select(scores, self.labels())
+    }
+
+    fn infer_tags(&self, image: &DynamicImage) -> Result<Vec<(f32, usize)>> {
+        let mut tags = self.infer_batch(std::slice::from_ref(image))?;
+        Ok(tags.pop().expect("one result per image"))
+    }
+
+    fn config(&self) -> TaggerConfig {
+        self.thresholds().read().unwrap().config.clone()
+    }
+
+    fn set_config(&self, config: TaggerConfig) -> Result<()> {
+        let thresholds = Thresholds::new(config, self.labels())?;
+        *self.thresholds().write().unwrap() = thresholds;
+        Ok(())
+    }
+
+    /// Sets or, given `None`, clears the minimum score for a single label.
+    fn set_override(&self, name: &str, min_score: Option<f32>) -> Result<()> {
+        let index = self
+            .labels()
+            .iter()
+            .position(|label| label.name == name)
+            .ok_or_else(|| Error::UnknownLabel(name.to_string()))?;
+        let mut thresholds = self.thresholds().write().unwrap();
+        match min_score {
+            Some(min_score) => {
+                thresholds.overrides.insert(index, min_score);
+                thresholds
+                    .config
+                    .overrides
+                    .insert(name.to_string(), min_score);
+            }
+            None => {
+                thresholds.overrides.remove(&index);
+                thresholds.config.overrides.remove(name);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Builds the tagger a descriptor asks for.
+pub fn load(descriptor: ModelDescriptor) -> Result<Arc<dyn Tagger>> {
+    Ok(match descriptor.kind {
+        TaggerKind::DeepDanbooru => Arc::new(Jarvis::new(descriptor)?),
+        TaggerKind::Wd14 => Arc::new(Wd14::new(descriptor)?),
+    })
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TaggerKind {
+    DeepDanbooru,
+    Wd14,
+}
+
+/// How pixel values are scaled before they reach the model.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Normalization {
+    /// `0.0..=1.0`
+    #[default]
+    Unit,
+    /// `0.0..=255.0`
+    Byte,
+    /// `-1.0..=1.0`
+    Signed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelOrder {
+    #[default]
+    Rgb,
+    Bgr,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    #[default]
+    Nhwc,
+    Nchw,
+}
+
+/// Everything needed to load a tagger and feed it images.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ModelDescriptor {
+    pub kind: TaggerKind,
+    pub name: String,
+    pub version: String,
+    pub model_path: PathBuf,
+    /// `tags.txt` style list for DeepDanbooru, `selected_tags.csv` style table for WD14.
+    pub labels_path: PathBuf,
+    /// Side of the square input image.
+    pub input_size: u32,
+    #[serde(default)]
+    pub normalization: Normalization,
+    #[serde(default)]
+    pub channel_order: ChannelOrder,
+    #[serde(default)]
+    pub layout: Layout,
+    #[serde(default)]
+    pub thresholds: TaggerConfig,
+}
+
+impl ModelDescriptor {
+    pub fn deepdanbooru() -> Self {
+        Self {
+            kind: TaggerKind::DeepDanbooru,
+            name: "deepdanbooru".to_string(),
+            version: "v3".to_string(),
+            model_path: PathBuf::from("deepdanbooru.onnx"),
+            labels_path: PathBuf::from("tags.txt"),
+            input_size: 512,
+            normalization: Normalization::Unit,
+            channel_order: ChannelOrder::Rgb,
+            layout: Layout::Nhwc,
+            thresholds: TaggerConfig::default(),
+        }
+    }
+
+    /// Reads a JSON list of descriptors. The first one is the primary tagger.
+    pub fn load_all(path: impl AsRef<Path>) -> Result<Vec<Self>> {
+        let descriptors = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&descriptors)?)
+    }
+
+    /// Identifies the model and version in tag sources and stored results.
+    pub fn id(&self) -> String {
+        format!("{}@{}", self.name, self.version)
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Label {
+    pub name: String,
+    pub category: TagCategory,
+}
+
+/// How many tags of one category to keep and how confident the model has to be.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct CategoryLimit {
+    pub min_score: f32,
+    pub max_count: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TaggerConfig {
+    pub general: CategoryLimit,
+    pub character: CategoryLimit,
+    pub rating: CategoryLimit,
+    /// Minimum scores for individual labels by name, replacing their category's `min_score`.
+    #[serde(default)]
+    pub overrides: BTreeMap<String, f32>,
+}
+
+impl Default for TaggerConfig {
+    fn default() -> Self {
+        Self {
+            general: CategoryLimit {
+                min_score: 0.5,
+                max_count: 40,
+            },
+            character: CategoryLimit {
+                min_score: 0.5,
+                max_count: 5,
+            },
+            rating: CategoryLimit {
+                min_score: 0.0,
+                max_count: 1,
+            },
+            overrides: BTreeMap::new(),
+        }
+    }
+}
+
+/// The active config along with its overrides keyed by label index.
+pub struct Thresholds {
+    config: TaggerConfig,
+    overrides: HashMap<usize, f32>,
+}
+
+impl Thresholds {
+    pub fn new(config: TaggerConfig, labels: &[Label]) -> Result<Self> {
+        let mut overrides = HashMap::with_capacity(config.overrides.len());
+        for (name, min_score) in &config.overrides {
+            let index = labels
+                .iter()
+                .position(|label| &label.name == name)
+                .ok_or_else(|| Error::UnknownLabel(name.clone()))?;
+            overrides.insert(index, *min_score);
+        }
+        Ok(Self { config, overrides })
+    }
+
+    /// Applies each category's limit to one image's scores.
+    fn select(&self, scores: &[f32], labels: &[Label]) -> Vec<(f32, usize)> {
+        let mut general = Vec::new();
+        let mut characters = Vec::new();
+        let mut rating = Vec::new();
+        for (index, (score, label)) in scores.iter().zip(labels).enumerate() {
+            match label.category {
+                TagCategory::Character => characters.push((*score, index)),
+                TagCategory::Rating => rating.push((*score, index)),
+                _ => general.push((*score, index)),
+            }
+        }
+        self.keep(&mut general, self.config.general);
+        self.keep(&mut characters, self.config.character);
+        self.keep(&mut rating, self.config.rating);
+        let mut tags = general;
+        tags.append(&mut characters);
+        tags.append(&mut rating);
+        tags
+    }
+
+    fn keep(&self, tags: &mut Vec<(f32, usize)>, limit: CategoryLimit) {
+        tags.retain(|(score, tag)| {
+            *score >= self.overrides.get(tag).copied().unwrap_or(limit.min_score)
+        });
+        tags.sort_unstable_by(|a, b| b.0.total_cmp(&a.0));
+        tags.truncate(limit.max_count);
+    }
+}
+
+pub(crate) fn session(descriptor: &ModelDescriptor) -> Result<Session> {
+    let environment = Environment::builder()
+        .with_name(&descriptor.name)
+        .with_execution_providers([ExecutionProvider::CPU(Default::default())])
+        .build()?
+        .into_arc();
+    let session = SessionBuilder::new(&environment)?
+        .with_optimization_level(GraphOptimizationLevel::Level3)?
+        .with_intra_threads(std::thread::available_parallelism()?.get() as i16)?
+        .with_model_from_file(&descriptor.model_path)?;
+    Ok(session)
+}
+
+/// Letterboxes, normalizes and stacks `images` into the tensor `descriptor` describes.
+pub(crate) fn to_tensor(images: &[DynamicImage], descriptor: &ModelDescriptor) -> Array4<f32> {
+    let size = descriptor.input_size;
+    let side = size as usize;
+    let mut batch = Vec::with_capacity(images.len() * side * side * 3);
+    for image in images {
+        batch.extend_from_slice(&preprocess::letterbox(image, size, size));
+    }
+    let batch = Array4::from_shape_vec((images.len(), side, side, 3), batch)
+        .expect("This should never fail");
+    let mut batch = batch.mapv(|e| match descriptor.normalization {
+        Normalization::Unit => f32::from(e) / 255.0,
+        Normalization::Byte => f32::from(e),
+        Normalization::Signed => f32::from(e) / 127.5 - 1.0,
+    });
+    if descriptor.channel_order == ChannelOrder::Bgr {
+        batch.invert_axis(Axis(3));
+    }
+    match descriptor.layout {
+        Layout::Nhwc => batch,
+        Layout::Nchw => batch.permuted_axes([0, 3, 1, 2]),
+    }
+}
+
+/// Runs a single input tensor through `session`, returning one row of scores per image.
+pub(crate) fn run(session: &Session, tensor: Array4<f32>) -> Result<Vec<Vec<f32>>> {
+    let tensor = CowArray::from(tensor.as_standard_layout().into_owned()).into_dyn();
+    let inputs = vec![Value::from_array(session.allocator(), &tensor)?];
+    let outputs: Vec<Value> = session.run(inputs)?;
+    let scores: OrtOwnedTensor<f32, _> = outputs[0].try_extract()?;
+    let scores = scores.view();
+    Ok(scores
+        .axis_iter(Axis(0))
+        .map(|scores| scores.iter().copied().collect())
+        .collect())
+}
+
+/// Reads a `selected_tags.csv` style label table. Only the `name` column is required;
+/// `category` holds Danbooru's numeric categories and `threshold` per label minimum scores.
+pub fn read_label_csv(path: impl AsRef<Path>) -> Result<(Vec<Label>, BTreeMap<String, f32>)> {
+    let csv = std::fs::read_to_string(path)?;
+    let mut lines = csv.lines().filter(|line| !line.trim().is_empty());
+    let header = split_csv_line(lines.next().unwrap_or_default());
+    let column = |name: &str| header.iter().position(|column| column.trim() == name);
+    let name_column = column("name").ok_or_else(|| Error::Labels("no name column".to_string()))?;
+    let category_column = column("category");
+    let threshold_column = column("threshold");
+
+    let mut labels = Vec::new();
+    let mut thresholds = BTreeMap::new();
+    for (row, line) in lines.enumerate() {
+        let fields = split_csv_line(line);
+        let field = |column: usize| fields.get(column).map(|field| field.trim());
+        let name = field(name_column)
+            .filter(|name| !name.is_empty())
+            .ok_or_else(|| Error::Labels(format!("row {} has no name", row + 1)))?;
+        let category = match category_column.and_then(field) {
+            Some(category) => danbooru_category(category)
+                .ok_or_else(|| Error::Labels(format!("unknown category {category}")))?,
+            None => TagCategory::General,
+        };
+        let name = match category {
+            TagCategory::Rating if !name.starts_with("rating:") => format!("rating:{name}"),
+            _ => name.to_string(),
+        };
+        if let Some(threshold) = threshold_column.and_then(field).filter(|t| !t.is_empty()) {
+            let threshold = threshold
+                .parse()
+                .map_err(|_| Error::Labels(format!("bad threshold {threshold} for {name}")))?;
+            thresholds.insert(name.clone(), threshold);
+        }
+        labels.push(Label { name, category });
+    }
+    Ok((labels, thresholds))
+}
+
+fn danbooru_category(category: &str) -> Option<TagCategory> {
+    match category {
+        "0" => Some(TagCategory::General),
+        "1" => Some(TagCategory::Artist),
+        "3" => Some(TagCategory::Copyright),
+        "4" => Some(TagCategory::Character),
+        "5" => Some(TagCategory::Meta),
+        "9" => Some(TagCategory::Rating),
+        category => TagCategory::from_namespace(category),
+    }
+}
+
+/// Splits a CSV line on commas outside double quotes, unescaping `""`.
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+#[test]
+fn thresholds_select() {
+    let labels: Vec<Label> = [
+        TagCategory::General,
+        TagCategory::General,
+        TagCategory::General,
+        TagCategory::General,
+        TagCategory::Character,
+        TagCategory::Rating,
+        TagCategory::Rating,
+    ]
+    .into_iter()
+    .enumerate()
+    .map(|(i, category)| Label {
+        name: format!("label_{i}"),
+        category,
+    })
+    .collect();
+    let config = TaggerConfig {
+        general: CategoryLimit {
+            min_score: 0.5,
+            max_count: 2,
+        },
+        overrides: BTreeMap::from([("label_2".to_string(), 0.9), ("label_3".to_string(), 0.1)]),
+        ..TaggerConfig::default()
+    };
+    let thresholds = Thresholds::new(config, &labels).unwrap();
+    let tags = thresholds.select(&[0.6, 0.2, 0.8, 0.2, 0.4, 0.3, 0.7], &labels);
+    assert_eq!(tags, vec![(0.6, 0), (0.2, 3), (0.7, 6)]);
+}
+
+#[test]
+fn csv_lines() {
+    assert_eq!(
+        split_csv_line(r#"1,"a,b",4,"say ""hi""""#),
+        vec!["1", "a,b", "4", r#"say "hi""#]
+    );
+}
+
+#[test]
+fn tensor_layout() {
+    // A wide black image is letterboxed with white bands above and below it, so rows and
+    // columns tell apart. DeepDanbooru's own `load_image_for_evaluate` feeds the decoded
+    // height x width x channels image as is, which is what NHWC means here.
+    let image = DynamicImage::ImageRgb8(image::RgbImage::new(8, 4));
+    let descriptor = ModelDescriptor {
+        input_size: 8,
+        ..ModelDescriptor::deepdanbooru()
+    };
+    let tensor = to_tensor(std::slice::from_ref(&image), &descriptor);
+    assert_eq!(tensor.shape(), &[1, 8, 8, 3]);
+    assert_eq!(tensor[[0, 0, 4, 0]], 1.0);
+    assert_eq!(tensor[[0, 4, 0, 0]], 0.0);
+    let descriptor = ModelDescriptor {
+        layout: Layout::Nchw,
+        ..descriptor
+    };
+    let tensor = to_tensor(&[image], &descriptor);
+    assert_eq!(tensor.shape(), &[1, 3, 8, 8]);
+    assert_eq!(tensor[[0, 0, 0, 4]], 1.0);
+    assert_eq!(tensor[[0, 0, 4, 0]], 0.0);
+}
@@ -0,0 +1,181 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use image::DynamicImage;
+use log::error;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+
+use crate::inference_cache::InferenceCache;
+use crate::tagger::{self, Tagger};
+use crate::workers::{self, CpuPool};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Model(#[from] tagger::Error),
+    #[error(transparent)]
+    Worker(#[from] workers::Error),
+    #[error("batch failed: {0}")]
+    Batch(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// How the tagging service groups pending images into a single model run.
+#[derive(Debug, Clone, Copy)]
+pub struct BatchConfig {
+    /// Most images run through the model at once.
+    pub max_batch: usize,
+    /// How long the first image of a batch waits for company before running alone.
+    pub max_wait: Duration,
+    /// Images that may wait for a batch before new ones are turned away as busy.
+    pub queue_size: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_batch: 8,
+            max_wait: Duration::from_millis(50),
+            queue_size: 64,
+        }
+    }
+}
+
+struct Job {
+    image: DynamicImage,
+    reply: oneshot::Sender<Result<Vec<f32>>>,
+}
+
+/// Collects images from concurrent requests and tags them in batches, one batch at a time on
+/// the CPU pool so the async runtime keeps serving requests. Raw scores are cached by content
+/// hash, see [`TaggingService::cached_scores`].
+#[derive(Clone)]
+pub struct TaggingService {
+    tagger: Arc<dyn Tagger>,
+    model: Arc<str>,
+    tag_ids: Arc<[i64]>,
+    jobs: mpsc::Sender<Job>,
+    cache: InferenceCache,
+}
+
+impl TaggingService {
+    /// `tag_ids` maps each of the tagger's outputs to a tag, see
+    /// [`Database::sync_model_labels`](crate::database::Database::sync_model_labels).
+    pub fn new(
+        tagger: Arc<dyn Tagger>,
+        tag_ids: Vec<i64>,
+        pool: CpuPool,
+        config: BatchConfig,
+        cache: InferenceCache,
+    ) -> Self {
+        let (jobs, receiver) = mpsc::channel(config.queue_size.max(1));
+        tokio::spawn(run_batches(tagger.clone(), pool, config, receiver));
+        Self {
+            model: tagger.descriptor().id().into(),
+            tagger,
+            tag_ids: tag_ids.into(),
+            jobs,
+            cache,
+        }
+    }
+
+    pub fn tagger(&self) -> &Arc<dyn Tagger> {
+        &self.tagger
+    }
+
+    /// The tag a label index returned by [`TaggingService::select`] stands for.
+    pub fn tag_id(&self, index: usize) -> i64 {
+        self.tag_ids[index]
+    }
+
+    /// Kept `(score, label index)` pairs for one image's raw scores.
+    pub fn select(&self, scores: &[f32]) -> Vec<(f32, usize)> {
+        self.tagger.select(scores)
+    }
+
+    /// Raw scores cached when this model version already saw the bytes hashing to
+    /// `content_hash`, see [`content_hash`](crate::inference_cache::content_hash).
+    pub async fn cached_scores(&self, content_hash: &str) -> Option<Vec<f32>> {
+        self.cache.get(content_hash, &self.model).await
+    }
+
+    /// Queues `image` for the next batch, waiting for room in the queue. Meant for background
+    /// work like re-tagging.
+    pub async fn infer_scores(&self, content_hash: &str, image: DynamicImage) -> Result<Vec<f32>> {
+        let (reply, result) = oneshot::channel();
+        self.jobs
+            .send(Job { image, reply })
+            .await
+            .map_err(|_| workers::Error::Stopped)?;
+        self.finish(Some(content_hash), result).await
+    }
+
+    /// Queues `image` for the next batch, failing with [`workers::Error::Busy`] when too many
+    /// images are already waiting. Without a `content_hash` the scores are not cached, for
+    /// images that should leave no trace like search queries.
+    pub async fn try_infer_scores(
+        &self,
+        content_hash: Option<&str>,
+        image: DynamicImage,
+    ) -> Result<Vec<f32>> {
+        let (reply, result) = oneshot::channel();
+        self.jobs
+            .try_send(Job { image, reply })
+            .map_err(|err| match err {
+                mpsc::error::TrySendError::Full(_) => workers::Error::Busy,
+                mpsc::error::TrySendError::Closed(_) => workers::Error::Stopped,
+            })?;
+        self.finish(content_hash, result).await
+    }
+
+    async fn finish(
+        &self,
+        content_hash: Option<&str>,
+        result: oneshot::Receiver<Result<Vec<f32>>>,
+    ) -> Result<Vec<f32>> {
+        let scores = result.await.map_err(|_| workers::Error::Stopped)??;
+        if let Some(content_hash) = content_hash {
+            self.cache.put(content_hash, &self.model, &scores).await;
+        }
+        Ok(scores)
+    }
+}
+
+async fn run_batches(
+    tagger: Arc<dyn Tagger>,
+    pool: CpuPool,
+    config: BatchConfig,
+    mut receiver: mpsc::Receiver<Job>,
+) {
+    let max_batch = config.max_batch.max(1);
+    while let Some(first) = receiver.recv().await {
+        let deadline = Instant::now() + config.max_wait;
+        let mut jobs = vec![first];
+        while jobs.len() < max_batch {
+            match tokio::time::timeout_at(deadline, receiver.recv()).await {
+                Ok(Some(job)) => jobs.push(job),
+                Ok(None) | Err(_) => break,
+            }
+        }
+        let (images, replies): (Vec<_>, Vec<_>) =
+            jobs.into_iter().map(|job| (job.image, job.reply)).unzip();
+        let tagger = tagger.clone();
+        let results = pool.run(move || tagger.scores(&images)).await;
+        let err = match results {
+            Ok(Ok(results)) => {
+                for (reply, scores) in replies.into_iter().zip(results) {
+                    reply.send(Ok(scores)).ok();
+                }
+                continue;
+            }
+            Ok(Err(err)) => err.to_string(),
+            Err(err) => err.to_string(),
+        };
+        error!("batch of {} failed: {}", replies.len(), err);
+        for reply in replies {
+            reply.send(Err(Error::Batch(err.clone()))).ok();
+        }
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use axum::body::{Body, Bytes};
+use log::{debug, warn};
+use serde::Deserialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+
+use crate::ffmpeg;
+
+/// Bytes read from ffmpeg at a time.
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Chunks buffered for a slow client before ffmpeg is left waiting.
+const CHANNEL_CHUNKS: usize = 16;
+/// Transcodes whose requests are counted at once. Past this the counts start over, so
+/// transcodes asked for once and never again do not pile up.
+const MAX_COUNTED: usize = 4096;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("too many transcodes are running")]
+    Busy,
+    #[error(transparent)]
+    Ffmpeg(#[from] ffmpeg::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Formats music can be transcoded to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioFormat {
+    Mp3,
+    /// AAC in ADTS, which plays wherever M4A does.
+    Aac,
+    /// Opus in Ogg.
+    Opus,
+}
+
+impl AudioFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "mp3" => Some(AudioFormat::Mp3),
+            "aac" => Some(AudioFormat::Aac),
+            "opus" => Some(AudioFormat::Opus),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            AudioFormat::Mp3 => "audio/mpeg",
+            AudioFormat::Aac => "audio/aac",
+            AudioFormat::Opus => "audio/ogg",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            AudioFormat::Mp3 => "mp3",
+            AudioFormat::Aac => "aac",
+            AudioFormat::Opus => "opus",
+        }
+    }
+
+    /// ffmpeg encoder and muxer.
+    fn ffmpeg(self) -> (&'static str, &'static str) {
+        match self {
+            AudioFormat::Mp3 => ("libmp3lame", "mp3"),
+            AudioFormat::Aac => ("aac", "adts"),
+            AudioFormat::Opus => ("libopus", "ogg"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TranscodeConfig {
+    /// Transcodes running at once; more are turned away as busy.
+    pub max_running: usize,
+    /// Where popular transcodes are kept, `None` to never keep them.
+    pub cache_dir: Option<PathBuf>,
+    /// Requests for the same transcode before it is kept.
+    pub cache_after: u32,
+    /// Size the cache is trimmed to, least recently used first.
+    pub cache_max_bytes: u64,
+}
+
+impl Default for TranscodeConfig {
+    fn default() -> Self {
+        Self {
+            max_running: std::thread::available_parallelism().map_or(2, |n| n.get()),
+            cache_dir: None,
+            cache_after: 2,
+            cache_max_bytes: 1024 * 1024 * 1024,
+        }
+    }
+}
+
+/// A transcode, either kept from before or coming out of ffmpeg.
+pub enum Transcode {
+    /// A complete file, which can be served with ranges.
+    Cached(PathBuf),
+    Stream(Body),
+}
+
+/// Pipes music through ffmpeg for clients that cannot play the stored format or want a lower
+/// bitrate, keeping transcodes that are asked for repeatedly.
+#[derive(Clone)]
+pub struct Transcoder {
+    config: Arc<TranscodeConfig>,
+    permits: Arc<Semaphore>,
+    /// Requests per cache key for transcodes not kept yet.
+    requests: Arc<Mutex<HashMap<String, u32>>>,
+}
+
+impl Transcoder {
+    pub fn new(config: TranscodeConfig) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(config.max_running.max(1))),
+            config: Arc::new(config),
+            requests: Arc::default(),
+        }
+    }
+
+    /// Transcodes the file at `path`, whose content hash is `hash`, at `bit_rate` kbps.
+    pub async fn transcode(
+        &self,
+        path: &str,
+        hash: &str,
+        format: AudioFormat,
+        bit_rate: u32,
+    ) -> Result<Transcode> {
+        let key = format!("{hash}-{bit_rate}.{}", format.extension());
+        let cache_path = match &self.config.cache_dir {
+            Some(dir) => {
+                let cache_path = dir.join(&key);
+                if tokio::fs::try_exists(&cache_path).await.unwrap_or(false) {
+                    touch(&cache_path);
+                    return Ok(Transcode::Cached(cache_path));
+                }
+                self.count_request(key).then_some(cache_path)
+            }
+            None => None,
+        };
+        let permit = self
+            .permits
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| Error::Busy)?;
+        let (codec, muxer) = format.ffmpeg();
+        let child = ffmpeg::transcode_audio(path, codec, muxer, bit_rate)?;
+        let (sender, receiver) = mpsc::channel(CHANNEL_CHUNKS);
+        tokio::spawn(pipe(
+            child,
+            sender,
+            cache_path,
+            self.config.cache_max_bytes,
+            permit,
+        ));
+        let chunks = futures::stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|chunk| (chunk, receiver))
+        });
+        Ok(Transcode::Stream(Body::from_stream(chunks)))
+    }
+
+    /// Counts a request for the transcode cached as `key`, true once it should be kept. Its
+    /// count is dropped then, as the cache file takes over.
+    fn count_request(&self, key: String) -> bool {
+        let mut requests = self.requests.lock().unwrap();
+        if requests.len() >= MAX_COUNTED && !requests.contains_key(&key) {
+            requests.clear();
+        }
+        let count = requests.entry(key.clone()).or_default();
+        *count += 1;
+        if *count < self.config.cache_after {
+            return false;
+        }
+        requests.remove(&key);
+        true
+    }
+}
+
+/// Sends ffmpeg's output to the client, and to `cache_path` when given, holding `permit`
+/// until ffmpeg is done. A client that goes away stops ffmpeg, and the partial cache file is
+/// dropped.
+async fn pipe(
+    mut child: tokio::process::Child,
+    sender: mpsc::Sender<std::io::Result<Bytes>>,
+    cache_path: Option<PathBuf>,
+    cache_max_bytes: u64,
+    _permit: OwnedSemaphorePermit,
+) {
+    let Some(mut stdout) = child.stdout.take() else {
+        return;
+    };
+    // Written under a temporary name so a half done transcode is never served.
+    let partial = cache_path
+        .as_ref()
+        .map(|path| path.with_extension(format!("{}.partial", uuid::Uuid::new_v4())));
+    let mut cache = match &partial {
+        Some(partial) => match tokio::fs::File::create(partial).await {
+            Ok(file) => Some(file),
+            Err(err) => {
+                warn!("failed to create {}: {}", partial.display(), err);
+                None
+            }
+        },
+        None => None,
+    };
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let completed = loop {
+        let read = match stdout.read(&mut buffer).await {
+            Ok(0) => break true,
+            Ok(read) => read,
+            Err(err) => {
+                sender.send(Err(err)).await.ok();
+                break false;
+            }
+        };
+        let chunk = Bytes::copy_from_slice(&buffer[..read]);
+        if let Some(file) = &mut cache {
+            if let Err(err) = file.write_all(&chunk).await {
+                warn!("stopped caching a transcode: {}", err);
+                cache = None;
+            }
+        }
+        if sender.send(Ok(chunk)).await.is_err() {
+            debug!("transcode client went away");
+            break false;
+        }
+    };
+    let succeeded = match completed {
+        true => child.wait().await.is_ok_and(|status| status.success()),
+        false => {
+            child.kill().await.ok();
+            false
+        }
+    };
+    let (Some(partial), Some(cache_path)) = (partial, cache_path) else {
+        return;
+    };
+    let flushed = match cache {
+        Some(mut file) => file.flush().await.is_ok(),
+        None => false,
+    };
+    if succeeded && flushed && tokio::fs::rename(&partial, &cache_path).await.is_ok() {
+        if let Some(dir) = cache_path.parent() {
+            if let Err(err) = evict(dir, cache_max_bytes).await {
+                warn!("failed to trim the transcode cache: {}", err);
+            }
+        }
+    } else {
+        tokio::fs::remove_file(&partial).await.ok();
+    }
+}
+
+/// Marks a cached transcode as just used.
+fn touch(path: &Path) {
+    if let Ok(file) = std::fs::File::options().write(true).open(path) {
+        file.set_modified(SystemTime::now()).ok();
+    }
+}
+
+/// Deletes the least recently used transcodes in `dir` until it holds at most `max_bytes`.
+async fn evict(dir: &Path, max_bytes: u64) -> std::io::Result<()> {
+    let mut files = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let metadata = entry.metadata().await?;
+        if metadata.is_file() && !entry.file_name().to_string_lossy().ends_with(".partial") {
+            files.push((metadata.modified()?, metadata.len(), entry.path()));
+        }
+    }
+    files.sort();
+    let mut total: u64 = files.iter().map(|(_, len, _)| len).sum();
+    for (_, len, path) in files {
+        if total <= max_bytes {
+            break;
+        }
+        tokio::fs::remove_file(&path).await?;
+        total -= len;
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn cache_eviction() {
+    use std::time::Duration;
+    let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+    tokio::fs::create_dir(&dir).await.unwrap();
+    let start = SystemTime::now();
+    for (i, name) in ["old.mp3", "mid.mp3", "new.mp3"].into_iter().enumerate() {
+        let path = dir.join(name);
+        std::fs::write(&path, [0; 100]).unwrap();
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(start - Duration::from_secs(100 - i as u64 * 10))
+            .unwrap();
+    }
+    // Using the oldest one makes it the newest.
+    touch(&dir.join("old.mp3"));
+    evict(&dir, 250).await.unwrap();
+    assert!(!dir.join("mid.mp3").exists());
+    assert!(dir.join("old.mp3").exists());
+    assert!(dir.join("new.mp3").exists());
+    evict(&dir, 100).await.unwrap();
+    assert!(dir.join("old.mp3").exists());
+    assert!(!dir.join("new.mp3").exists());
+    tokio::fs::remove_dir_all(&dir).await.unwrap();
+}
+
+#[test]
+fn request_counts_stay_bounded() {
+    let transcoder = Transcoder::new(TranscodeConfig::default());
+    assert!(!transcoder.count_request("a".to_string()));
+    assert!(transcoder.count_request("a".to_string()));
+    assert!(transcoder.requests.lock().unwrap().is_empty());
+    for i in 0..MAX_COUNTED * 2 {
+        transcoder.count_request(i.to_string());
+    }
+    assert!(transcoder.requests.lock().unwrap().len() <= MAX_COUNTED);
+}
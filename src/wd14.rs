@@ -0,0 +1,55 @@
+use std::sync::RwLock;
+
+use image::DynamicImage;
+use ort::Session;
+
+use crate::tagger::{self, Label, ModelDescriptor, Result, Tagger, Thresholds};
+
+/// SmilingWolf's WD14 taggers (ConvNeXt, SwinV2, ViT and friends). They take BGR pixels in
+/// `0..=255` and ship their labels as `selected_tags.csv`.
+pub struct Wd14 {
+    session: Session,
+    descriptor: ModelDescriptor,
+    labels: Vec<Label>,
+    thresholds: RwLock<Thresholds>,
+}
+
+impl Wd14 {
+    /// Per label thresholds from the CSV apply unless the descriptor overrides them.
+    pub fn new(mut descriptor: ModelDescriptor) -> Result<Self> {
+        let session = tagger::session(&descriptor)?;
+        let (labels, label_thresholds) = tagger::read_label_csv(&descriptor.labels_path)?;
+        for (name, threshold) in label_thresholds {
+            descriptor
+                .thresholds
+                .overrides
+                .entry(name)
+                .or_insert(threshold);
+        }
+        let thresholds = Thresholds::new(descriptor.thresholds.clone(), &labels)?;
+        Ok(Self {
+            session,
+            descriptor,
+            labels,
+            thresholds: RwLock::new(thresholds),
+        })
+    }
+}
+
+impl Tagger for Wd14 {
+    fn descriptor(&self) -> &ModelDescriptor {
+        &self.descriptor
+    }
+
+    fn labels(&self) -> &[Label] {
+        &self.labels
+    }
+
+    fn thresholds(&self) -> &RwLock<Thresholds> {
+        &self.thresholds
+    }
+
+    fn scores(&self, images: &[DynamicImage]) -> Result<Vec<Vec<f32>>> {
+        tagger::run(&self.session, tagger::to_tensor(images, &self.descriptor))
+    }
+}
@@ -0,0 +1,116 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+
+use log::error;
+use tokio::sync::{mpsc, oneshot};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("worker pool is saturated")]
+    Busy,
+    #[error("worker pool stopped")]
+    Stopped,
+    #[error("worker panicked")]
+    Panicked,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+type Task = Box<dyn FnOnce() + Send>;
+
+/// A fixed set of threads for CPU heavy work like decoding and inference, so it never runs on
+/// (or starves) the async runtime. At most `queue_size` tasks wait for a free thread.
+#[derive(Clone)]
+pub struct CpuPool {
+    tasks: mpsc::Sender<Task>,
+}
+
+impl CpuPool {
+    pub fn new(threads: usize, queue_size: usize) -> Self {
+        let (tasks, receiver) = mpsc::channel::<Task>(queue_size.max(1));
+        let receiver = Arc::new(Mutex::new(receiver));
+        for i in 0..threads.max(1) {
+            let receiver = receiver.clone();
+            std::thread::Builder::new()
+                .name(format!("cpu-worker-{i}"))
+                .spawn(move || loop {
+                    let task = receiver.lock().unwrap().blocking_recv();
+                    let Some(task) = task else {
+                        break;
+                    };
+                    if catch_unwind(AssertUnwindSafe(task)).is_err() {
+                        error!("cpu worker task panicked");
+                    }
+                })
+                .expect("failed to spawn cpu worker");
+        }
+        Self { tasks }
+    }
+
+    /// Runs `task` on the pool, failing with [`Error::Busy`] instead of waiting when the queue
+    /// is full. Meant for work started by requests, which should be shed under load.
+    pub async fn try_run<T, F>(&self, task: F) -> Result<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (task, result) = wrap(task);
+        self.tasks.try_send(task).map_err(|err| match err {
+            mpsc::error::TrySendError::Full(_) => Error::Busy,
+            mpsc::error::TrySendError::Closed(_) => Error::Stopped,
+        })?;
+        result.await.map_err(|_| Error::Panicked)
+    }
+
+    /// Runs `task` on the pool, waiting for room in the queue. Meant for background work that
+    /// has already been admitted.
+    pub async fn run<T, F>(&self, task: F) -> Result<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (task, result) = wrap(task);
+        self.tasks.send(task).await.map_err(|_| Error::Stopped)?;
+        result.await.map_err(|_| Error::Panicked)
+    }
+}
+
+fn wrap<T, F>(task: F) -> (Task, oneshot::Receiver<T>)
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (sender, receiver) = oneshot::channel();
+    let task: Task = Box::new(move || {
+        sender.send(task()).ok();
+    });
+    (task, receiver)
+}
+
+#[tokio::test]
+async fn sheds_load_when_full() {
+    let pool = CpuPool::new(1, 1);
+    let (started, worker_busy) = oneshot::channel();
+    let (release, blocked) = std::sync::mpsc::channel::<()>();
+    let running = {
+        let pool = pool.clone();
+        tokio::spawn(async move {
+            pool.try_run(move || {
+                started.send(()).ok();
+                blocked.recv().ok()
+            })
+            .await
+        })
+    };
+    // The worker holds the first task until released, so the second one fills the queue.
+    worker_busy.await.unwrap();
+    let queued = pool.try_run(|| 2);
+    tokio::pin!(queued);
+    assert!(futures::poll!(&mut queued).is_pending());
+    assert!(matches!(pool.try_run(|| 3).await, Err(Error::Busy)));
+    release.send(()).unwrap();
+    assert!(running.await.unwrap().is_ok());
+    assert_eq!(queued.await.unwrap(), 2);
+    let panicked: Result<()> = pool.run(|| panic!("boom")).await;
+    assert!(matches!(panicked, Err(Error::Panicked)));
+}
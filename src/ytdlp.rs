@@ -1,4 +1,9 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
 const YT_DLP: &str = "yt-dlp";
+const OUTPUT_TEMPLATE: &str = "/tmp/%(title)s_%(id)s.%(ext)s";
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -6,62 +11,229 @@ pub enum Error {
     YTD(String),
     #[error("io")]
     IO(#[from] tokio::io::Error),
+    #[error("profiles: {0}")]
+    Config(#[from] serde_json::Error),
+    #[error("not an http or https URL: {0}")]
+    Url(String),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-pub async fn download_music(url: &str) -> Result<String> {
-    let mut command = tokio::process::Command::new(YT_DLP);
-    command.args([
-        "--format-sort",
-        "asr",
-        "--format",
-        "bestaudio",
-        "-x",
-        "--remux-video",
-        "opus",
-        "--embed-thumbnail",
-        "--embed-metadata",
-        "-o",
-        "/tmp/%(title)s_%(id)s.%(ext)s",
-        "--print",
-        "after_move:filepath",
-        url,
-    ]);
-    let output = match command.output().await {
-        Ok(output) => output,
-        Err(err) => return Err(Error::IO(err)),
-    };
-    if output.status.success() {
-        Ok(String::from_utf8(output.stdout).unwrap_or("utf error".to_string()))
-    } else {
-        Err(Error::YTD(
-            String::from_utf8(output.stderr).unwrap_or("utf error".to_string()),
-        ))
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DownloadKind {
+    Video,
+    /// Audio only.
+    Music,
+}
+
+/// A named set of download choices that compiles to yt-dlp arguments.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DownloadProfile {
+    pub name: String,
+    pub kind: DownloadKind,
+    /// Tallest video picked.
+    #[serde(default)]
+    pub max_height: Option<u32>,
+    /// yt-dlp codec names to prefer in order, e.g. `avc1` or `vp9`; any codec when none of
+    /// them is offered.
+    #[serde(default)]
+    pub video_codecs: Vec<String>,
+    #[serde(default)]
+    pub audio_codecs: Vec<String>,
+    /// Highest audio bitrate picked in kbps.
+    #[serde(default)]
+    pub audio_bit_rate: Option<u32>,
+    /// Container the download is remuxed into, e.g. `mp4` or `opus`.
+    #[serde(default)]
+    pub container: Option<String>,
+    #[serde(default)]
+    pub embed_subtitles: bool,
+    /// Subtitles downloaded next to the file as WebVTT, e.g. `en` or `de`, which ingest
+    /// stores and indexes. Embedded subtitles are limited to these too when given.
+    #[serde(default)]
+    pub subtitle_languages: Vec<String>,
+    /// Whether to fall back to generated captions for languages without subtitles.
+    #[serde(default)]
+    pub auto_captions: bool,
+    /// Writes yt-dlp's info JSON next to the file, which ingest reads chapters from.
+    #[serde(default)]
+    pub write_info_json: bool,
+    #[serde(default = "enabled")]
+    pub embed_thumbnail: bool,
+    #[serde(default = "enabled")]
+    pub embed_metadata: bool,
+    /// yt-dlp output template.
+    #[serde(default = "output_template")]
+    pub output: String,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+fn output_template() -> String {
+    OUTPUT_TEMPLATE.to_string()
+}
+
+impl DownloadProfile {
+    /// The best video and audio streams.
+    pub fn video() -> Self {
+        Self {
+            name: "video".to_string(),
+            kind: DownloadKind::Video,
+            max_height: None,
+            video_codecs: Vec::new(),
+            audio_codecs: Vec::new(),
+            audio_bit_rate: None,
+            container: None,
+            embed_subtitles: false,
+            subtitle_languages: vec!["en".to_string()],
+            auto_captions: true,
+            write_info_json: true,
+            embed_thumbnail: true,
+            embed_metadata: true,
+            output: output_template(),
+        }
+    }
+
+    /// H.264 in MP4 at up to 1080p, for devices that play nothing else.
+    pub fn video_h264_1080p() -> Self {
+        Self {
+            name: "video-h264-1080p".to_string(),
+            max_height: Some(1080),
+            video_codecs: vec!["avc1".to_string()],
+            audio_codecs: vec!["mp4a".to_string()],
+            container: Some("mp4".to_string()),
+            ..Self::video()
+        }
+    }
+
+    /// The best audio stream as Opus.
+    pub fn music() -> Self {
+        Self {
+            name: "music".to_string(),
+            kind: DownloadKind::Music,
+            container: Some("opus".to_string()),
+            subtitle_languages: Vec::new(),
+            auto_captions: false,
+            write_info_json: false,
+            ..Self::video()
+        }
+    }
+
+    /// Reads a JSON list of profiles. The first of each kind is that kind's default.
+    pub fn load_all(path: impl AsRef<Path>) -> Result<Vec<Self>> {
+        let profiles = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&profiles)?)
+    }
+
+    /// The `--format` selector: each preferred codec in turn, then any codec, within the
+    /// height and bitrate limits.
+    fn format(&self) -> String {
+        let video_limits = self
+            .max_height
+            .map(|height| format!("[height<={height}]"))
+            .unwrap_or_default();
+        let audio_limits = self
+            .audio_bit_rate
+            .map(|bit_rate| format!("[abr<={bit_rate}]"))
+            .unwrap_or_default();
+        let choices = |base: &str, codec_field: &str, codecs: &[String], limits: &str| {
+            let mut choices: Vec<String> = codecs
+                .iter()
+                .map(|codec| format!("{base}[{codec_field}^={codec}]{limits}"))
+                .collect();
+            choices.push(format!("{base}{limits}"));
+            choices
+        };
+        match self.kind {
+            DownloadKind::Music => {
+                let mut audio = choices("bestaudio", "acodec", &self.audio_codecs, &audio_limits);
+                if !audio_limits.is_empty() {
+                    // Better a higher bitrate than nothing.
+                    audio.push("bestaudio".to_string());
+                }
+                audio.join("/")
+            }
+            DownloadKind::Video => {
+                let audio = choices("ba", "acodec", &self.audio_codecs, &audio_limits);
+                let video = choices("bv", "vcodec", &self.video_codecs, &video_limits);
+                let mut pairs = Vec::new();
+                for video in &video {
+                    for audio in &audio {
+                        pairs.push(format!("{video}+{audio}"));
+                    }
+                }
+                pairs.join("/")
+            }
+        }
+    }
+
+    /// yt-dlp arguments before the URL.
+    pub fn args(&self) -> Vec<String> {
+        let mut args: Vec<String> = Vec::new();
+        let format_sort = match self.kind {
+            DownloadKind::Video => "vbr,abr",
+            DownloadKind::Music => "asr",
+        };
+        args.extend(["--format-sort".to_string(), format_sort.to_string()]);
+        args.extend(["--format".to_string(), self.format()]);
+        if self.kind == DownloadKind::Music {
+            args.push("-x".to_string());
+        }
+        if let Some(container) = &self.container {
+            args.extend(["--remux-video".to_string(), container.clone()]);
+        }
+        if !self.subtitle_languages.is_empty() {
+            args.push("--write-subs".to_string());
+            if self.auto_captions {
+                args.push("--write-auto-subs".to_string());
+            }
+            args.extend(["--convert-subs", "vtt"].map(String::from));
+        }
+        if self.embed_subtitles {
+            args.push("--embed-subs".to_string());
+        }
+        if self.embed_subtitles || !self.subtitle_languages.is_empty() {
+            let languages = match self.subtitle_languages.is_empty() {
+                true => "all,-live_chat".to_string(),
+                false => self.subtitle_languages.join(","),
+            };
+            args.extend(["--sub-langs".to_string(), languages]);
+        }
+        if self.write_info_json {
+            args.push("--write-info-json".to_string());
+        }
+        if self.embed_thumbnail {
+            args.push("--embed-thumbnail".to_string());
+        }
+        if self.embed_metadata {
+            args.push("--embed-metadata".to_string());
+        }
+        args.extend(["-o".to_string(), self.output.clone()]);
+        args.extend(["--print", "after_move:filepath"].map(String::from));
+        args
     }
 }
 
-pub async fn download_video(url: &str) -> Result<String> {
+/// Downloads `url` as `profile` says, returning the path of the file.
+pub async fn download(url: &str, profile: &DownloadProfile) -> Result<String> {
+    if !is_web_url(url) {
+        return Err(Error::Url(url.to_string()));
+    }
     let mut command = tokio::process::Command::new(YT_DLP);
-    command.args([
-        "--format-sort",
-        "vbr,abr",
-        "--format",
-        "bv[vcodec!=h265]+ba",
-        "--embed-thumbnail",
-        "--embed-metadata",
-        "-o",
-        "/tmp/%(title)s_%(id)s.%(ext)s",
-        "--print",
-        "after_move:filepath",
-        url,
-    ]);
+    // `--` keeps a URL starting with `-` from being read as an option.
+    command.args(profile.args()).arg("--").arg(url);
     let output = match command.output().await {
         Ok(output) => output,
         Err(err) => return Err(Error::IO(err)),
     };
     if output.status.success() {
-        Ok(String::from_utf8(output.stdout).unwrap_or("utf error".to_string()))
+        Ok(String::from_utf8(output.stdout)
+            .unwrap_or("utf error".to_string())
+            .trim()
+            .to_string())
     } else {
         Err(Error::YTD(
             String::from_utf8(output.stderr).unwrap_or("utf error".to_string()),
@@ -69,6 +241,75 @@ pub async fn download_video(url: &str) -> Result<String> {
     }
 }
 
+/// Whether `url` is an http or https URL, the only kind yt-dlp is given; it would also read
+/// local files and other schemes.
+fn is_web_url(url: &str) -> bool {
+    match url.split_once("://") {
+        Some((scheme, rest)) => {
+            (scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https"))
+                && !rest.is_empty()
+        }
+        None => false,
+    }
+}
+
+pub async fn download_music(url: &str) -> Result<String> {
+    download(url, &DownloadProfile::music()).await
+}
+
+pub async fn download_video(url: &str) -> Result<String> {
+    download(url, &DownloadProfile::video()).await
+}
+
+#[test]
+fn profile_arguments() {
+    let args = |profile: DownloadProfile| profile.args().join(" ");
+    assert_eq!(
+        args(DownloadProfile::music()),
+        "--format-sort asr --format bestaudio -x --remux-video opus --embed-thumbnail \
+         --embed-metadata -o /tmp/%(title)s_%(id)s.%(ext)s --print after_move:filepath"
+    );
+    assert_eq!(
+        args(DownloadProfile::video()),
+        "--format-sort vbr,abr --format bv+ba --write-subs --write-auto-subs --convert-subs vtt \
+         --sub-langs en --write-info-json --embed-thumbnail --embed-metadata \
+         -o /tmp/%(title)s_%(id)s.%(ext)s --print after_move:filepath"
+    );
+    assert_eq!(
+        DownloadProfile::video_h264_1080p().format(),
+        "bv[vcodec^=avc1][height<=1080]+ba[acodec^=mp4a]/bv[vcodec^=avc1][height<=1080]+ba/\
+         bv[height<=1080]+ba[acodec^=mp4a]/bv[height<=1080]+ba"
+    );
+    let profiles: Vec<DownloadProfile> = serde_json::from_str(
+        r#"[{"name": "podcast", "kind": "music", "audio_bit_rate": 96,
+            "embed_thumbnail": false, "output": "/srv/%(id)s.%(ext)s"},
+            {"name": "lectures", "kind": "video", "embed_subtitles": true,
+            "subtitle_languages": ["de", "en"], "embed_thumbnail": false,
+            "embed_metadata": false}]"#,
+    )
+    .unwrap();
+    assert_eq!(
+        args(profiles[0].clone()),
+        "--format-sort asr --format bestaudio[abr<=96]/bestaudio -x --embed-metadata \
+         -o /srv/%(id)s.%(ext)s --print after_move:filepath"
+    );
+    assert_eq!(
+        args(profiles[1].clone()),
+        "--format-sort vbr,abr --format bv+ba --write-subs --convert-subs vtt --embed-subs \
+         --sub-langs de,en -o /tmp/%(title)s_%(id)s.%(ext)s --print after_move:filepath"
+    );
+}
+
+#[test]
+fn web_urls() {
+    assert!(is_web_url("https://www.youtube.com/watch?v=VFbhKZFzbzk"));
+    assert!(is_web_url("HTTP://example.com/video"));
+    assert!(!is_web_url("--exec=touch /tmp/pwned"));
+    assert!(!is_web_url("file:///etc/passwd"));
+    assert!(!is_web_url("https://"));
+    assert!(!is_web_url("/tmp/video.mp4"));
+}
+
 #[tokio::test]
 async fn test_download_music() {
     download_music("https://www.youtube.com/watch?v=VFbhKZFzbzk")